@@ -0,0 +1,615 @@
+use discord::model::{ChannelId, RoleId, UserId};
+use std::{collections::hash_map::HashMap, fmt, iter::Peekable, str::CharIndices, time::Duration};
+
+// The type of value an argument accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // A single word or a quoted string.
+    Text,
+    // The rest of the text exactly as it was typed.
+    Rest,
+    Integer,
+    // A duration like `30s`, `15m` or `1h30m`.
+    Duration,
+    User,
+    Role,
+    Channel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Required,
+    // Skipped if the next word isn't a valid value of this kind.
+    Optional,
+    // Takes as many valid values as there are, possibly zero.
+    Many,
+}
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub arity: Arity,
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    // None for flags that don't take a value, like `--dry-run`.
+    pub kind: Option<Kind>,
+}
+
+// The arguments a command accepts. Positional arguments are matched in order,
+// flags (`--name [value]`) can appear anywhere before a `Rest` argument. Commands without flags
+// take words starting with `--` as they are.
+pub struct Schema {
+    pub args: &'static [Arg],
+    pub flags: &'static [Flag],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Integer(i64),
+    Duration(Duration),
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+    Switch,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnterminatedQuote,
+    Missing(&'static str),
+    Invalid {
+        name: &'static str,
+        kind: Kind,
+        value: String,
+    },
+    UnknownFlag(String),
+    MissingFlagValue(&'static str),
+    Unexpected(String),
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Text | Kind::Rest => write!(f, "text"),
+            Kind::Integer => write!(f, "number"),
+            Kind::Duration => write!(f, "duration (like `30m` or `1h30m`)"),
+            Kind::User => write!(f, "user mention"),
+            Kind::Role => write!(f, "role mention"),
+            Kind::Channel => write!(f, "channel mention"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnterminatedQuote => write!(f, "You have an unterminated quote in there."),
+            Error::Missing(name) => write!(f, "You forgot the `<{}>` argument.", name),
+            Error::Invalid { name,
+                             kind,
+                             ref value, } => {
+                write!(f, "`{}` isn't a valid {} for `<{}>`.", value, kind, name)
+            }
+            Error::UnknownFlag(ref name) => write!(f, "I don't know the `--{}` flag.", name),
+            Error::MissingFlagValue(name) => write!(f, "The `--{}` flag needs a value.", name),
+            Error::Unexpected(ref value) => write!(f, "I didn't expect `{}` there.", value),
+        }
+    }
+}
+
+// Parsed values, keyed by argument or flag name.
+#[derive(Debug, Default)]
+pub struct Arguments {
+    values: HashMap<&'static str, Vec<Value>>,
+}
+
+impl Arguments {
+    fn push(&mut self, name: &'static str, value: Value) {
+        self.values.entry(name).or_insert_with(Vec::new).push(value);
+    }
+
+    fn all(&self, name: &str) -> &[Value] {
+        self.values.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Whether the argument or flag was given at all.
+    pub fn has(&self, name: &str) -> bool {
+        !self.all(name).is_empty()
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.all(name).iter().filter_map(|x| match *x {
+                                                 Value::Text(ref x) => Some(x.as_str()),
+                                                 _ => None,
+                                             })
+                             .next()
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        self.all(name).iter().filter_map(|x| match *x {
+                                                 Value::Integer(x) => Some(x),
+                                                 _ => None,
+                                             })
+                             .next()
+    }
+
+    pub fn duration(&self, name: &str) -> Option<Duration> {
+        self.all(name).iter().filter_map(|x| match *x {
+                                                 Value::Duration(x) => Some(x),
+                                                 _ => None,
+                                             })
+                             .next()
+    }
+
+    pub fn users(&self, name: &str) -> Vec<UserId> {
+        self.all(name).iter()
+                      .filter_map(|x| match *x {
+                                      Value::User(x) => Some(x),
+                                      _ => None,
+                                  })
+                      .collect()
+    }
+
+    pub fn user(&self, name: &str) -> Option<UserId> {
+        self.users(name).into_iter().next()
+    }
+
+    pub fn roles(&self, name: &str) -> Vec<RoleId> {
+        self.all(name).iter()
+                      .filter_map(|x| match *x {
+                                      Value::Role(x) => Some(x),
+                                      _ => None,
+                                  })
+                      .collect()
+    }
+
+    pub fn channel(&self, name: &str) -> Option<ChannelId> {
+        self.all(name).iter().filter_map(|x| match *x {
+                                                 Value::Channel(x) => Some(x),
+                                                 _ => None,
+                                             })
+                             .next()
+    }
+}
+
+struct Token {
+    text: String,
+    quoted: bool,
+    // Where the token starts in the text.
+    start: usize,
+}
+
+fn is_opening_quote(c: char) -> bool {
+    c == '"' || c == '“'
+}
+
+fn is_closing_quote(c: char) -> bool {
+    c == '"' || c == '”'
+}
+
+// Splits the text into words one at a time, treating quoted strings as single words.
+struct Tokens<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Self {
+        Tokens { text,
+                 chars: text.char_indices().peekable(), }
+    }
+
+    // Returns where the next word starts, or the end of the text if there are no more.
+    fn skip_whitespace(&mut self) -> usize {
+        while self.chars.peek().map_or(false, |&(_, c)| c.is_whitespace()) {
+            self.chars.next();
+        }
+
+        self.chars.peek().map_or(self.text.len(), |&(i, _)| i)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        let start = self.skip_whitespace();
+
+        let first = match self.chars.peek() {
+            Some(&(_, c)) => c,
+            None => return Ok(None),
+        };
+
+        let mut buf = String::new();
+
+        if is_opening_quote(first) {
+            self.chars.next();
+
+            let mut closed = false;
+            while let Some((_, c)) = self.chars.next() {
+                if c == '\\' {
+                    // A backslash escapes the next character, including quotes.
+                    match self.chars.next() {
+                        Some((_, c)) => buf.push(c),
+                        None => break,
+                    }
+                } else if is_closing_quote(c) {
+                    closed = true;
+                    break;
+                } else {
+                    buf.push(c);
+                }
+            }
+
+            if !closed {
+                return Err(Error::UnterminatedQuote);
+            }
+
+            Ok(Some(Token { text: buf,
+                            quoted: true,
+                            start, }))
+        } else {
+            while let Some(&(_, c)) = self.chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+
+                buf.push(c);
+                self.chars.next();
+            }
+
+            Ok(Some(Token { text: buf,
+                            quoted: false,
+                            start, }))
+        }
+    }
+}
+
+// Parses an ID either from a mention with one of the given prefixes or from a plain number.
+fn parse_id(text: &str, prefixes: &[&str]) -> Option<u64> {
+    if let Ok(id) = text.parse() {
+        return Some(id);
+    }
+
+    for prefix in prefixes {
+        if text.starts_with(prefix) && text.ends_with('>') && text.len() > prefix.len() {
+            if let Ok(id) = text[prefix.len()..text.len() - 1].parse() {
+                return Some(id);
+            }
+        }
+    }
+
+    None
+}
+
+// Parses durations like `90s`, `15m`, `1h30m`, `2d` or `1w`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    let mut any = false;
+
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => return None,
+        };
+
+        let value: u64 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(multiplier)?)?;
+        number.clear();
+        any = true;
+    }
+
+    if !any || !number.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+// Formats a duration the same way parse_duration() accepts it, for example `1h 30m`.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        // Round up so that "0s left" never happens.
+        seconds += 1;
+    }
+
+    let mut parts = Vec::new();
+    for &(unit, length) in &[("w", 60 * 60 * 24 * 7),
+                             ("d", 60 * 60 * 24),
+                             ("h", 60 * 60),
+                             ("m", 60),
+                             ("s", 1)]
+    {
+        if seconds >= length {
+            parts.push(format!("{}{}", seconds / length, unit));
+            seconds %= length;
+        }
+    }
+
+    if parts.is_empty() {
+        "0s".to_owned()
+    } else {
+        parts.join(" ")
+    }
+}
+
+fn convert(name: &'static str, kind: Kind, text: &str) -> Result<Value, Error> {
+    let value = match kind {
+        Kind::Text | Kind::Rest => Some(Value::Text(text.to_owned())),
+        Kind::Integer => text.parse().ok().map(Value::Integer),
+        Kind::Duration => parse_duration(text).map(Value::Duration),
+        Kind::User => parse_id(text, &["<@!", "<@"]).map(|x| Value::User(UserId(x))),
+        Kind::Role => parse_id(text, &["<@&"]).map(|x| Value::Role(RoleId(x))),
+        Kind::Channel => parse_id(text, &["<#"]).map(|x| Value::Channel(ChannelId(x))),
+    };
+
+    value.ok_or_else(|| Error::Invalid { name,
+                                         kind,
+                                         value: text.to_owned(), })
+}
+
+// Goes through the words one at a time, picking out the flags on the way.
+struct Parser<'a> {
+    schema: &'a Schema,
+    text: &'a str,
+    tokens: Tokens<'a>,
+    // The next positional word, read but not taken yet.
+    peeked: Option<Token>,
+    arguments: Arguments,
+}
+
+impl<'a> Parser<'a> {
+    // The next positional word, after any flags in front of it.
+    fn peek(&mut self) -> Result<Option<&Token>, Error> {
+        while self.peeked.is_none() {
+            let token = match self.tokens.next_token()? {
+                Some(token) => token,
+                None => break,
+            };
+
+            if self.schema.flags.is_empty()
+               || token.quoted
+               || !token.text.starts_with("--")
+               || token.text.len() <= 2
+            {
+                self.peeked = Some(token);
+            } else {
+                self.parse_flag(&token.text[2..])?;
+            }
+        }
+
+        Ok(self.peeked.as_ref())
+    }
+
+    fn parse_flag(&mut self, name: &str) -> Result<(), Error> {
+        let flag = self.schema
+                       .flags
+                       .iter()
+                       .find(|x| x.name == name)
+                       .ok_or_else(|| Error::UnknownFlag(name.to_owned()))?;
+
+        let value = match flag.kind {
+            Some(kind) => {
+                let token = self.tokens
+                                .next_token()?
+                                .ok_or(Error::MissingFlagValue(flag.name))?;
+                convert(flag.name, kind, &token.text)?
+            }
+            None => Value::Switch,
+        };
+
+        self.arguments.push(flag.name, value);
+        Ok(())
+    }
+
+    // Everything from the next positional word on, as it was typed. Flags in there are just
+    // text.
+    fn rest(&mut self) -> Result<&'a str, Error> {
+        self.peek()?;
+
+        let start = match self.peeked.take() {
+            Some(token) => token.start,
+            None => self.text.len(),
+        };
+
+        // Nothing gets read after this.
+        self.tokens = Tokens::new("");
+        Ok(self.text[start..].trim_right())
+    }
+}
+
+pub fn parse(schema: &Schema, text: &str) -> Result<Arguments, Error> {
+    let mut parser = Parser { schema,
+                              text,
+                              tokens: Tokens::new(text),
+                              peeked: None,
+                              arguments: Arguments::default(), };
+
+    for arg in schema.args {
+        if arg.kind == Kind::Rest {
+            let rest = parser.rest()?;
+
+            if !rest.is_empty() {
+                parser.arguments.push(arg.name, Value::Text(rest.to_owned()));
+            } else if arg.arity == Arity::Required {
+                return Err(Error::Missing(arg.name));
+            }
+
+            continue;
+        }
+
+        loop {
+            let value = match parser.peek()? {
+                Some(token) => convert(arg.name, arg.kind, &token.text),
+                None if arg.arity == Arity::Required => return Err(Error::Missing(arg.name)),
+                None => break,
+            };
+
+            match value {
+                Ok(value) => {
+                    parser.peeked = None;
+                    parser.arguments.push(arg.name, value);
+                }
+                Err(err) => {
+                    if arg.arity == Arity::Required {
+                        return Err(err);
+                    }
+                    break;
+                }
+            }
+
+            if arg.arity != Arity::Many {
+                break;
+            }
+        }
+    }
+
+    if let Some(token) = parser.peek()? {
+        return Err(Error::Unexpected(token.text.clone()));
+    }
+
+    Ok(parser.arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PB: Schema = Schema { args: &[Arg { name: "player",
+                                               kind: Kind::Text,
+                                               arity: Arity::Required, },
+                                         Arg { name: "game",
+                                               kind: Kind::Rest,
+                                               arity: Arity::Required, }],
+                                 flags: &[Flag { name: "top",
+                                                 kind: Some(Kind::Integer), },
+                                          Flag { name: "verbose",
+                                                 kind: None, }], };
+
+    static NUKE: Schema = Schema { args: &[Arg { name: "amount",
+                                                 kind: Kind::Integer,
+                                                 arity: Arity::Required, },
+                                           Arg { name: "whose",
+                                                 kind: Kind::User,
+                                                 arity: Arity::Many, }],
+                                   flags: &[], };
+
+    static BAN: Schema = Schema { args: &[Arg { name: "user",
+                                                kind: Kind::User,
+                                                arity: Arity::Required, },
+                                          Arg { name: "duration",
+                                                kind: Kind::Duration,
+                                                arity: Arity::Optional, },
+                                          Arg { name: "reason",
+                                                kind: Kind::Rest,
+                                                arity: Arity::Optional, }],
+                                  flags: &[], };
+
+    #[test]
+    fn quoted_text() {
+        let args = parse(&PB, "\"Some Player\" Half-Life 2").unwrap();
+        assert_eq!(Some("Some Player"), args.text("player"));
+        assert_eq!(Some("Half-Life 2"), args.text("game"));
+    }
+
+    #[test]
+    fn escaped_quote() {
+        let args = parse(&PB, r#""a \"b\" c" game"#).unwrap();
+        assert_eq!(Some("a \"b\" c"), args.text("player"));
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert_eq!(Some(Error::UnterminatedQuote),
+                   parse(&PB, "\"Some Player Half-Life").err());
+    }
+
+    #[test]
+    fn flags_anywhere() {
+        let args = parse(&PB, "--verbose YaLTeR --top 5 Half-Life").unwrap();
+        assert_eq!(Some("YaLTeR"), args.text("player"));
+        assert_eq!(Some("Half-Life"), args.text("game"));
+        assert_eq!(Some(5), args.integer("top"));
+        assert!(args.has("verbose"));
+    }
+
+    #[test]
+    fn flag_errors() {
+        assert_eq!(Some(Error::UnknownFlag("bottom".to_owned())),
+                   parse(&PB, "--bottom 5 a b").err());
+        assert_eq!(Some(Error::MissingFlagValue("top")), parse(&PB, "--top").err());
+        assert_eq!(Some(Error::Invalid { name: "top",
+                                         kind: Kind::Integer,
+                                         value: "five".to_owned(), }),
+                   parse(&PB, "a --top five b").err());
+    }
+
+    #[test]
+    fn rest_as_typed() {
+        let args = parse(&PB, "YaLTeR  Half-Life\n  \"2\" --top 5  ").unwrap();
+        assert_eq!(Some("Half-Life\n  \"2\" --top 5"), args.text("game"));
+        assert_eq!(None, args.integer("top"));
+
+        // Commands without flags don't look for any.
+        let args = parse(&BAN, "<@1> --help \"unterminated").unwrap();
+        assert_eq!(Some("--help \"unterminated"), args.text("reason"));
+    }
+
+    #[test]
+    fn missing() {
+        assert_eq!(Some(Error::Missing("game")), parse(&PB, "YaLTeR").err());
+        assert_eq!(Some(Error::Missing("amount")), parse(&NUKE, "").err());
+    }
+
+    #[test]
+    fn mentions() {
+        let args = parse(&NUKE, "10 <@123> <@!456> 789").unwrap();
+        assert_eq!(Some(10), args.integer("amount"));
+        assert_eq!(vec![UserId(123), UserId(456), UserId(789)], args.users("whose"));
+    }
+
+    #[test]
+    fn unexpected() {
+        assert_eq!(Some(Error::Unexpected("<@&123>".to_owned())),
+                   parse(&NUKE, "10 <@123> <@&123>").err());
+    }
+
+    #[test]
+    fn optional_skipped() {
+        let args = parse(&BAN, "<@1> spamming links").unwrap();
+        assert_eq!(Some(UserId(1)), args.user("user"));
+        assert_eq!(None, args.duration("duration"));
+        assert_eq!(Some("spamming links"), args.text("reason"));
+
+        let args = parse(&BAN, "<@1> 1d12h").unwrap();
+        assert_eq!(Some(Duration::from_secs(36 * 60 * 60)), args.duration("duration"));
+        assert_eq!(None, args.text("reason"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Some(Duration::from_secs(90)), parse_duration("1m30s"));
+        assert_eq!(Some(Duration::from_secs(60 * 60 * 24 * 7)), parse_duration("1w"));
+        assert_eq!(None, parse_duration("30"));
+        assert_eq!(None, parse_duration("m"));
+        assert_eq!(None, parse_duration("5x"));
+        assert_eq!(None, parse_duration(""));
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!("1h 30m", format_duration(Duration::from_secs(90 * 60)));
+        assert_eq!("1s", format_duration(Duration::from_millis(200)));
+        assert_eq!("0s", format_duration(Duration::from_secs(0)));
+    }
+}
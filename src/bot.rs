@@ -1,70 +1,214 @@
+use config::Config;
+use cooldowns::Cooldowns;
+use crashes::{self, Crashes, Recover};
 use discord::{self, model::*, *};
+use embed::Embed;
 use hyper::status::StatusCode;
 use module::Module;
+use pages::{Page, Paginated};
+use pool::Pool;
+use queue::{Queue, Route};
+use rand::{self, Rng};
+use replies::{Kind, Replies, Reply};
 use std::{
+    cmp,
+    collections::hash_map::HashMap,
     io::Read,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, Once, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
+use storage::{Storage, Store};
+use transport::Transport;
+
+// The prefix used on servers which haven't set their own.
+pub const DEFAULT_COMMAND_PREFIX: &str = "!";
+
+// Discord doesn't accept messages longer than this.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+// Longest code block opening line, like "```rust", that is carried over to the next part.
+const MAX_FENCE_LENGTH: usize = 16;
+
+const PREVIOUS_PAGE_EMOJI: &str = "◀";
+const NEXT_PAGE_EMOJI: &str = "▶";
+// Paginated messages stop reacting to flipping after this long.
+const PAGINATION_TIMEOUT_SECS: u64 = 10 * 60;
+
+const WORKER_COUNT: usize = 8;
+const WORKER_QUEUE_SIZE: usize = 32;
+const HANDLER_TIMEOUT_SECS: u64 = 60;
+
+// The delay before a reconnect attempt doubles every time, up to the max, and then gets
+// randomly shortened by up to half so that many bots don't all come back at once.
+const RECONNECT_BASE_DELAY_MS: u64 = 1000;
+const RECONNECT_MAX_DELAY_MS: u64 = 5 * 60 * 1000;
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+// Gateway close codes which mean that reconnecting won't help, like a wrong token.
+const FATAL_CLOSE_CODES: [u16; 6] = [4004, 4010, 4011, 4012, 4013, 4014];
+
+// What to do after an error from the gateway.
+#[derive(Debug, PartialEq)]
+enum Recovery {
+    // Keep receiving on the same connection.
+    Retry,
+    Reconnect,
+    // Reconnecting won't help.
+    Stop,
+}
+
+// What happens after the bot stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shutdown {
+    Exit,
+    Restart,
+}
 
 pub struct BotThreadUnsafe {
+    discord: Arc<Discord>,
     connection: Connection,
     sync_part: Arc<Bot>,
 }
 
 pub struct Bot {
-    discord: Discord,
+    transport: Arc<Transport>,
+    // Every REST request goes through here.
+    requests: Queue,
     state: RwLock<State>,
     modules: Vec<Box<Module>>,
+    workers: Pool,
+    cooldowns: Cooldowns,
+    crashes: Crashes,
+    storage: Storage,
+    // Zero means never upload replies as files.
+    max_message_parts: usize,
+    paginated: Mutex<HashMap<MessageId, Paginated>>,
+    replies: Replies,
+    owners: Vec<UserId>,
+    shutdown: Mutex<Option<Shutdown>>,
+    shutdown_grace: Duration,
+    shut_down: Once,
 }
 
 impl BotThreadUnsafe {
-    pub fn new(discord: Discord, modules: Vec<Box<Module>>) -> Self {
-        // Connect.
-        let (connection, ready) = discord.connect().expect("Connect failed");
-        println!("[Ready] {} is serving {} servers.",
-                 ready.user.username,
-                 ready.servers.len());
+    pub fn new(discord: Discord,
+               modules: Vec<Box<Module>>,
+               config: &Config)
+               -> Result<Self, String> {
+        // Connect, going through the same backoff as reconnects if the first attempt fails.
+        let (connection, ready) = match discord.connect() {
+            Ok(x) => x,
+            Err(err) => {
+                warn!("Couldn't connect: {:?}.", err);
+                if recovery(&err) == Recovery::Stop {
+                    return Err(format!("The gateway refused the connection: {:?}", err));
+                }
+
+                match connect_with_backoff(&discord, || false) {
+                    Some(x) => x,
+                    None => return Err("Couldn't connect to Discord.".to_owned()),
+                }
+            }
+        };
+        info!("{} is serving {} servers.",
+              ready.user.username,
+              ready.servers.len());
+
+        let discord = Arc::new(discord);
+
+        let sync_part = Arc::new(Bot::new(discord.clone(), ready, modules, config)?);
 
-        BotThreadUnsafe { connection,
-                          sync_part: Arc::new(Bot { discord,
-                                                    state: RwLock::new(State::new(ready)),
-                                                    modules, }), }
+        Ok(BotThreadUnsafe { discord,
+                             connection,
+                             sync_part, })
     }
 
+    // Returns None once the connection is gone for good.
     pub fn receive_event(&mut self) -> Option<Event> {
-        let event = match self.connection.recv_event() {
-            Ok(event) => event,
-            Err(err) => {
-                println!("[Warning] Receive error: {:?}.", err);
-
-                match err {
-                    discord::Error::WebSocket(..) => {
-                        // The connection was dropped, try to reconnect.
-                        let (new_connection, ready) =
-                            self.sync_part.discord.connect().expect("Connect failed");
-                        self.connection = new_connection;
-                        *self.sync_part.state.write().unwrap() = State::new(ready);
-                        println!("[Ready] Reconnected successfully.");
+        loop {
+            let err = match self.connection.recv_event() {
+                Ok(event) => {
+                    match event {
+                        // discord-rs had to start a new session on its own.
+                        Event::Ready(ref ready) => {
+                            *self.sync_part.state.write().recover() = State::new(ready.clone())
+                        }
+                        Event::Resumed { .. } => info!("Resumed the session."),
+                        _ => self.sync_part.state.write().recover().update(&event),
                     }
-                    discord::Error::Closed(..) => return None,
-                    _ => {}
+
+                    return Some(event);
                 }
+                Err(err) => err,
+            };
+
+            warn!("Receive error: {:?}.", err);
 
-                return self.receive_event();
+            match recovery(&err) {
+                Recovery::Retry => {}
+                Recovery::Reconnect => return self.reconnect().map(Event::Ready),
+                Recovery::Stop => {
+                    error!("The gateway closed the connection for good: {:?}.", err);
+                    return None;
+                }
             }
-        };
+        }
+    }
 
-        self.sync_part.state.write().unwrap().update(&event);
+    // Starts a new session. The events from while the bot was disconnected are lost, the
+    // returned Ready event lets the modules resync.
+    fn reconnect(&mut self) -> Option<ReadyEvent> {
+        let sync_part = self.sync_part.clone();
+        let (connection, ready) =
+            connect_with_backoff(&self.discord, || sync_part.get_shutdown().is_some())?;
 
-        Some(event)
+        self.connection = connection;
+        *self.sync_part.state.write().recover() = State::new(ready.clone());
+        info!("Reconnected successfully.");
+        Some(ready)
     }
 
     pub fn get_sync(&self) -> &Arc<Bot> {
         &self.sync_part
     }
+
+    // Closes the gateway connection.
+    pub fn close(self) {
+        if let Err(err) = self.connection.shutdown() {
+            warn!("Couldn't close the connection: {:?}.", err);
+        }
+    }
 }
 
 impl Bot {
+    pub fn new(transport: Arc<Transport>,
+               ready: ReadyEvent,
+               modules: Vec<Box<Module>>,
+               config: &Config)
+               -> Result<Self, String> {
+        let workers = Pool::new(WORKER_COUNT,
+                                WORKER_QUEUE_SIZE,
+                                Duration::from_secs(HANDLER_TIMEOUT_SECS));
+        let storage = Storage::open(&config.data_dir).map_err(|err| {
+            format!("Couldn't open the storage in {}: {}", config.data_dir.display(), err)
+        })?;
+
+        Ok(Bot { transport,
+                 requests: Queue::new(),
+                 state: RwLock::new(State::new(ready)),
+                 modules,
+                 workers,
+                 cooldowns: Cooldowns::new(),
+                 crashes: Crashes::new(),
+                 storage,
+                 max_message_parts: config.max_message_parts,
+                 paginated: Mutex::new(HashMap::new()),
+                 replies: Replies::new(),
+                 owners: config.owners.iter().map(|&x| UserId(x)).collect(),
+                 shutdown: Mutex::new(None),
+                 shutdown_grace: Duration::from_secs(config.shutdown_grace_secs),
+                 shut_down: Once::new(), })
+    }
+
     pub fn get_modules(&self) -> &Vec<Box<Module>> {
         &self.modules
     }
@@ -73,19 +217,343 @@ impl Bot {
         &self.state
     }
 
+    pub fn get_workers(&self) -> &Pool {
+        &self.workers
+    }
+
+    pub fn get_cooldowns(&self) -> &Cooldowns {
+        &self.cooldowns
+    }
+
+    // The module's part of the storage. Modules use their config name as the namespace.
+    pub fn get_storage<'a>(&'a self, namespace: &'a str) -> Store<'a> {
+        self.storage.store(namespace)
+    }
+
+    // Runs f, which calls into the module, catching panics. Crashes are logged with a backtrace
+    // and reported to the channel, if there is one, along with an ID to find them in the log.
+    pub fn run_isolated<F: FnOnce()>(&self, module: &Module, channel: Option<ChannelId>, f: F) {
+        let name = module.name();
+
+        if self.crashes.is_disabled(name) {
+            if let Some(channel) = channel {
+                self.send(channel,
+                          &format!("The {} module is turned off because it kept crashing.",
+                                   name));
+            }
+            return;
+        }
+
+        if let Err(crash) = crashes::catch(f) {
+            error!("The {} module crashed, error ID {}: {}\n{}",
+                   name, crash.id, crash.message, crash.backtrace);
+
+            if let Some(channel) = channel {
+                self.send(channel,
+                          &format!("Sorry, something went wrong. :( The error ID is `{}`.",
+                                   crash.id));
+            }
+
+            if self.crashes.record(name) {
+                error!("Turned off the {} module until restart, it crashes too often.", name);
+            }
+        }
+    }
+
+    // Calls f for every module, isolated from each other.
+    pub fn for_each_module<F: Fn(&Module)>(&self, f: F) {
+        for module in &self.modules {
+            self.run_isolated(&**module, None, || f(&**module));
+        }
+    }
+
+    pub fn is_module_disabled(&self, name: &str) -> bool {
+        self.crashes.is_disabled(name)
+    }
+
+    pub fn is_owner(&self, user: UserId) -> bool {
+        self.owners.contains(&user)
+    }
+
+    // Asks the main loop to stop. Only the first request counts.
+    pub fn request_shutdown(&self, what: Shutdown) {
+        let mut shutdown = self.shutdown.lock().recover();
+
+        if shutdown.is_none() {
+            *shutdown = Some(what);
+        }
+    }
+
+    pub fn get_shutdown(&self) -> Option<Shutdown> {
+        *self.shutdown.lock().recover()
+    }
+
+    // Gives the running handlers the grace period to finish, then lets the modules save their
+    // state. Only does anything the first time.
+    pub fn shut_down(&self) {
+        self.shut_down.call_once(|| {
+            if !self.workers.wait_idle_for(self.shutdown_grace) {
+                warn!("Some handlers didn't finish within the grace period.");
+            }
+
+            self.for_each_module(|module| module.on_shutdown(self));
+        });
+    }
+
+    pub fn is_admin(&self, server: ServerId, user: UserId) -> bool {
+        self.modules.iter().any(|m| m.is_admin(self, server, user))
+    }
+
+    pub fn get_command_prefix(&self, server: Option<ServerId>) -> String {
+        server.and_then(|server| {
+                            self.modules
+                                .iter()
+                                .filter_map(|m| m.command_prefix(self, server))
+                                .next()
+                        })
+              .unwrap_or_else(|| DEFAULT_COMMAND_PREFIX.to_owned())
+    }
+
+    pub fn get_command_prefix_for_channel(&self, channel: ChannelId) -> String {
+        self.get_command_prefix(self.get_server_id(channel))
+    }
+
+    // The server the channel is on, None for private channels and groups.
+    pub fn get_server_id(&self, channel: ChannelId) -> Option<ServerId> {
+        match self.state.read().recover().find_channel(channel) {
+            Some(ChannelRef::Public(server, _)) => Some(server.id),
+            _ => None,
+        }
+    }
+
+    // Whether the server's admins turned the command, or its whole module, off in the channel.
+    pub fn is_command_turned_off(&self,
+                                 server: Option<ServerId>,
+                                 channel: ChannelId,
+                                 module: &Module,
+                                 id: u32)
+                                 -> bool {
+        let server = match server {
+            Some(server) => server,
+            None => return false,
+        };
+        let command = module.commands()[&id][0];
+
+        self.modules
+            .iter()
+            .any(|m| m.is_command_turned_off(self, server, channel, module.name(), command))
+    }
+
     pub fn send(&self, channel: ChannelId, text: &str) {
-        self.handle_error(channel, self.discord.send_message(channel, text, "", false));
+        self.send_and_get(channel, text);
     }
 
+    // Long text is split into several messages, the first one is returned.
     pub fn send_and_get(&self, channel: ChannelId, text: &str) -> Option<Message> {
-        let result = self.discord.send_message(channel, text, "", false);
+        let parts = split_message(text, MAX_MESSAGE_LENGTH);
+
+        if self.max_message_parts > 0 && parts.len() > self.max_message_parts {
+            let result = self.post_file(channel,
+                                        "That's a lot of text, so here's a file instead.",
+                                        text.as_bytes(),
+                                        "message.txt");
+            return self.handle_error_and_return(result).ok();
+        }
+
+        // One turn for all of the parts so that nothing else gets sent in between.
+        self.requests.hold(channel, || {
+            let mut first = None;
+
+            for part in parts {
+                match self.post(channel,
+                                Kind::Text,
+                                |t| t.send_message(channel, &part),
+                                |t, id| t.edit_message(channel, id, &part))
+                {
+                    Ok(message) => {
+                        if first.is_none() {
+                            first = Some(message);
+                        }
+                    }
+
+                    Err(err) => {
+                        self.handle_error(channel, Err::<(), _>(err));
+                        break;
+                    }
+                }
+            }
+
+            first
+        })
+    }
+
+    // Sends the embed, or its text if the bot can't send embeds to the channel.
+    pub fn send_embed(&self, channel: ChannelId, embed: &Embed) {
+        if !self.can_embed(channel) {
+            self.send(channel, &embed.to_text());
+            return;
+        }
 
-        if result.is_ok() {
-            Some(result.unwrap())
+        self.handle_error(channel,
+                          self.post(channel,
+                                    Kind::Embed,
+                                    |t| t.send_embed(channel, "", embed),
+                                    |t, id| t.edit_embed(channel, id, embed)));
+    }
+
+    pub fn edit_embed(&self, channel: ChannelId, message: MessageId, embed: &Embed) {
+        if !self.can_embed(channel) {
+            self.edit(channel, message, &embed.to_text());
+            return;
+        }
+
+        self.handle_error(channel,
+                          self.request(Route::EditMessage(channel), |t| {
+                                  t.edit_embed(channel, message, embed)
+                              }));
+    }
+
+    // Sends the first page and adds reactions for flipping through the rest.
+    pub fn send_paginated(&self, channel: ChannelId, pages: Vec<String>) {
+        self.send_pages(channel, pages.into_iter().map(Page::Text).collect());
+    }
+
+    // Same as send_paginated(), the page numbers go into the footers.
+    pub fn send_paginated_embeds(&self, channel: ChannelId, pages: Vec<Embed>) {
+        let pages = if self.can_embed(channel) {
+            pages.into_iter().map(Page::Embed).collect()
         } else {
-            self.handle_error(channel, result);
-            None
+            pages.into_iter().map(|x| Page::Text(x.to_text())).collect()
+        };
+
+        self.send_pages(channel, pages);
+    }
+
+    fn send_pages(&self, channel: ChannelId, pages: Vec<Page>) {
+        if pages.len() < 2 {
+            match pages.first() {
+                Some(&Page::Text(ref text)) => self.send(channel, text),
+                Some(&Page::Embed(ref embed)) => self.send_embed(channel, embed),
+                None => {}
+            }
+            return;
+        }
+
+        let paginated = Paginated { pages,
+                                    current: 0,
+                                    expires: Instant::now()
+                                             + Duration::from_secs(PAGINATION_TIMEOUT_SECS), };
+
+        let page = paginated.page();
+        let result = match page {
+            Page::Text(ref text) => {
+                self.post(channel,
+                          Kind::Text,
+                          |t| t.send_message(channel, text),
+                          |t, id| t.edit_message(channel, id, text))
+            }
+            Page::Embed(ref embed) => {
+                self.post(channel,
+                          Kind::Embed,
+                          |t| t.send_embed(channel, "", embed),
+                          |t, id| t.edit_embed(channel, id, embed))
+            }
+        };
+        let message = match result {
+            Ok(message) => message,
+            Err(err) => {
+                self.handle_error(channel, Err::<(), _>(err));
+                return;
+            }
+        };
+
+        self.paginated.lock().recover().insert(message.id, paginated);
+
+        for emoji in &[PREVIOUS_PAGE_EMOJI, NEXT_PAGE_EMOJI] {
+            self.handle_error(channel,
+                              self.request(Route::Reactions(channel), |t| {
+                                      t.add_reaction(channel,
+                                                     message.id,
+                                                     ReactionEmoji::Unicode(emoji.to_string()))
+                                  }));
+        }
+    }
+
+    // Flips the page if the reaction was one of the arrows on a paginated message.
+    pub fn handle_pagination_reaction(&self, reaction: &Reaction) {
+        if reaction.user_id == self.state.read().recover().user().id {
+            return;
         }
+
+        let forward = match reaction.emoji {
+            ReactionEmoji::Unicode(ref x) if x == PREVIOUS_PAGE_EMOJI => false,
+            ReactionEmoji::Unicode(ref x) if x == NEXT_PAGE_EMOJI => true,
+            _ => return,
+        };
+
+        let (page, expired) = {
+            let mut paginated = self.paginated.lock().recover();
+
+            let now = Instant::now();
+            let expired = paginated.get(&reaction.message_id)
+                                   .map_or(false, |x| x.expires <= now);
+            paginated.retain(|_, x| x.expires > now);
+
+            let page = paginated.get_mut(&reaction.message_id).map(|message| {
+                let count = message.pages.len();
+                message.current = if forward {
+                    (message.current + 1) % count
+                } else {
+                    (message.current + count - 1) % count
+                };
+
+                message.page()
+            });
+
+            (page, expired)
+        };
+
+        if expired {
+            // Take the arrows away so it's clear that they don't work anymore.
+            for emoji in &[PREVIOUS_PAGE_EMOJI, NEXT_PAGE_EMOJI] {
+                let emoji = ReactionEmoji::Unicode(emoji.to_string());
+                let _ = self.request(Route::Reactions(reaction.channel_id), |t| {
+                                         t.delete_reaction(reaction.channel_id,
+                                                           reaction.message_id,
+                                                           None,
+                                                           emoji.clone())
+                                     });
+            }
+            return;
+        }
+
+        let page = match page {
+            Some(page) => page,
+            None => return,
+        };
+
+        self.handle_error(reaction.channel_id,
+                          self.request(Route::EditMessage(reaction.channel_id), |t| match page {
+                                           Page::Text(ref text) => {
+                                               t.edit_message(reaction.channel_id,
+                                                              reaction.message_id,
+                                                              text)
+                                           }
+                                           Page::Embed(ref embed) => {
+                                               t.edit_embed(reaction.channel_id,
+                                                            reaction.message_id,
+                                                            embed)
+                                           }
+                                       }));
+
+        // Remove the reaction so that the same arrow can be clicked again. This needs the
+        // Manage Messages permission, without it people will have to re-add the reaction.
+        let _ = self.request(Route::Reactions(reaction.channel_id), |t| {
+                                 t.delete_reaction(reaction.channel_id,
+                                                   reaction.message_id,
+                                                   Some(reaction.user_id),
+                                                   reaction.emoji.clone())
+                             });
     }
 
     #[allow(dead_code)]
@@ -95,56 +563,87 @@ impl Bot {
                             text: &str)
                             -> Result<Message> {
         match *message {
-            Ok(ref msg) => self.discord.edit_message(msg.channel_id, msg.id, text),
+            Ok(ref msg) => self.request(Route::EditMessage(msg.channel_id), |t| {
+                                            t.edit_message(msg.channel_id, msg.id, text)
+                                        }),
 
-            Err(_) => self.discord.send_message(channel, text, "", false),
+            Err(_) => self.request(Route::SendMessage(channel), |t| t.send_message(channel, text)),
         }
     }
 
+    // Whatever doesn't fit into the edited message is sent as new messages.
     pub fn edit(&self, channel: ChannelId, message: MessageId, text: &str) {
-        self.handle_error(channel, self.discord.edit_message(channel, message, text));
+        let mut parts = split_message(text, MAX_MESSAGE_LENGTH).into_iter();
+
+        self.requests.hold(channel, || {
+            if let Some(first) = parts.next() {
+                self.handle_error(channel,
+                                  self.request(Route::EditMessage(channel), |t| {
+                                          t.edit_message(channel, message, &first)
+                                      }));
+            }
+
+            for part in parts {
+                self.handle_error(channel,
+                                  self.request(Route::SendMessage(channel), |t| {
+                                          t.send_message(channel, &part)
+                                      }));
+            }
+        });
     }
 
     pub fn send_pm(&self, user: UserId, text: &str, error_reporting_channel: ChannelId) {
-        match self.discord.create_private_channel(user) {
+        match self.request(Route::CreatePrivateChannel, |t| t.create_private_channel(user)) {
             Ok(private_channel) => {
                 self.handle_error(error_reporting_channel,
-                                  self.discord
-                                      .send_message(private_channel.id, text, "", false));
+                                  self.request(Route::SendMessage(private_channel.id), |t| {
+                                          t.send_message(private_channel.id, text)
+                                      }));
             }
 
             Err(err) => {
-                self.handle_error(
-                    error_reporting_channel,
-                    self.discord.send_message(
-                        error_reporting_channel,
-                        &format!("Error creating a private channel: `{:?}`.", err),
-                        "",
-                        false,
-                    ),
-                );
+                self.send(error_reporting_channel,
+                          &format!("Error creating a private channel: `{:?}`.", err));
             }
         }
     }
 
-    pub fn send_file<R: Read>(&self, channel: ChannelId, text: &str, file: R, filename: &str) {
-        self.handle_error(channel,
-                          self.discord.send_file(channel, text, file, filename));
+    pub fn send_file<R: Read>(&self, channel: ChannelId, text: &str, mut file: R, filename: &str) {
+        // Read it all up front so that it can be sent again if it needs a retry.
+        let mut contents = Vec::new();
+        if let Err(err) = file.read_to_end(&mut contents) {
+            warn!("Couldn't read {}: {}", filename, err);
+            return;
+        }
+
+        self.handle_error(channel, self.post_file(channel, text, &contents, filename));
     }
 
     pub fn broadcast_typing(&self, channel: ChannelId) {
-        self.handle_error(channel, self.discord.broadcast_typing(channel));
+        self.handle_error(channel,
+                          self.request(Route::Typing(channel), |t| t.broadcast_typing(channel)));
     }
 
     pub fn delete_messages(&self, channel: ChannelId, messages: &[MessageId]) {
-        // The Discord API accepts up to 100 at once.
+        // The Discord API accepts from 2 up to 100 at once.
         for chunk in messages.chunks(100) {
-            self.handle_error(channel, self.discord.delete_messages(channel, chunk));
+            if chunk.len() == 1 {
+                self.delete_message(channel, chunk[0]);
+                continue;
+            }
+
+            self.handle_error(channel,
+                              self.request(Route::DeleteMessages(channel), |t| {
+                                      t.delete_messages(channel, chunk)
+                                  }));
         }
     }
 
-    pub fn get_message(&self, channel: ChannelId, message: MessageId) -> Result<Message> {
-        self.handle_error_and_return(self.discord.get_message(channel, message))
+    pub fn delete_message(&self, channel: ChannelId, message: MessageId) {
+        self.handle_error(channel,
+                          self.request(Route::DeleteMessage(channel), |t| {
+                                  t.delete_message(channel, message)
+                              }));
     }
 
     pub fn get_messages(&self,
@@ -152,11 +651,42 @@ impl Bot {
                         what: GetMessages,
                         limit: u64)
                         -> Result<Vec<Message>> {
-        self.handle_error_and_return(self.discord.get_messages(channel, what, Some(limit)))
+        self.handle_error_and_return(self.request(Route::GetMessages(channel), |t| {
+                                                      t.get_messages(channel,
+                                                                     copy_get_messages(&what),
+                                                                     Some(limit))
+                                                  }))
     }
 
     pub fn get_member(&self, server: ServerId, user: UserId) -> Result<Member> {
-        self.handle_error_and_return(self.discord.get_member(server, user))
+        self.handle_error_and_return(self.request(Route::GetMember(server), |t| {
+                                                      t.get_member(server, user)
+                                                  }))
+    }
+
+    pub fn kick_member(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.handle_error_and_return(self.request(Route::Members(server), |t| {
+                                                      t.kick_member(server, user)
+                                                  }))
+    }
+
+    pub fn ban_member(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.handle_error_and_return(self.request(Route::Bans(server), |t| {
+                                                      t.add_ban(server, user)
+                                                  }))
+    }
+
+    pub fn unban_member(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.handle_error_and_return(self.request(Route::Bans(server), |t| {
+                                                      t.remove_ban(server, user)
+                                                  }))
+    }
+
+    // Replaces all of the member's roles.
+    pub fn set_member_roles(&self, server: ServerId, user: UserId, roles: &[RoleId]) -> Result<()> {
+        self.handle_error_and_return(self.request(Route::Members(server), |t| {
+                                                      t.edit_member_roles(server, user, roles)
+                                                  }))
     }
 
     pub fn create_channel(&self,
@@ -164,11 +694,117 @@ impl Bot {
                           name: &str,
                           kind: ChannelType)
                           -> Result<Channel> {
-        self.handle_error_and_return(self.discord.create_channel(server, name, kind))
+        self.handle_error_and_return(self.request(Route::CreateChannel(server), |t| {
+                                                      t.create_channel(server, name, kind)
+                                                  }))
     }
 
     pub fn create_permissions(&self, channel: ChannelId, target: PermissionOverwrite) {
-        let _ = self.handle_error_and_return(self.discord.create_permission(channel, target));
+        let _ = self.handle_error_and_return(self.request(Route::Permissions(channel), |t| {
+                                                              t.create_permission(channel,
+                                                                                  target.clone())
+                                                          }));
+    }
+
+    // Embeds in server channels need the Embed Links permission.
+    fn can_embed(&self, channel: ChannelId) -> bool {
+        let state = self.state.read().recover();
+
+        match state.find_channel(channel) {
+            Some(ChannelRef::Public(server, _)) => {
+                server.permissions_for(channel, state.user().id)
+                      .contains(permissions::EMBED_LINKS)
+            }
+            _ => true,
+        }
+    }
+
+    // Runs f, which is the command in the message, so that its replies can be updated when the
+    // command is edited and deleted along with it. When the command is run again after an edit,
+    // its earlier replies get edited instead of sending new messages and the rest are deleted.
+    pub fn track_replies<F: FnOnce()>(&self, message: &Message, earlier: Vec<Reply>, f: F) {
+        let left_over = self.replies.track(message, earlier, f);
+        self.delete_replies(message.channel_id, &left_over);
+    }
+
+    // The edited command with its new content and its replies so far, if it's a recent command
+    // and its content did change.
+    pub fn take_edited_command(&self,
+                               channel: ChannelId,
+                               message: MessageId,
+                               content: &str)
+                               -> Option<(Message, Vec<Reply>)> {
+        self.replies.take_edited(channel, message, content)
+    }
+
+    // Deletes the replies to the command, which was deleted.
+    pub fn delete_command_replies(&self, channel: ChannelId, message: MessageId) {
+        let replies = self.replies.take_deleted(channel, message);
+        self.delete_replies(channel, &replies);
+    }
+
+    pub fn delete_replies(&self, channel: ChannelId, replies: &[Reply]) {
+        if !replies.is_empty() {
+            let ids = replies.iter().map(|x| x.id).collect::<Vec<_>>();
+            self.delete_messages(channel, &ids);
+        }
+    }
+
+    // Sends a message with send, or when an edited command is being run again, edits one of its
+    // earlier replies with edit instead.
+    fn post<S, E>(&self,
+                  channel: ChannelId,
+                  kind: Kind,
+                  mut send: S,
+                  mut edit: E)
+                  -> Result<Message>
+        where S: FnMut(&Transport) -> Result<Message>,
+              E: FnMut(&Transport, MessageId) -> Result<Message>
+    {
+        let result = match self.replies.take_earlier(channel, kind) {
+            Some(id) => self.request(Route::EditMessage(channel), |t| edit(t, id)),
+            None => self.request(Route::SendMessage(channel), |t| send(t)),
+        };
+
+        if let Ok(ref message) = result {
+            self.replies.record(channel,
+                                Reply { id: message.id,
+                                        kind });
+        }
+
+        result
+    }
+
+    // Attachments can't be edited, so an earlier reply in the file's place gets deleted instead.
+    fn post_file(&self,
+                 channel: ChannelId,
+                 text: &str,
+                 contents: &[u8],
+                 filename: &str)
+                 -> Result<Message> {
+        self.replies.take_earlier(channel, Kind::File);
+
+        let result = self.request(Route::SendMessage(channel), |t| {
+                                      t.send_file(channel, text, &mut &contents[..], filename)
+                                  });
+
+        if let Ok(ref message) = result {
+            self.replies.record(channel,
+                                Reply { id: message.id,
+                                        kind: Kind::File });
+        }
+
+        result
+    }
+
+    // The number of REST requests waiting for their turn or the rate limit, or being sent.
+    pub fn get_queue_depth(&self) -> usize {
+        self.requests.depth()
+    }
+
+    // Makes a request through the queue, f is called again for every retry.
+    fn request<T, F: FnMut(&Transport) -> Result<T>>(&self, route: Route, mut f: F) -> Result<T> {
+        self.requests.run(route, || f(&*self.transport))
     }
 
     fn handle_error<T>(&self, channel: ChannelId, res: Result<T>) {
@@ -184,15 +820,325 @@ impl Bot {
                 }
             }
 
-            println!("[Warning] {:?}", err);
+            warn!("{:?}", err);
         }
     }
 
     fn handle_error_and_return<T>(&self, res: Result<T>) -> Result<T> {
         if let Err(ref err) = res {
-            println!("[Warning] {:?}", err);
+            warn!("{:?}", err);
         }
 
         res
     }
 }
+
+// GetMessages can't be cloned, but every retry needs its own.
+fn copy_get_messages(what: &GetMessages) -> GetMessages {
+    match *what {
+        GetMessages::MostRecent => GetMessages::MostRecent,
+        GetMessages::Before(id) => GetMessages::Before(id),
+        GetMessages::After(id) => GetMessages::After(id),
+        GetMessages::Around(id) => GetMessages::Around(id),
+    }
+}
+
+// Splits text into parts of at most `limit` characters, preferring line boundaries.
+// Code blocks cut in the middle are closed and reopened in the next part.
+fn split_message(text: &str, limit: usize) -> Vec<String> {
+    if text.chars().count() <= limit {
+        return vec![text.to_owned()];
+    }
+
+    // Room for closing and reopening a code block.
+    let reserve = cmp::min(MAX_FENCE_LENGTH * 2, limit / 2);
+    let piece_limit = limit - reserve;
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut current_length = 0;
+    // The line which opened the code block we're in, for example "```rust".
+    let mut fence: Option<String> = None;
+
+    for line in text.split('\n') {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut pieces = chars.chunks(piece_limit)
+                              .map(|x| x.iter().collect::<String>())
+                              .collect::<Vec<_>>();
+        if pieces.is_empty() {
+            pieces.push(String::new());
+        }
+
+        for piece in pieces {
+            let piece_length = piece.chars().count();
+            let separator = if current.is_empty() { 0 } else { 1 };
+            let closing = if fence.is_some() { 4 } else { 0 };
+
+            if !current.is_empty()
+               && current_length + separator + piece_length + closing > limit
+            {
+                if fence.is_some() {
+                    current.push_str("\n```");
+                }
+                parts.push(current);
+
+                current = fence.clone().unwrap_or_default();
+                current_length = current.chars().count();
+            }
+
+            if !current.is_empty() {
+                current.push('\n');
+                current_length += 1;
+            }
+            current.push_str(&piece);
+            current_length += piece_length;
+
+            if piece.matches("```").count() % 2 == 1 {
+                fence = match fence {
+                    Some(_) => None,
+                    None => {
+                        let language = piece.rsplit("```").next().unwrap();
+                        if language.len() <= MAX_FENCE_LENGTH - 3
+                           && language.chars()
+                                      .all(|c| c.is_alphanumeric() || "+-_".contains(c))
+                        {
+                            Some(format!("```{}", language))
+                        } else {
+                            Some("```".to_owned())
+                        }
+                    }
+                };
+            }
+        }
+    }
+
+    parts.push(current);
+    parts.retain(|x| !x.trim().is_empty());
+    parts
+}
+
+fn recovery(err: &discord::Error) -> Recovery {
+    match *err {
+        // discord-rs tries to resume the session by itself before giving up with these.
+        discord::Error::WebSocket(..) => Recovery::Reconnect,
+        discord::Error::Closed(Some(code), _) if FATAL_CLOSE_CODES.contains(&code) => {
+            Recovery::Stop
+        }
+        discord::Error::Closed(..) => Recovery::Reconnect,
+        _ => Recovery::Retry,
+    }
+}
+
+// Connects, waiting longer and longer between the attempts. Gives up after too many of them, if
+// the gateway refuses the connection for good, or once stop() returns true.
+fn connect_with_backoff<F: Fn() -> bool>(discord: &Discord,
+                                         stop: F)
+                                         -> Option<(Connection, ReadyEvent)> {
+    for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+        let delay = reconnect_delay(attempt, rand::thread_rng().gen_range(0.5, 1.0));
+        info!("Reconnecting in {}ms, attempt {} of {}.",
+              delay.as_secs() * 1000 + u64::from(delay.subsec_millis()),
+              attempt + 1,
+              MAX_RECONNECT_ATTEMPTS);
+        thread::sleep(delay);
+
+        if stop() {
+            return None;
+        }
+
+        match discord.connect() {
+            Ok(x) => return Some(x),
+            Err(err) => {
+                warn!("Couldn't reconnect: {:?}.", err);
+                if recovery(&err) == Recovery::Stop {
+                    error!("The gateway refused the connection for good.");
+                    return None;
+                }
+            }
+        }
+    }
+
+    error!("Couldn't reconnect after {} attempts, giving up.",
+           MAX_RECONNECT_ATTEMPTS);
+    None
+}
+
+// The delay before the reconnect attempt, jitter is between 0.5 and 1.
+fn reconnect_delay(attempt: u32, jitter: f64) -> Duration {
+    let delay = RECONNECT_BASE_DELAY_MS.saturating_mul(1 << cmp::min(attempt, 20));
+    let delay = cmp::min(delay, RECONNECT_MAX_DELAY_MS) as f64 * jitter;
+
+    Duration::from_millis(delay as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconnect_delay, recovery, split_message, Recovery, NEXT_PAGE_EMOJI};
+    use std::time::Duration;
+    use discord::{self, model::*};
+    use embed::Embed;
+    use fake::{self, Action};
+
+    #[test]
+    fn reconnect_delay_backs_off() {
+        assert_eq!(Duration::from_millis(1000), reconnect_delay(0, 1.0));
+        assert_eq!(Duration::from_millis(4000), reconnect_delay(2, 1.0));
+        assert_eq!(Duration::from_millis(2000), reconnect_delay(2, 0.5));
+        assert_eq!(Duration::from_secs(5 * 60), reconnect_delay(9, 1.0));
+        assert_eq!(Duration::from_secs(5 * 60), reconnect_delay(1000, 1.0));
+    }
+
+    #[test]
+    fn fatal_close_codes() {
+        assert_eq!(Recovery::Stop,
+                   recovery(&discord::Error::Closed(Some(4004),
+                                                    "Authentication failed.".to_owned())));
+        assert_eq!(Recovery::Reconnect,
+                   recovery(&discord::Error::Closed(Some(4000), String::new())));
+        assert_eq!(Recovery::Reconnect, recovery(&discord::Error::Closed(None, String::new())));
+        assert_eq!(Recovery::Retry, recovery(&discord::Error::Other("unknown event")));
+    }
+
+    #[test]
+    fn split_message_short() {
+        assert_eq!(vec!["hello\nworld"], split_message("hello\nworld", 2000));
+    }
+
+    #[test]
+    fn split_message_lines() {
+        let text = (0..300).map(|x| format!("Line number {}", x)).collect::<Vec<_>>().join("\n");
+        let parts = split_message(&text, 2000);
+
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|x| x.chars().count() <= 2000));
+        assert!(parts.iter().all(|x| x.starts_with("Line number ")));
+        assert_eq!(text, parts.join("\n"));
+    }
+
+    #[test]
+    fn split_message_long_line() {
+        let text = "ы".repeat(4500);
+        let parts = split_message(&text, 2000);
+
+        assert_eq!(3, parts.len());
+        assert!(parts.iter().all(|x| x.chars().count() <= 2000));
+        assert_eq!(text, parts.concat());
+    }
+
+    #[test]
+    fn split_message_code_block() {
+        let mut text = "Here:\n```rust\n".to_owned();
+        for x in 0..100 {
+            text.push_str(&format!("let x{} = {};\n", x, x));
+        }
+        text.push_str("```\nDone.");
+
+        let parts = split_message(&text, 500);
+
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.chars().count() <= 500);
+            assert_eq!(0, part.matches("```").count() % 2, "{}", part);
+        }
+        assert!(parts[1].starts_with("```rust\n"));
+        assert!(parts.last().unwrap().ends_with("```\nDone."));
+    }
+
+    #[test]
+    fn split_message_inline_code_block() {
+        let mut text = "```Server ID: 1,\n".to_owned();
+        for x in 0..100 {
+            text.push_str(&format!("- {} 'role'\n", x));
+        }
+        text.push_str("```");
+
+        let parts = split_message(&text, 300);
+
+        for part in &parts {
+            assert_eq!(0, part.matches("```").count() % 2, "{}", part);
+        }
+        assert!(parts[1].starts_with("```\n"));
+    }
+
+    #[test]
+    fn pagination() {
+        let (fake, bot) = fake::bot(Vec::new());
+
+        bot.send_paginated(fake::CHANNEL_ID, vec!["one".to_owned(), "two".to_owned()]);
+        let message = match fake.take_actions().as_slice() {
+            &[Action::Send { ref text, .. },
+              Action::AddReaction { message, .. },
+              Action::AddReaction { .. }] => {
+                assert_eq!("one\n\n`Page 1/2`", text);
+                message
+            }
+            x => panic!("unexpected actions {:?}", x),
+        };
+
+        let reaction = Reaction { channel_id: fake::CHANNEL_ID,
+                                  message_id: message,
+                                  user_id: fake::USER_ID,
+                                  emoji: ReactionEmoji::Unicode(NEXT_PAGE_EMOJI.to_owned()), };
+        bot.handle_pagination_reaction(&reaction);
+
+        match fake.take_actions().as_slice() {
+            &[Action::Edit { ref text, .. }, Action::DeleteReaction { user, .. }] => {
+                assert_eq!("two\n\n`Page 2/2`", text);
+                assert_eq!(Some(fake::USER_ID), user);
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+    }
+
+    #[test]
+    fn embed_fallback() {
+        let (fake, bot) = fake::bot(Vec::new());
+        let embed = Embed::new().title("Title").field("Name", "Value", true);
+
+        bot.send_embed(fake::CHANNEL_ID, &embed);
+        assert_eq!(vec![embed.clone()], fake.take_embeds());
+
+        bot.send_embed(fake::NO_EMBEDS_CHANNEL_ID, &embed);
+        assert_eq!(vec!["**Title**\n**Name**: Value"], fake.take_replies());
+
+        bot.send_paginated_embeds(fake::CHANNEL_ID, vec![embed.clone(), embed.clone()]);
+        match fake.take_actions().as_slice() {
+            &[Action::SendEmbed { ref embed, .. },
+              Action::AddReaction { .. },
+              Action::AddReaction { .. }] => {
+                assert_eq!(Some("Page 1/2".to_owned()), embed.footer);
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+    }
+
+    #[test]
+    fn edited_command() {
+        let (fake, bot) = fake::bot(Vec::new());
+        let message = fake::message(MessageId(1), fake::CHANNEL_ID, &fake::owner(), "!cmd");
+
+        let mut ids = Vec::new();
+        bot.track_replies(&message, Vec::new(), || {
+                              for text in &["one", "two"] {
+                                  ids.push(bot.send_and_get(fake::CHANNEL_ID, text).unwrap().id);
+                              }
+                          });
+        let (first, second) = (ids[0], ids[1]);
+        fake.take_actions();
+
+        let (message, earlier) = bot.take_edited_command(fake::CHANNEL_ID, MessageId(1), "!cmd 2")
+                                    .unwrap();
+        bot.track_replies(&message, earlier, || bot.send(fake::CHANNEL_ID, "three"));
+        assert_eq!(vec![Action::Edit { channel: fake::CHANNEL_ID,
+                                       message: first,
+                                       text: "three".to_owned(), },
+                        Action::DeleteMessage { channel: fake::CHANNEL_ID,
+                                                message: second, }],
+                   fake.take_actions());
+
+        bot.delete_command_replies(fake::CHANNEL_ID, MessageId(1));
+        assert_eq!(vec![Action::DeleteMessage { channel: fake::CHANNEL_ID,
+                                                message: first, }],
+                   fake.take_actions());
+    }
+}
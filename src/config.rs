@@ -0,0 +1,284 @@
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use toml::{value::Table, Value};
+
+pub const DEFAULT_CONFIG_FILENAME: &str = "config.toml";
+
+// Replies that would take more messages than this are uploaded as a file instead.
+const DEFAULT_MAX_MESSAGE_PARTS: usize = 5;
+
+// How long the running handlers get to finish when the bot is shutting down.
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 10;
+
+const DEFAULT_LOG_FILE: &str = "yalter-bot.log";
+const DEFAULT_MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_LOG_FILES: usize = 5;
+
+// Environment variables which override settings from the file, and the settings they override.
+// Module settings are under ["modules", <module>, <setting>].
+static ENV_OVERRIDES: [(&str, &[&str]); 5] =
+    [("YALTER_BOT_TOKEN", &["token"]),
+     ("YALTER_BOT_DATA_DIR", &["data_dir"]),
+     ("YALTER_BOT_LOG_LEVEL", &["logging", "level"]),
+     ("YALTER_BOT_CLIENT_ID", &["modules", "invite", "client_id"]),
+     ("YALTER_BOT_WOLFRAMALPHA_APPID", &["modules", "wolframalpha", "app_id"])];
+
+// Module settings which are kept out of the log, along with the token.
+static SECRET_MODULE_SETTINGS: [(&str, &str); 1] = [("wolframalpha", "app_id")];
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: Option<String>,
+    // User IDs of the people who can shut down and restart the bot.
+    pub owners: Vec<u64>,
+    // Where the modules keep their files.
+    pub data_dir: PathBuf,
+    // Zero means never upload replies as files.
+    pub max_message_parts: usize,
+    pub shutdown_grace_secs: u64,
+    pub logging: Logging,
+    // The map is from the module config name into its settings.
+    modules: BTreeMap<String, Table>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    // One of off, error, warn, info, debug or trace.
+    pub level: String,
+    // Levels for parts of the bot, like "bot" or "modules::wolframalpha", which override `level`.
+    pub targets: BTreeMap<String, String>,
+    // Relative to the data directory. Empty means only log to stdout.
+    pub file: String,
+    // The log file is rotated once it gets this big. Zero means never.
+    pub max_file_size: u64,
+    // How many rotated log files to keep.
+    pub max_files: usize,
+    // Whether to log every message the bot sees.
+    pub message_log: bool,
+}
+
+// The settings of a single module, which the module parses itself.
+#[derive(Default)]
+pub struct ModuleConfig {
+    name: String,
+    settings: Table,
+    data_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { token: None,
+                 owners: Vec::new(),
+                 data_dir: PathBuf::from("."),
+                 max_message_parts: DEFAULT_MAX_MESSAGE_PARTS,
+                 shutdown_grace_secs: DEFAULT_SHUTDOWN_GRACE_SECS,
+                 logging: Logging::default(),
+                 modules: BTreeMap::new(), }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging { level: "info".to_owned(),
+                  targets: BTreeMap::new(),
+                  file: DEFAULT_LOG_FILE.to_owned(),
+                  max_file_size: DEFAULT_MAX_LOG_FILE_SIZE,
+                  max_files: DEFAULT_MAX_LOG_FILES,
+                  message_log: true, }
+    }
+}
+
+impl Config {
+    // Loads the config file, if it's there, and applies the environment variable overrides.
+    // It's fine for the file to be missing unless it was asked for explicitly.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let mut root = match read_file(path.unwrap_or_else(|| Path::new(DEFAULT_CONFIG_FILENAME)))
+        {
+            Ok(text) => text.parse::<Value>()
+                            .map_err(|err| format!("Couldn't parse the config file: {}", err))?,
+            Err(ref err) if path.is_none() && err.kind() == io::ErrorKind::NotFound => {
+                Value::Table(Table::new())
+            }
+            Err(err) => return Err(format!("Couldn't read the config file: {}", err)),
+        };
+
+        for &(var, path) in &ENV_OVERRIDES {
+            if let Ok(value) = env::var(var) {
+                set(&mut root, path, Value::String(value));
+            }
+        }
+
+        let mut config = root.try_into::<Config>()
+                             .map_err(|err| format!("Invalid config file: {}", err))?;
+
+        // Not a string, so this one can't go through ENV_OVERRIDES.
+        if let Ok(value) = env::var("YALTER_BOT_MAX_MESSAGE_PARTS") {
+            config.max_message_parts =
+                value.parse()
+                     .map_err(|_| "YALTER_BOT_MAX_MESSAGE_PARTS must be a number".to_owned())?;
+        }
+
+        Ok(config)
+    }
+
+    // Modules are enabled unless their section says `enabled = false`.
+    pub fn module_enabled(&self, name: &str) -> bool {
+        self.modules
+            .get(name)
+            .and_then(|x| x.get("enabled"))
+            .and_then(Value::as_bool)
+            .unwrap_or(true)
+    }
+
+    // The token and the secret module settings, which must not show up in the log.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets = self.token.iter().cloned().collect::<Vec<_>>();
+
+        for &(module, setting) in &SECRET_MODULE_SETTINGS {
+            if let Some(secret) = self.modules
+                                      .get(module)
+                                      .and_then(|x| x.get(setting))
+                                      .and_then(Value::as_str)
+            {
+                secrets.push(secret.to_owned());
+            }
+        }
+
+        secrets
+    }
+
+    pub fn module(&self, name: &str) -> ModuleConfig {
+        let mut settings = self.modules.get(name).cloned().unwrap_or_default();
+        settings.remove("enabled");
+
+        ModuleConfig { name: name.to_owned(),
+                       settings,
+                       data_dir: self.data_dir.clone(), }
+    }
+}
+
+impl ModuleConfig {
+    // Parses the module's section of the config. Missing settings are taken from T::default().
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, String> {
+        Value::Table(self.settings.clone()).try_into()
+                                           .map_err(|err| {
+                                                        format!("Invalid config for the {} \
+                                                                 module: {}",
+                                                                self.name, err)
+                                                    })
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    #[cfg(test)]
+    pub fn from_toml(name: &str, text: &str) -> Self {
+        ModuleConfig { name: name.to_owned(),
+                       settings: text.parse::<Value>().unwrap().try_into().unwrap(),
+                       data_dir: PathBuf::new(), }
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// Sets the value at the path, making the tables on the way if needed.
+fn set(root: &mut Value, path: &[&str], value: Value) {
+    let (last, tables) = path.split_last().unwrap();
+
+    let mut current = root;
+    for key in tables {
+        let table = match *current {
+            Value::Table(ref mut table) => table,
+            _ => return,
+        };
+
+        current = table.entry(key.to_string())
+                       .or_insert_with(|| Value::Table(Table::new()));
+    }
+
+    if let Value::Table(ref mut table) = *current {
+        table.insert(last.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Default, Debug)]
+    #[serde(default)]
+    struct Settings {
+        api_base: String,
+    }
+
+    fn config(text: &str) -> Config {
+        text.parse::<Value>().unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = config("");
+        assert_eq!(None, config.token);
+        assert_eq!(5, config.max_message_parts);
+        assert!(config.module_enabled("fun"));
+    }
+
+    #[test]
+    fn modules() {
+        let config = config("[modules.fun]\n\
+                             enabled = false\n\
+                             [modules.speedruncom]\n\
+                             api_base = \"http://localhost/\"\n");
+
+        assert!(!config.module_enabled("fun"));
+        assert!(config.module_enabled("speedruncom"));
+
+        let settings = config.module("speedruncom").parse::<Settings>().unwrap();
+        assert_eq!("http://localhost/", settings.api_base);
+        assert_eq!("", config.module("fun").parse::<Settings>().unwrap().api_base);
+    }
+
+    #[test]
+    fn invalid_module_settings() {
+        let config = config("[modules.speedruncom]\napi_base = 5\n");
+        assert!(config.module("speedruncom")
+                      .parse::<Settings>()
+                      .unwrap_err()
+                      .starts_with("Invalid config for the speedruncom module: "));
+    }
+
+    #[test]
+    fn secrets() {
+        let config = config("token = \"abc\"\n\
+                             [modules.wolframalpha]\n\
+                             app_id = \"def\"\n\
+                             [modules.invite]\n\
+                             client_id = \"1234\"\n");
+
+        assert_eq!(vec!["abc", "def"], config.secrets());
+    }
+
+    #[test]
+    fn set_makes_tables() {
+        let mut root = Value::Table(Table::new());
+        set(&mut root,
+            &["modules", "invite", "client_id"],
+            Value::String("1234".to_owned()));
+
+        assert_eq!(Some("1234"),
+                   root["modules"]["invite"]["client_id"].as_str());
+    }
+}
@@ -0,0 +1,726 @@
+use bot::Bot;
+use chrono::Utc;
+use config::Config;
+use discord::{model::*, Error, GetMessages, Result};
+use embed::Embed;
+use hyper::status::StatusCode;
+use module::Module;
+use serde_json::{self, Value};
+use std::{
+    fmt,
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use transport::Transport;
+
+// IDs of the things in the console's world.
+pub const BOT_ID: UserId = UserId(1);
+pub const OWNER_ID: UserId = UserId(2);
+pub const USER_ID: UserId = UserId(3);
+pub const SERVER_ID: ServerId = ServerId(10);
+pub const CHANNEL_ID: ChannelId = ChannelId(20);
+pub const PRIVATE_CHANNEL_ID: ChannelId = ChannelId(21);
+// A server channel where the bot doesn't have the Embed Links permission.
+pub const NO_EMBEDS_CHANNEL_ID: ChannelId = ChannelId(22);
+// A server channel for the admin module's log.
+pub const LOG_CHANNEL_ID: ChannelId = ChannelId(23);
+pub const ROLE_ID: RoleId = RoleId(30);
+// The role the admin module hands out to mute people.
+pub const MUTED_ROLE_ID: RoleId = RoleId(31);
+
+// IDs of the messages and channels made up by the console start from here.
+const FIRST_GENERATED_ID: usize = 1000;
+
+// Something the bot did through the transport.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send {
+        channel: ChannelId,
+        text: String,
+    },
+    Edit {
+        channel: ChannelId,
+        message: MessageId,
+        text: String,
+    },
+    SendEmbed {
+        channel: ChannelId,
+        text: String,
+        embed: Embed,
+    },
+    EditEmbed {
+        channel: ChannelId,
+        message: MessageId,
+        embed: Embed,
+    },
+    SendFile {
+        channel: ChannelId,
+        text: String,
+        filename: String,
+        contents: Vec<u8>,
+    },
+    DeleteMessages {
+        channel: ChannelId,
+        messages: Vec<MessageId>,
+    },
+    DeleteMessage {
+        channel: ChannelId,
+        message: MessageId,
+    },
+    Kick {
+        server: ServerId,
+        user: UserId,
+    },
+    Ban {
+        server: ServerId,
+        user: UserId,
+    },
+    Unban {
+        server: ServerId,
+        user: UserId,
+    },
+    EditMemberRoles {
+        server: ServerId,
+        user: UserId,
+        roles: Vec<RoleId>,
+    },
+    CreateChannel {
+        server: ServerId,
+        name: String,
+    },
+    CreatePermission {
+        channel: ChannelId,
+    },
+    BroadcastTyping {
+        channel: ChannelId,
+    },
+    AddReaction {
+        channel: ChannelId,
+        message: MessageId,
+    },
+    DeleteReaction {
+        channel: ChannelId,
+        message: MessageId,
+        user: Option<UserId>,
+    },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Send { channel, ref text } => write!(f, "[#{}] {}", channel.0, text),
+            Action::Edit { channel,
+                           message,
+                           ref text, } => {
+                write!(f, "[#{}] (edited message {}) {}", channel.0, message.0, text)
+            }
+            Action::SendEmbed { channel,
+                                ref text,
+                                ref embed, } => {
+                write!(f, "[#{}] {}\n(embed)\n{}", channel.0, text, embed.to_text())
+            }
+            Action::EditEmbed { channel,
+                                message,
+                                ref embed, } => {
+                write!(f,
+                       "[#{}] (edited message {})\n(embed)\n{}",
+                       channel.0,
+                       message.0,
+                       embed.to_text())
+            }
+            Action::SendFile { channel,
+                               ref text,
+                               ref filename,
+                               ref contents, } => {
+                write!(f,
+                       "[#{}] {}\n(uploaded `{}`, {} bytes)",
+                       channel.0,
+                       text,
+                       filename,
+                       contents.len())?;
+
+                match ::std::str::from_utf8(contents) {
+                    Ok(contents) => write!(f, "\n{}", contents),
+                    Err(_) => Ok(()),
+                }
+            }
+            Action::DeleteMessages { channel,
+                                     ref messages, } => {
+                write!(f, "[#{}] (deleted {} messages)", channel.0, messages.len())
+            }
+            Action::DeleteMessage { channel, message } => {
+                write!(f, "[#{}] (deleted message {})", channel.0, message.0)
+            }
+            Action::Kick { server, user } => {
+                write!(f, "(kicked {} from server {})", user.0, server.0)
+            }
+            Action::Ban { server, user } => {
+                write!(f, "(banned {} from server {})", user.0, server.0)
+            }
+            Action::Unban { server, user } => {
+                write!(f, "(unbanned {} from server {})", user.0, server.0)
+            }
+            Action::EditMemberRoles { server,
+                                      user,
+                                      ref roles, } => {
+                write!(f,
+                       "(set the roles of {} on server {} to {:?})",
+                       user.0,
+                       server.0,
+                       roles.iter().map(|x| x.0).collect::<Vec<_>>())
+            }
+            Action::CreateChannel { server, ref name } => {
+                write!(f, "(created channel `{}` on server {})", name, server.0)
+            }
+            Action::CreatePermission { channel } => {
+                write!(f, "[#{}] (changed permissions)", channel.0)
+            }
+            Action::BroadcastTyping { channel } => write!(f, "[#{}] (typing...)", channel.0),
+            Action::AddReaction { channel, message } => {
+                write!(f, "[#{}] (reacted to message {})", channel.0, message.0)
+            }
+            Action::DeleteReaction { channel,
+                                     message,
+                                     user, } => {
+                write!(f,
+                       "[#{}] (removed a reaction of {} from message {})",
+                       channel.0,
+                       user.map_or("the bot".to_owned(), |x| x.0.to_string()),
+                       message.0)
+            }
+        }
+    }
+}
+
+// An in-memory stand-in for Discord. The console mode prints everything the bot does in it, the
+// tests record it instead to look at it.
+pub struct Console {
+    // Whether to print the actions as they happen.
+    print: bool,
+    actions: Mutex<Vec<Action>>,
+    // Every message the console knows about, oldest first.
+    messages: Mutex<Vec<Message>>,
+    members: Mutex<Vec<Member>>,
+    next_id: AtomicUsize,
+}
+
+impl Console {
+    // A console which prints every action to stdout.
+    pub fn new() -> Self {
+        Console { print: true,
+                  actions: Mutex::new(Vec::new()),
+                  messages: Mutex::new(Vec::new()),
+                  members: Mutex::new(Vec::new()),
+                  next_id: AtomicUsize::new(FIRST_GENERATED_ID), }
+    }
+
+    // A console which records every action instead of printing it.
+    #[cfg(test)]
+    pub fn recording() -> Self {
+        Console { print: false,
+                  ..Console::new() }
+    }
+
+    // Takes the actions recorded so far.
+    #[cfg(test)]
+    pub fn take_actions(&self) -> Vec<Action> {
+        self.actions.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn add_message(&self, message: Message) {
+        self.messages.lock().unwrap().push(message);
+    }
+
+    pub fn add_member(&self, member: Member) {
+        self.members.lock().unwrap().push(member);
+    }
+
+    pub fn generate_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    fn record(&self, action: Action) {
+        if self.print {
+            println!("{}", action);
+        } else {
+            self.actions.lock().unwrap().push(action);
+        }
+    }
+
+    fn post(&self, channel: ChannelId, text: &str) -> Message {
+        let message = message(MessageId(self.generate_id()), channel, &bot_user(), text);
+        self.add_message(message.clone());
+        message
+    }
+}
+
+impl Transport for Console {
+    fn send_message(&self, channel: ChannelId, text: &str) -> Result<Message> {
+        self.record(Action::Send { channel,
+                                   text: text.to_owned(), });
+        Ok(self.post(channel, text))
+    }
+
+    fn edit_message(&self, channel: ChannelId, message: MessageId, text: &str) -> Result<Message> {
+        self.record(Action::Edit { channel,
+                                   message,
+                                   text: text.to_owned(), });
+
+        let mut messages = self.messages.lock().unwrap();
+        match messages.iter_mut()
+                      .find(|x| x.channel_id == channel && x.id == message)
+        {
+            Some(message) => {
+                message.content = text.to_owned();
+                Ok(message.clone())
+            }
+            None => Err(not_found(10008, "Unknown Message")),
+        }
+    }
+
+    fn send_embed(&self, channel: ChannelId, text: &str, embed: &Embed) -> Result<Message> {
+        self.record(Action::SendEmbed { channel,
+                                        text: text.to_owned(),
+                                        embed: embed.clone(), });
+        Ok(self.post(channel, text))
+    }
+
+    fn edit_embed(&self, channel: ChannelId, message: MessageId, embed: &Embed) -> Result<Message> {
+        self.record(Action::EditEmbed { channel,
+                                        message,
+                                        embed: embed.clone(), });
+        self.get_message(channel, message)
+    }
+
+    fn send_file(&self,
+                 channel: ChannelId,
+                 text: &str,
+                 file: &mut Read,
+                 filename: &str)
+                 -> Result<Message> {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        self.record(Action::SendFile { channel,
+                                       text: text.to_owned(),
+                                       filename: filename.to_owned(),
+                                       contents, });
+        Ok(self.post(channel, text))
+    }
+
+    fn get_message(&self, channel: ChannelId, message: MessageId) -> Result<Message> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.channel_id == channel && x.id == message)
+            .cloned()
+            .ok_or_else(|| not_found(10008, "Unknown Message"))
+    }
+
+    fn get_messages(&self,
+                    channel: ChannelId,
+                    what: GetMessages,
+                    limit: Option<u64>)
+                    -> Result<Vec<Message>> {
+        let limit = limit.unwrap_or(50) as usize;
+
+        // Newest first, like Discord does it.
+        let messages = self.messages
+                           .lock()
+                           .unwrap()
+                           .iter()
+                           .rev()
+                           .filter(|x| x.channel_id == channel)
+                           .cloned()
+                           .collect::<Vec<_>>();
+
+        Ok(match what {
+               GetMessages::MostRecent => messages.into_iter().take(limit).collect(),
+               GetMessages::Before(id) => {
+                   messages.into_iter().filter(|x| x.id < id).take(limit).collect()
+               }
+               GetMessages::After(id) => {
+                   let mut newer = messages.into_iter()
+                                           .filter(|x| x.id > id)
+                                           .collect::<Vec<_>>();
+                   let skip = newer.len().saturating_sub(limit);
+                   newer.drain(..skip);
+                   newer
+               }
+               GetMessages::Around(id) => {
+                   let position = messages.iter().position(|x| x.id <= id).unwrap_or(0);
+                   let start = position.saturating_sub(limit / 2);
+                   messages.into_iter().skip(start).take(limit).collect()
+               }
+           })
+    }
+
+    fn delete_messages(&self, channel: ChannelId, ids: &[MessageId]) -> Result<()> {
+        self.record(Action::DeleteMessages { channel,
+                                             messages: ids.to_vec(), });
+        self.messages
+            .lock()
+            .unwrap()
+            .retain(|x| x.channel_id != channel || !ids.contains(&x.id));
+        Ok(())
+    }
+
+    fn delete_message(&self, channel: ChannelId, message: MessageId) -> Result<()> {
+        self.record(Action::DeleteMessage { channel, message });
+        self.messages
+            .lock()
+            .unwrap()
+            .retain(|x| x.channel_id != channel || x.id != message);
+        Ok(())
+    }
+
+    fn get_member(&self, server: ServerId, user: UserId) -> Result<Member> {
+        if server != SERVER_ID {
+            return Err(not_found(10004, "Unknown Guild"));
+        }
+
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.user.id == user)
+            .cloned()
+            .ok_or_else(|| not_found(10007, "Unknown Member"))
+    }
+
+    fn kick_member(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.record(Action::Kick { server, user });
+        Ok(())
+    }
+
+    fn add_ban(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.record(Action::Ban { server, user });
+        Ok(())
+    }
+
+    fn remove_ban(&self, server: ServerId, user: UserId) -> Result<()> {
+        self.record(Action::Unban { server, user });
+        Ok(())
+    }
+
+    fn edit_member_roles(&self, server: ServerId, user: UserId, roles: &[RoleId]) -> Result<()> {
+        self.record(Action::EditMemberRoles { server,
+                                              user,
+                                              roles: roles.to_vec(), });
+
+        if let Some(member) = self.members
+                                  .lock()
+                                  .unwrap()
+                                  .iter_mut()
+                                  .find(|x| x.user.id == user)
+        {
+            member.roles = roles.to_vec();
+        }
+
+        Ok(())
+    }
+
+    fn create_channel(&self, server: ServerId, name: &str, kind: ChannelType) -> Result<Channel> {
+        self.record(Action::CreateChannel { server,
+                                            name: name.to_owned(), });
+
+        let kind = match kind {
+            ChannelType::Voice => 2,
+            _ => 0,
+        };
+
+        let channel = json!({
+            "id": self.generate_id().to_string(),
+            "guild_id": server.0.to_string(),
+            "name": name,
+            "type": kind,
+            "position": 0,
+            "permission_overwrites": [],
+        });
+
+        Ok(Channel::Public(serde_json::from_value(channel).unwrap()))
+    }
+
+    fn create_permission(&self, channel: ChannelId, _target: PermissionOverwrite) -> Result<()> {
+        self.record(Action::CreatePermission { channel });
+        Ok(())
+    }
+
+    fn create_private_channel(&self, recipient: UserId) -> Result<PrivateChannel> {
+        let channel = json!({
+            "id": PRIVATE_CHANNEL_ID.0.to_string(),
+            "type": 1,
+            "recipients": [user_json(recipient, "Someone")],
+        });
+
+        Ok(serde_json::from_value(channel).unwrap())
+    }
+
+    fn broadcast_typing(&self, channel: ChannelId) -> Result<()> {
+        self.record(Action::BroadcastTyping { channel });
+        Ok(())
+    }
+
+    fn add_reaction(&self, channel: ChannelId, message: MessageId, _emoji: ReactionEmoji)
+                    -> Result<()> {
+        self.record(Action::AddReaction { channel, message });
+        Ok(())
+    }
+
+    fn delete_reaction(&self,
+                       channel: ChannelId,
+                       message: MessageId,
+                       user: Option<UserId>,
+                       _emoji: ReactionEmoji)
+                       -> Result<()> {
+        self.record(Action::DeleteReaction { channel,
+                                             message,
+                                             user, });
+        Ok(())
+    }
+}
+
+fn user_json(id: UserId, name: &str) -> Value {
+    json!({
+        "id": id.0.to_string(),
+        "username": name,
+        "discriminator": "0001",
+        "avatar": null,
+        "bot": id == BOT_ID,
+    })
+}
+
+pub fn user(id: UserId, name: &str) -> User {
+    serde_json::from_value(user_json(id, name)).unwrap()
+}
+
+pub fn bot_user() -> User {
+    user(BOT_ID, "yalter-bot")
+}
+
+pub fn owner() -> User {
+    user(OWNER_ID, "Owner")
+}
+
+pub fn someone() -> User {
+    user(USER_ID, "Someone")
+}
+
+fn member_json(user: Value, roles: &[RoleId]) -> Value {
+    json!({
+        "user": user,
+        "roles": roles.iter().map(|x| x.0.to_string()).collect::<Vec<_>>(),
+        "nick": null,
+        "joined_at": "2018-01-01T00:00:00+00:00",
+        "mute": false,
+        "deaf": false,
+    })
+}
+
+pub fn member(user: &User, roles: &[RoleId]) -> Member {
+    serde_json::from_value(member_json(user_json(user.id, &user.name), roles)).unwrap()
+}
+
+pub fn message(id: MessageId, channel: ChannelId, author: &User, content: &str) -> Message {
+    message_with_attachments(id, channel, author, content, &[])
+}
+
+// Attachments are given as (filename, url) pairs.
+pub fn message_with_attachments(id: MessageId,
+                                channel: ChannelId,
+                                author: &User,
+                                content: &str,
+                                attachments: &[(&str, &str)])
+                                -> Message {
+    // Mentions are picked out of the content like Discord does it.
+    let mentions = content.split(|c: char| c.is_whitespace())
+                          .filter(|x| x.starts_with("<@") && !x.starts_with("<@&"))
+                          .filter_map(|x| {
+                                          x.trim_left_matches("<@")
+                                           .trim_left_matches('!')
+                                           .trim_right_matches('>')
+                                           .parse::<u64>()
+                                           .ok()
+                                      })
+                          .map(|x| user_json(UserId(x), "Someone"))
+                          .collect::<Vec<_>>();
+
+    let mention_roles = content.split(|c: char| c.is_whitespace())
+                               .filter(|x| x.starts_with("<@&"))
+                               .map(|x| x.trim_left_matches("<@&").trim_right_matches('>'))
+                               .collect::<Vec<_>>();
+
+    let attachments = attachments.iter()
+                                 .enumerate()
+                                 .map(|(i, &(filename, url))| {
+                                          json!({
+                                              "id": (FIRST_GENERATED_ID - 1 - i).to_string(),
+                                              "filename": filename,
+                                              "url": url,
+                                              "proxy_url": url,
+                                              "size": 0,
+                                          })
+                                      })
+                                 .collect::<Vec<_>>();
+
+    let message = json!({
+        "id": id.0.to_string(),
+        "channel_id": channel.0.to_string(),
+        "content": content,
+        "nonce": null,
+        "tts": false,
+        // Recent enough for bulk deletion.
+        "timestamp": Utc::now().to_rfc3339(),
+        "edited_timestamp": null,
+        "pinned": false,
+        "type": 0,
+        "author": user_json(author.id, &author.name),
+        "mention_everyone": false,
+        "mentions": mentions,
+        "mention_roles": mention_roles,
+        "reactions": [],
+        "attachments": attachments,
+        "embeds": [],
+    });
+
+    serde_json::from_value(message).unwrap()
+}
+
+// A session with a single server, owned by OWNER_ID, with a single text channel.
+pub fn ready_event() -> ReadyEvent {
+    let server = json!({
+        "id": SERVER_ID.0.to_string(),
+        "name": "Test Server",
+        "owner_id": OWNER_ID.0.to_string(),
+        "icon": null,
+        "splash": null,
+        "region": "eu-central",
+        "afk_channel_id": null,
+        "afk_timeout": 300,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "mfa_level": 0,
+        "large": false,
+        "member_count": 3,
+        "joined_at": "2018-01-01T00:00:00+00:00",
+        "features": [],
+        "emojis": [],
+        "voice_states": [],
+        "presences": [],
+        "roles": [{
+            // @everyone, with Discord's default permissions.
+            "id": SERVER_ID.0.to_string(),
+            "name": "@everyone",
+            "color": 0,
+            "hoist": false,
+            "managed": false,
+            "position": 0,
+            "mentionable": false,
+            "permissions": 104324161,
+        }, {
+            "id": ROLE_ID.0.to_string(),
+            "name": "Moderators",
+            "color": 0,
+            "hoist": false,
+            "managed": false,
+            "position": 1,
+            "mentionable": true,
+            "permissions": 0,
+        }, {
+            "id": MUTED_ROLE_ID.0.to_string(),
+            "name": "Muted",
+            "color": 0,
+            "hoist": false,
+            "managed": false,
+            "position": 2,
+            "mentionable": false,
+            "permissions": 0,
+        }],
+        "members": [
+            member_json(user_json(BOT_ID, "yalter-bot"), &[]),
+            member_json(user_json(OWNER_ID, "Owner"), &[]),
+            member_json(user_json(USER_ID, "Someone"), &[]),
+        ],
+        "channels": [{
+            "id": CHANNEL_ID.0.to_string(),
+            "guild_id": SERVER_ID.0.to_string(),
+            "name": "general",
+            "type": 0,
+            "position": 0,
+            "topic": null,
+            "last_message_id": null,
+            "permission_overwrites": [],
+        }, {
+            "id": NO_EMBEDS_CHANNEL_ID.0.to_string(),
+            "guild_id": SERVER_ID.0.to_string(),
+            "name": "no-embeds",
+            "type": 0,
+            "position": 1,
+            "topic": null,
+            "last_message_id": null,
+            "permission_overwrites": [{
+                "id": SERVER_ID.0.to_string(),
+                "type": "role",
+                "allow": 0,
+                "deny": 16384,
+            }],
+        }, {
+            "id": LOG_CHANNEL_ID.0.to_string(),
+            "guild_id": SERVER_ID.0.to_string(),
+            "name": "mod-log",
+            "type": 0,
+            "position": 2,
+            "topic": null,
+            "last_message_id": null,
+            "permission_overwrites": [],
+        }],
+    });
+
+    let ready = json!({
+        "v": 6,
+        "session_id": "fake",
+        "user": {
+            "id": BOT_ID.0.to_string(),
+            "username": "yalter-bot",
+            "discriminator": "0001",
+            "avatar": null,
+            "email": null,
+            "verified": true,
+            "bot": true,
+            "mfa_enabled": false,
+        },
+        "private_channels": [],
+        "presences": [],
+        "relationships": [],
+        "guilds": [server],
+        "_trace": [],
+    });
+
+    serde_json::from_value(ready).unwrap()
+}
+
+// What Discord answers with when the thing isn't there.
+fn not_found(code: u64, message: &str) -> Error {
+    Error::Status(StatusCode::NotFound,
+                  Some(json!({ "code": code, "message": message })))
+}
+
+// A bot in the console's world, along with the console to look at what it did. Fails if the
+// storage couldn't be opened.
+pub fn bot_with(console: Console,
+                modules: Vec<Box<Module>>,
+                config: &Config)
+                -> Result<(Arc<Console>, Bot), String> {
+    let console = Arc::new(console);
+
+    for user in &[bot_user(), owner(), someone()] {
+        console.add_member(member(user, &[]));
+    }
+
+    let bot = Bot::new(console.clone(), ready_event(), modules, config)?;
+    Ok((console, bot))
+}
@@ -0,0 +1,152 @@
+use crashes::Recover;
+use discord::model::{ChannelId, ServerId, UserId};
+use std::{
+    collections::hash_map::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Who shares a cooldown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    User,
+    Channel,
+    Server,
+}
+
+pub struct Cooldown {
+    pub scope: Scope,
+    pub seconds: u64,
+}
+
+impl fmt::Display for Cooldown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scope = match self.scope {
+            Scope::User => "user",
+            Scope::Channel => "channel",
+            Scope::Server => "server",
+        };
+
+        write!(f, "{}s per {}", self.seconds, scope)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Bucket {
+    module: &'static str,
+    id: u32,
+    scope: Scope,
+    // The user, channel or server ID.
+    target: u64,
+}
+
+pub struct Cooldowns {
+    // Map from a bucket into the time its cooldown ends.
+    expires: Mutex<HashMap<Bucket, Instant>>,
+}
+
+impl Cooldowns {
+    pub fn new() -> Self {
+        Cooldowns { expires: Mutex::new(HashMap::new()) }
+    }
+
+    // Starts the cooldowns of a command unless one of them is already running,
+    // in which case returns how long is left until the command can be used again.
+    pub fn try_use(&self,
+                   module: &'static str,
+                   id: u32,
+                   cooldowns: &[Cooldown],
+                   user: UserId,
+                   channel: ChannelId,
+                   server: Option<ServerId>)
+                   -> Result<(), Duration> {
+        if cooldowns.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut expires = self.expires.lock().recover();
+
+        // Forget about the finished cooldowns so the map doesn't keep growing.
+        expires.retain(|_, x| *x > now);
+
+        let buckets = cooldowns.iter()
+                               .filter_map(|cooldown| {
+                                   let target = match cooldown.scope {
+                                       Scope::User => Some(user.0),
+                                       Scope::Channel => Some(channel.0),
+                                       // Private channels don't have a server.
+                                       Scope::Server => server.map(|x| x.0),
+                                   };
+
+                                   target.map(|target| {
+                                             (Bucket { module,
+                                                       id,
+                                                       scope: cooldown.scope,
+                                                       target, },
+                                              Duration::from_secs(cooldown.seconds))
+                                         })
+                               })
+                               .collect::<Vec<_>>();
+
+        if let Some(left) = buckets.iter()
+                                   .filter_map(|&(ref bucket, _)| expires.get(bucket))
+                                   .map(|&x| x - now)
+                                   .max()
+        {
+            return Err(left);
+        }
+
+        for (bucket, duration) in buckets {
+            expires.insert(bucket, now + duration);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COOLDOWNS: [Cooldown; 2] = [Cooldown { scope: Scope::User,
+                                                  seconds: 30, },
+                                       Cooldown { scope: Scope::Server,
+                                                  seconds: 10, }];
+
+    #[test]
+    fn per_user_and_server() {
+        let cooldowns = Cooldowns::new();
+        let server = Some(ServerId(1));
+
+        assert_eq!(Ok(()),
+                   cooldowns.try_use("Test", 0, &COOLDOWNS, UserId(1), ChannelId(1), server));
+
+        // The user bucket is the longest one.
+        let left = cooldowns.try_use("Test", 0, &COOLDOWNS, UserId(1), ChannelId(2), server)
+                            .unwrap_err();
+        assert!(left > Duration::from_secs(20));
+
+        // Someone else only hits the server bucket.
+        let left = cooldowns.try_use("Test", 0, &COOLDOWNS, UserId(2), ChannelId(1), server)
+                            .unwrap_err();
+        assert!(left <= Duration::from_secs(10));
+
+        // Other servers and commands are unaffected.
+        assert_eq!(Ok(()),
+                   cooldowns.try_use("Test", 0, &COOLDOWNS, UserId(2), ChannelId(3), Some(ServerId(2))));
+        assert_eq!(Ok(()),
+                   cooldowns.try_use("Test", 1, &COOLDOWNS, UserId(1), ChannelId(1), server));
+    }
+
+    #[test]
+    fn private_channels_skip_server_bucket() {
+        let cooldowns = Cooldowns::new();
+
+        assert_eq!(Ok(()),
+                   cooldowns.try_use("Test", 0, &COOLDOWNS[1..], UserId(1), ChannelId(1), None));
+        assert_eq!(Ok(()),
+                   cooldowns.try_use("Test", 0, &COOLDOWNS[1..], UserId(1), ChannelId(1), None));
+    }
+}
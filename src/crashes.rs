@@ -0,0 +1,218 @@
+use backtrace::Backtrace;
+use rand;
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::{hash_map::HashMap, hash_set::HashSet},
+    panic::{self, AssertUnwindSafe},
+    sync::{LockResult, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+// Modules which crash this many times within CRASH_WINDOW_SECS get disabled until restart.
+const CRASH_LIMIT: usize = 3;
+const CRASH_WINDOW_SECS: u64 = 10 * 60;
+
+thread_local! {
+    // Whether a panic on this thread is going to be caught by catch().
+    static CATCHING: Cell<bool> = Cell::new(false);
+    // The message and the backtrace of the last caught panic, left here by the panic hook.
+    static LAST_PANIC: RefCell<Option<(String, String)>> = RefCell::new(None);
+}
+
+// A caught panic.
+#[derive(Debug)]
+pub struct Crash {
+    // A short random ID to find the crash in the log by.
+    pub id: String,
+    pub message: String,
+    // Empty if the panic hook isn't installed.
+    pub backtrace: String,
+}
+
+pub struct Crashes {
+    // Map from a module name into the times of its recent crashes.
+    recent: Mutex<HashMap<&'static str, Vec<Instant>>>,
+    disabled: Mutex<HashSet<&'static str>>,
+}
+
+// For locks which might have been poisoned by a crashed handler. The crash gets reported on its
+// own, and the data behind the lock is still good to use.
+pub trait Recover<G> {
+    fn recover(self) -> G;
+}
+
+impl<G> Recover<G> for LockResult<G> {
+    fn recover(self) -> G {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Makes panics record a backtrace. Panics which aren't caught by catch() are logged right away.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let mut message = payload_message(info.payload());
+        if let Some(location) = info.location() {
+            message.push_str(&format!(" at {}:{}", location.file(), location.line()));
+        }
+
+        let backtrace = format!("{:?}", Backtrace::new());
+
+        if CATCHING.with(|x| x.get()) {
+            LAST_PANIC.with(|x| *x.borrow_mut() = Some((message, backtrace)));
+        } else {
+            error!("Panic: {}\n{}", message, backtrace);
+        }
+    }));
+}
+
+// Runs f, turning a panic into an error.
+pub fn catch<F: FnOnce() -> R, R>(f: F) -> Result<R, Crash> {
+    let was_catching = CATCHING.with(|x| x.replace(true));
+    // Whatever f was in the middle of changing is either behind a lock, which is recovered
+    // from, or thrown away.
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|x| x.set(was_catching));
+
+    result.map_err(|payload| {
+                       let (message, backtrace) =
+                           LAST_PANIC.with(|x| x.borrow_mut().take()).unwrap_or_else(|| {
+                               (payload_message(&*payload), String::new())
+                           });
+
+                       Crash { id: format!("{:08x}", rand::random::<u32>()),
+                               message,
+                               backtrace, }
+                   })
+}
+
+fn payload_message(payload: &(Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_owned()
+    }
+}
+
+impl Crashes {
+    pub fn new() -> Self {
+        Crashes { recent: Mutex::new(HashMap::new()),
+                  disabled: Mutex::new(HashSet::new()), }
+    }
+
+    // Returns true if the module crashed too many times and got disabled.
+    pub fn record(&self, module: &'static str) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(CRASH_WINDOW_SECS);
+
+        let mut recent = self.recent.lock().recover();
+        let crashes = recent.entry(module).or_insert_with(Vec::new);
+        crashes.retain(|&x| now.duration_since(x) < window);
+        crashes.push(now);
+
+        if crashes.len() < CRASH_LIMIT {
+            return false;
+        }
+
+        crashes.clear();
+        self.disabled.lock().recover().insert(module)
+    }
+
+    pub fn is_disabled(&self, module: &str) -> bool {
+        self.disabled.lock().recover().contains(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bot::Bot;
+    use config::ModuleConfig;
+    use discord::model::Message;
+    use fake;
+    use module::{self, Module as ModuleTrait};
+    use std::{collections::hash_map::HashMap, sync::RwLock};
+
+    struct Module {
+        commands: HashMap<u32, &'static [&'static str]>,
+        lock: RwLock<u32>,
+    }
+
+    impl module::Module for Module {
+        fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
+            static CRASH: [&'static str; 1] = ["crash"];
+            static COUNT: [&'static str; 1] = ["count"];
+            let mut map: HashMap<u32, &[&str]> = HashMap::new();
+            map.insert(0, &CRASH);
+            map.insert(1, &COUNT);
+
+            Ok(Box::new(Module { commands: map,
+                                 lock: RwLock::new(0), }))
+        }
+
+        fn name(&self) -> &'static str {
+            "Crashy"
+        }
+
+        fn description(&self) -> &'static str {
+            "Crashes."
+        }
+
+        fn commands(&self) -> &HashMap<u32, &[&str]> {
+            &self.commands
+        }
+
+        fn command_description(&self, _: u32) -> &'static str {
+            ""
+        }
+
+        fn command_help_message(&self, _: u32) -> &'static str {
+            ""
+        }
+
+        fn handle(&self, bot: &Bot, message: &Message, id: u32, _text: &str) {
+            let mut count = self.lock.write().recover();
+            *count += 1;
+
+            if id == 0 {
+                panic!("crashed");
+            }
+
+            bot.send(message.channel_id, &count.to_string());
+        }
+    }
+
+    #[test]
+    fn catch_returns_the_message() {
+        assert_eq!(5, catch(|| 5).unwrap());
+
+        let crash = catch(|| panic!("oh no")).unwrap_err();
+        assert_eq!("oh no", crash.message);
+        assert_eq!(8, crash.id.len());
+    }
+
+    #[test]
+    fn crashing_module() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!crash");
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].starts_with("Sorry, something went wrong. :( The error ID is `"));
+
+        // The poisoned lock still works.
+        fake::run(&fake, &bot, "!count");
+        assert_eq!(vec!["2"], fake.take_replies());
+
+        fake::run(&fake, &bot, "!crash");
+        fake::run(&fake, &bot, "!crash");
+        assert!(bot.is_module_disabled("Crashy"));
+        fake.take_replies();
+
+        fake::run(&fake, &bot, "!count");
+        assert_eq!(vec!["The Crashy module is turned off because it kept crashing."],
+                   fake.take_replies());
+    }
+}
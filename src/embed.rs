@@ -0,0 +1,207 @@
+// Discord's limits on embeds. Longer text is cut off and extra fields are left out.
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 2048;
+const MAX_FIELDS: usize = 25;
+const MAX_FIELD_NAME_LENGTH: usize = 256;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+// The "```\n" and "\n```" around a code block.
+const CODE_BLOCK_FENCES_LENGTH: usize = 8;
+const MAX_FOOTER_LENGTH: usize = 2048;
+// All of the text together.
+const MAX_TOTAL_LENGTH: usize = 6000;
+
+// A rich message, built up like Embed::new().title("Title").field("Name", "Value", true).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Embed {
+    pub title: Option<String>,
+    // Where the title links to.
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<u64>,
+    pub thumbnail: Option<String>,
+    pub image: Option<String>,
+    pub fields: Vec<Field>,
+    pub footer: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+    // Inline fields are shown side by side.
+    pub inline: bool,
+}
+
+impl Embed {
+    pub fn new() -> Self {
+        Embed::default()
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(truncate(title, MAX_TITLE_LENGTH));
+        self
+    }
+
+    pub fn url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_owned());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(truncate(description, MAX_DESCRIPTION_LENGTH));
+        self
+    }
+
+    pub fn color(mut self, color: u64) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn thumbnail(mut self, url: &str) -> Self {
+        self.thumbnail = Some(url.to_owned());
+        self
+    }
+
+    pub fn image(mut self, url: &str) -> Self {
+        self.image = Some(url.to_owned());
+        self
+    }
+
+    // Discord doesn't show fields with an empty name or value, so those get a dash.
+    pub fn field(mut self, name: &str, value: &str, inline: bool) -> Self {
+        let field = Field { name: truncate(or_dash(name), MAX_FIELD_NAME_LENGTH),
+                            value: truncate(or_dash(value), MAX_FIELD_VALUE_LENGTH),
+                            inline, };
+
+        let length = field.name.chars().count() + field.value.chars().count();
+        if self.fields.len() < MAX_FIELDS && self.length() + length <= MAX_TOTAL_LENGTH {
+            self.fields.push(field);
+        }
+
+        self
+    }
+
+    // A field with the value in a code block. The code is cut down rather than the whole value,
+    // so that the block stays closed.
+    pub fn code_field(self, name: &str, code: &str, inline: bool) -> Self {
+        let code = truncate(code, MAX_FIELD_VALUE_LENGTH - CODE_BLOCK_FENCES_LENGTH);
+        self.field(name, &format!("```\n{}\n```", code), inline)
+    }
+
+    pub fn footer(mut self, footer: &str) -> Self {
+        self.footer = Some(truncate(footer, MAX_FOOTER_LENGTH));
+        self
+    }
+
+    // The embed as a regular message, for where the bot can't send embeds.
+    pub fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+
+        if let Some(ref title) = self.title {
+            lines.push(format!("**{}**", title));
+        }
+        if let Some(ref url) = self.url {
+            // The angle brackets stop Discord from adding a preview.
+            lines.push(format!("<{}>", url));
+        }
+        if let Some(ref description) = self.description {
+            lines.push(description.clone());
+        }
+
+        for field in &self.fields {
+            if field.value.contains('\n') {
+                lines.push(format!("**{}**\n{}", field.name, field.value));
+            } else {
+                lines.push(format!("**{}**: {}", field.name, field.value));
+            }
+        }
+
+        if let Some(ref image) = self.image {
+            lines.push(image.clone());
+        }
+        if let Some(ref footer) = self.footer {
+            lines.push(format!("*{}*", footer));
+        }
+
+        lines.join("\n")
+    }
+
+    fn length(&self) -> usize {
+        let count = |x: &Option<String>| x.as_ref().map_or(0, |x| x.chars().count());
+
+        count(&self.title)
+        + count(&self.description)
+        + count(&self.footer)
+        + self.fields
+              .iter()
+              .map(|x| x.name.chars().count() + x.value.chars().count())
+              .sum::<usize>()
+    }
+}
+
+fn or_dash(text: &str) -> &str {
+    if text.trim().is_empty() {
+        "-"
+    } else {
+        text
+    }
+}
+
+// Cuts the text down to at most `limit` characters, ending it with an ellipsis if it was cut.
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_owned();
+    }
+
+    let mut text = text.chars().take(limit - 1).collect::<String>();
+    text.push('…');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let long = "a".repeat(300);
+        let embed = Embed::new().title(&long).field("", "value", true);
+
+        assert_eq!(MAX_TITLE_LENGTH, embed.title.as_ref().unwrap().chars().count());
+        assert!(embed.title.as_ref().unwrap().ends_with('…'));
+        assert_eq!("-", embed.fields[0].name);
+
+        let embed = (0..30).fold(Embed::new(), |embed, i| embed.field(&i.to_string(), "x", false));
+        assert_eq!(MAX_FIELDS, embed.fields.len());
+
+        let value = "b".repeat(MAX_FIELD_VALUE_LENGTH);
+        let embed = (0..10).fold(Embed::new(), |embed, _| embed.field("name", &value, false));
+        assert_eq!(5, embed.fields.len());
+    }
+
+    #[test]
+    fn code_field() {
+        let embed = Embed::new().code_field("Result", "42", false);
+        assert_eq!("```\n42\n```", embed.fields[0].value);
+
+        let embed = Embed::new().code_field("Result", &"c".repeat(2000), false);
+        let value = &embed.fields[0].value;
+        assert_eq!(MAX_FIELD_VALUE_LENGTH, value.chars().count());
+        assert!(value.starts_with("```\nccc"));
+        assert!(value.ends_with("…\n```"));
+    }
+
+    #[test]
+    fn to_text() {
+        let embed = Embed::new().title("Title")
+                                .url("https://example.com")
+                                .color(0xff0000)
+                                .thumbnail("https://example.com/thumbnail.png")
+                                .field("One", "1", true)
+                                .field("Many", "1\n2", false)
+                                .footer("Footer");
+
+        assert_eq!("**Title**\n<https://example.com>\n**One**: 1\n**Many**\n1\n2\n*Footer*",
+                   embed.to_text());
+    }
+}
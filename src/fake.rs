@@ -0,0 +1,88 @@
+// Helpers for testing the modules against the console's in-memory Discord.
+
+pub use console::Console as Fake;
+pub use console::*;
+
+use bot::Bot;
+use config::Config;
+use discord::model::{ChannelId, MessageId, User};
+use embed::Embed;
+use module::Module;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+impl Fake {
+    // Takes the recorded actions and returns the text of the sent messages.
+    pub fn take_replies(&self) -> Vec<String> {
+        self.take_actions()
+            .into_iter()
+            .filter_map(|action| match action {
+                            Action::Send { text, .. } => Some(text),
+                            _ => None,
+                        })
+            .collect()
+    }
+
+    // Takes the recorded actions and returns the sent embeds.
+    pub fn take_embeds(&self) -> Vec<Embed> {
+        self.take_actions()
+            .into_iter()
+            .filter_map(|action| match action {
+                            Action::SendEmbed { embed, .. } => Some(embed),
+                            _ => None,
+                        })
+            .collect()
+    }
+}
+
+// An empty directory which no other test uses.
+pub fn temp_dir() -> ::std::path::PathBuf {
+    use std::{env, fs, process};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = env::temp_dir().join(format!("yalter-bot-test-{}-{}",
+                                           process::id(),
+                                           COUNTER.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// A bot in a recording console, along with the console to inspect what it did.
+pub fn bot(modules: Vec<Box<Module>>) -> (Arc<Fake>, Arc<Bot>) {
+    // So that the tests don't see each other's storage.
+    let mut config = Config::default();
+    config.data_dir = temp_dir();
+    config.owners = vec![OWNER_ID.0];
+
+    let (fake, bot) = bot_with(Fake::recording(), modules, &config).unwrap();
+    (fake, Arc::new(bot))
+}
+
+// Handles the message the way the bot handles commands from Discord and waits for the command
+// to finish, as if the author sent it to the channel. Returns false if the message wasn't a
+// command of any of the modules or the command is turned off in the channel.
+pub fn run_as(fake: &Fake, bot: &Arc<Bot>, author: &User, channel: ChannelId, text: &str) -> bool {
+    let message = Arc::new(message(MessageId(fake.generate_id()), channel, author, text));
+    fake.add_message((*message).clone());
+
+    let server = bot.get_server_id(channel);
+    let prefix = bot.get_command_prefix(server);
+
+    let found = match ::parse_command(&message.content, &prefix, Some(BOT_ID)) {
+        Some((command, text)) => {
+            ::handle_command(bot.clone(), message.clone(), server, command, text, Vec::new())
+        }
+        None => false,
+    };
+
+    bot.get_workers().wait_idle();
+    found
+}
+
+// Runs the command as the server owner in the server channel.
+pub fn run(fake: &Fake, bot: &Arc<Bot>, text: &str) -> bool {
+    run_as(fake, bot, &owner(), CHANNEL_ID, text)
+}
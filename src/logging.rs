@@ -0,0 +1,285 @@
+use chrono::Local;
+use config::{Config, Logging};
+use crashes::Recover;
+use log::{self, LevelFilter, Log, Metadata, Record};
+use std::{
+    cmp,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+// The target of the chat messages the bot sees, which is turned on and off on its own.
+pub const MESSAGE_LOG_TARGET: &str = "messages";
+
+// Targets of the bot's own code start with this, it's left out in the log and the config.
+const CRATE_TARGET_PREFIX: &str = "yalter_bot::";
+
+const REDACTED: &str = "[redacted]";
+
+pub struct Logger {
+    level: LevelFilter,
+    // Levels of individual targets, longest targets first so that the most specific one wins.
+    targets: Vec<(String, LevelFilter)>,
+    message_log: bool,
+    secrets: Vec<String>,
+    file: Option<Mutex<LogFile>>,
+}
+
+// A log file which is moved to <path>.1 once it grows too big, <path>.1 to <path>.2 and so on.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    // Zero means never rotate.
+    max_size: u64,
+    // How many of the old files to keep.
+    max_files: usize,
+}
+
+// Sets up logging to stdout and the log file. Secrets from the config never make it to either.
+pub fn init(config: &Config) -> Result<(), String> {
+    let logger = Logger::new(&config.logging, &config.data_dir, config.secrets())?;
+
+    log::set_max_level(logger.max_level());
+    log::set_boxed_logger(Box::new(logger)).map_err(|err| err.to_string())
+}
+
+impl Logger {
+    pub fn new(config: &Logging, data_dir: &Path, secrets: Vec<String>) -> Result<Self, String> {
+        let mut targets = Vec::new();
+        for (target, level) in &config.targets {
+            targets.push((target.clone(), parse_level(level)?));
+        }
+        targets.sort_by_key(|x| cmp::Reverse(x.0.len()));
+
+        let file = if config.file.is_empty() {
+            None
+        } else {
+            let path = data_dir.join(&config.file);
+            let file =
+                LogFile::open(path, config.max_file_size, config.max_files).map_err(|err| {
+                    format!("Couldn't open the log file: {}", err)
+                })?;
+
+            Some(Mutex::new(file))
+        };
+
+        Ok(Logger { level: parse_level(&config.level)?,
+                    targets,
+                    message_log: config.message_log,
+                    // Replacing empty strings would garble every line.
+                    secrets: secrets.into_iter().filter(|x| !x.is_empty()).collect(),
+                    file, })
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        let max = self.targets
+                      .iter()
+                      .map(|&(_, level)| level)
+                      .fold(self.level, cmp::max);
+
+        if self.message_log {
+            cmp::max(max, LevelFilter::Info)
+        } else {
+            max
+        }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = short_target(target);
+
+        self.targets
+            .iter()
+            .find(|x| {
+                      target == x.0
+                      || (target.starts_with(x.0.as_str()) && target[x.0.len()..].starts_with("::"))
+                  })
+            .map_or(self.level, |x| x.1)
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut line = format!("{} {:<5} [{}] {}",
+                               Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                               record.level(),
+                               short_target(record.target()),
+                               record.args());
+
+        for secret in &self.secrets {
+            if line.contains(secret.as_str()) {
+                line = line.replace(secret.as_str(), REDACTED);
+            }
+        }
+
+        line
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target() == MESSAGE_LOG_TARGET {
+            return self.message_log;
+        }
+
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(record);
+        println!("{}", line);
+
+        if let Some(ref file) = self.file {
+            if let Err(err) = file.lock().recover().write_line(&line) {
+                println!("Couldn't write to the log file: {}", err);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            let _ = file.lock().recover().file.flush();
+        }
+    }
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile { path,
+                     file,
+                     size,
+                     max_size,
+                     max_files, })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+
+        if self.max_size > 0 && self.size > 0 && self.size + length > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            for i in (1..self.max_files).rev() {
+                match fs::rename(self.numbered(i), self.numbered(i + 1)) {
+                    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+                    x => x?,
+                }
+            }
+
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn numbered(&self, i: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", i));
+        path.into()
+    }
+}
+
+fn short_target(target: &str) -> &str {
+    target.trim_left_matches(CRATE_TARGET_PREFIX)
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| {
+                                             format!("Invalid log level `{}`, must be one of \
+                                                      off, error, warn, info, debug or trace",
+                                                     level)
+                                         })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::temp_dir;
+    use log::Level;
+    use std::collections::BTreeMap;
+
+    fn logger(config: Logging) -> Logger {
+        Logger::new(&Logging { file: String::new(),
+                               ..config },
+                    Path::new("."),
+                    vec!["hunter2".to_owned(), String::new()]).unwrap()
+    }
+
+    fn enabled(logger: &Logger, level: Level, target: &str) -> bool {
+        logger.enabled(&Metadata::builder().level(level).target(target).build())
+    }
+
+    #[test]
+    fn levels() {
+        let mut targets = BTreeMap::new();
+        targets.insert("modules".to_owned(), "info".to_owned());
+        targets.insert("modules::fun".to_owned(), "debug".to_owned());
+
+        let logger = logger(Logging { level: "warn".to_owned(),
+                                      targets,
+                                      message_log: false,
+                                      ..Logging::default() });
+
+        assert!(enabled(&logger, Level::Warn, "yalter_bot::bot"));
+        assert!(!enabled(&logger, Level::Info, "yalter_bot::bot"));
+        assert!(enabled(&logger, Level::Info, "yalter_bot::modules::admin"));
+        assert!(enabled(&logger, Level::Debug, "yalter_bot::modules::fun"));
+        assert!(!enabled(&logger, Level::Debug, "yalter_bot::modules::funny"));
+        assert!(!enabled(&logger, Level::Info, MESSAGE_LOG_TARGET));
+        assert_eq!(LevelFilter::Debug, logger.max_level());
+
+        assert!(Logger::new(&Logging { level: "loud".to_owned(),
+                                       ..Logging::default() },
+                            Path::new("."),
+                            Vec::new()).is_err());
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let logger = logger(Logging::default());
+
+        let line = logger.format(&Record::builder().args(format_args!("url?appid=hunter2&x"))
+                                                   .level(Level::Debug)
+                                                   .target("yalter_bot::modules::wolframalpha")
+                                                   .build());
+        assert!(line.ends_with(" DEBUG [modules::wolframalpha] url?appid=[redacted]&x"));
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = temp_dir();
+        let path = dir.join("logs/bot.log");
+        let mut file = LogFile::open(path.clone(), 10, 2).unwrap();
+
+        for line in &["first", "second", "third", "fourth"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!("fourth\n", read(&path));
+        assert_eq!("third\n", read(&dir.join("logs/bot.log.1")));
+        assert_eq!("second\n", read(&dir.join("logs/bot.log.2")));
+        assert!(!dir.join("logs/bot.log.3").exists());
+    }
+}
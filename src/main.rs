@@ -1,53 +1,133 @@
-extern crate circular_queue;
+extern crate backtrace;
+extern crate chrono;
+extern crate ctrlc;
 #[macro_use]
 extern crate failure;
 extern crate hldemo;
 extern crate hyper;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 extern crate rand;
 extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate serde_xml_rs;
+extern crate toml;
 extern crate url;
 
-use std::{env, sync::Arc, thread};
+use std::{
+    env,
+    io::{self, BufRead},
+    mem,
+    path::PathBuf,
+    process::{self, Command},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
 extern crate discord;
 use discord::{
-    model::{ChannelId, Event, Message, MessageId},
-    ChannelRef, Discord,
+    model::{
+        ChannelId, Event, LiveServer, Member, Message, MessageId, PossibleServer, Reaction,
+        ServerId, User, UserId,
+    },
+    ChannelRef, Discord, State,
 };
 
+mod args;
+
 mod module;
 use module::Module;
 
 mod bot;
-use bot::{Bot, BotThreadUnsafe};
+use bot::{Bot, BotThreadUnsafe, Shutdown};
+
+mod config;
+use config::Config;
+
+mod cooldowns;
+
+mod console;
+use console::Console;
+
+mod crashes;
+use crashes::Recover;
+
+mod embed;
+
+#[cfg(test)]
+mod fake;
+
+mod logging;
+use logging::MESSAGE_LOG_TARGET;
+
+mod pages;
+
+mod pool;
+use pool::Busy;
+
+mod queue;
+
+mod replies;
+use replies::Reply;
+
+mod storage;
+
+mod transport;
 
 mod modules {
     pub mod admin;
+    pub mod automod;
     pub mod demos;
     pub mod fun;
     pub mod hello;
     pub mod invite;
     pub mod modules;
+    pub mod owner;
     pub mod speedruncom;
     pub mod wolframalpha;
 }
 
-fn parse_command(message: &str) -> Option<(&str, &str)> {
-    // Commands must start with a ! and are at least one symbol long
-    // (excluding the exclamation mark).
-    if !message.starts_with('!') || message.len() == 1 {
+// How many jobs of each event kind can be queued or running at once.
+const EVENT_JOB_LIMIT: usize = 8;
+
+// How often the modules' on_tick() gets called.
+const TICK_SECS: u64 = 10;
+
+// How often the main loop checks for a shutdown request while there are no events.
+const SHUTDOWN_POLL_MILLIS: u64 = 250;
+
+// The gateway thread can only close the connection once the next event wakes it up. If none
+// arrives within this long after the modules have shut down, the bot stops without closing it.
+const SHUTDOWN_WAKE_TIMEOUT_SECS: u64 = 30;
+
+fn parse_command<'a>(message: &'a str,
+                     prefix: &str,
+                     bot_id: Option<UserId>)
+                     -> Option<(&'a str, &'a str)> {
+    // Commands must start either with the prefix or with a mention of the bot.
+    let message = if let Some(rest) = bot_id.and_then(|id| strip_mention(message, id)) {
+        rest
+    } else if !prefix.is_empty() && message.starts_with(prefix) {
+        // Chop off the prefix.
+        message.split_at(prefix.len()).1
+    } else {
         return None;
-    }
+    };
 
-    // Chop off the !.
-    let message = message.split_at(1).1;
+    // Commands are at least one symbol long (excluding the prefix).
+    if message.is_empty() {
+        return None;
+    }
 
     // Separate the command from the arguments.
     match message.find(char::is_whitespace) {
@@ -57,7 +137,7 @@ fn parse_command(message: &str) -> Option<(&str, &str)> {
                 return None;
             }
 
-            // a is the command excluding the !, b is the rest of the message.
+            // a is the command excluding the prefix, b is the rest of the message.
             let (a, b) = message.split_at(pos);
 
             // Chop off the first whitespace character.
@@ -79,152 +159,666 @@ fn parse_command(message: &str) -> Option<(&str, &str)> {
     }
 }
 
-fn handle_command(bot: Arc<Bot>, message: Arc<Message>, command: &str, text: &str) {
-    let command = command.to_lowercase();
+fn strip_mention(message: &str, id: UserId) -> Option<&str> {
+    // Mentions come in two forms, with and without the nickname marker.
+    for mention in &[format!("<@{}>", id.0), format!("<@!{}>", id.0)] {
+        if message.starts_with(mention.as_str()) {
+            return Some(message.split_at(mention.len()).1.trim_left());
+        }
+    }
 
-    let mut index = None;
+    None
+}
 
-    'outer: for i in 0..bot.get_modules().len() {
-        let module = &bot.get_modules()[i];
+fn run_command(bot: &Bot, module: &Module, message: &Message, id: u32, text: &str) {
+    match module.command_arguments(id) {
+        Some(schema) => match args::parse(schema, text) {
+            Ok(arguments) => module.handle_with_arguments(bot, message, id, &arguments),
+            Err(err) => {
+                bot.send(message.channel_id,
+                         &format!("{}\n{}", err, module.command_help_message(id)));
+            }
+        },
+
+        None => module.handle(bot, message, id, text),
+    }
+}
 
+// The index of the module and the ID of the command, unless the command is turned off in the
+// channel.
+fn find_command(bot: &Bot,
+                server_id: Option<ServerId>,
+                channel_id: ChannelId,
+                command: &str)
+                -> Option<(usize, u32)> {
+    let command = command.to_lowercase();
+
+    for (i, module) in bot.get_modules().iter().enumerate() {
         for (&id, &cmds) in module.commands() {
             if cmds.iter().any(|&x| x == command) {
-                index = Some((i, id));
-                break 'outer;
+                if bot.is_command_turned_off(server_id, channel_id, &**module, id) {
+                    return None;
+                }
+
+                return Some((i, id));
             }
         }
     }
 
-    if let Some((i, id)) = index {
+    None
+}
+
+// Starts the cooldowns of the command. If one of them is still running, tells the author to slow
+// down and returns false. Admins aren't affected by the cooldowns, so they don't start them either.
+fn use_cooldowns(bot: &Bot,
+                 module: &Module,
+                 message: &Message,
+                 id: u32,
+                 server_id: Option<ServerId>)
+                 -> bool {
+    let cooldowns = module.command_cooldowns(id);
+
+    // Checking for admins might need a request, so it's left for the commands with cooldowns.
+    if cooldowns.is_empty() || server_id.map_or(false, |x| bot.is_admin(x, message.author.id)) {
+        return true;
+    }
+
+    match bot.get_cooldowns().try_use(module.name(),
+                                      id,
+                                      cooldowns,
+                                      message.author.id,
+                                      message.channel_id,
+                                      server_id)
+    {
+        Ok(()) => true,
+        Err(left) => {
+            bot.send(message.channel_id,
+                     &format!("Slow down! You can use this command again in {}.",
+                              args::format_duration(left)));
+            false
+        }
+    }
+}
+
+// The earlier replies are from before the command was edited, they get reused or deleted.
+// Returns false if there's no such command or it's turned off in the channel.
+fn handle_command(bot: Arc<Bot>,
+                  message: Arc<Message>,
+                  server_id: Option<ServerId>,
+                  command: &str,
+                  text: &str,
+                  earlier: Vec<Reply>)
+                  -> bool {
+    if let Some((i, id)) = find_command(&bot, server_id, message.channel_id, command) {
+        let module = &bot.get_modules()[i];
+        let channel_id = message.channel_id;
+
+        // Whichever way it goes, the reply takes the place of the earlier ones of an edited
+        // command, and the command stays tracked for further edits.
+        if bot.get_shutdown().is_some() {
+            bot.track_replies(&message, earlier, || {
+                                  bot.send(channel_id, "I'm shutting down, try again in a bit.")
+                              });
+            return true;
+        }
+
+        // Shared with the job so that they're still around if it doesn't get to run.
+        let earlier = Arc::new(Mutex::new(earlier));
+
         let text_copy = text.to_string();
+        let job_message = message.clone();
+        let job_earlier = earlier.clone();
+        let job_bot = bot.clone();
+        let timeout_bot = bot.clone();
+
+        let result = bot.get_workers().execute(
+            module.name(),
+            module.max_concurrent_handlers(),
+            move || {
+                let module = &*job_bot.get_modules()[i];
+                let earlier = mem::replace(&mut *job_earlier.lock().recover(), Vec::new());
+
+                job_bot.track_replies(&job_message, earlier, || {
+                           if !use_cooldowns(&job_bot, module, &job_message, id, server_id) {
+                               return;
+                           }
+
+                           job_bot.run_isolated(module, Some(channel_id), || {
+                                      run_command(&job_bot, module, &job_message, id, &text_copy)
+                                  })
+                       });
+            },
+            move || {
+                timeout_bot.send(channel_id, "Sorry, that's taking way too long. :( I'll stop waiting.");
+            },
+        );
+
+        let busy = match result {
+            Ok(()) => return true,
+            Err(Busy::Limit) => format!("I'm already running too many `{}` commands, try again \
+                                         in a bit.",
+                                        module.name()),
+            Err(Busy::Queue) => "I'm busy right now, try again in a bit.".to_owned(),
+        };
+
+        let earlier = mem::replace(&mut *earlier.lock().recover(), Vec::new());
+        bot.track_replies(&message, earlier, || bot.send(channel_id, &busy));
+        true
+    } else {
+        if !earlier.is_empty() {
+            // Edited into something that isn't a command anymore.
+            delete_replies(bot, message.channel_id, earlier);
+        }
+        false
+    }
+}
+
+// Runs the edited command again if it was a recent one.
+fn handle_command_edit(bot: Arc<Bot>, channel_id: ChannelId, id: MessageId, content: &str) {
+    let (message, earlier) = match bot.take_edited_command(channel_id, id, content) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let (server_id, bot_id) = {
+        let state = bot.get_state().read().recover();
+        let server_id = match state.find_channel(channel_id) {
+            Some(ChannelRef::Public(server, _)) => Some(server.id),
+            _ => None,
+        };
+        (server_id, state.user().id)
+    };
+
+    let prefix = bot.get_command_prefix(server_id);
+    let message = Arc::new(message);
+
+    match parse_command(&message.content, &prefix, Some(bot_id)) {
+        Some((command, text)) => {
+            handle_command(bot.clone(), message.clone(), server_id, command, text, earlier);
+        }
+        None => delete_replies(bot, channel_id, earlier),
+    }
+}
+
+fn delete_replies(bot: Arc<Bot>, channel_id: ChannelId, replies: Vec<Reply>) {
+    dispatch_event(bot, "reply delete", move |bot| {
+        bot.delete_replies(channel_id, &replies);
+    });
+}
+
+// Event handlers of all modules run as a single job.
+fn dispatch_event<F>(bot: Arc<Bot>, name: &'static str, f: F)
+    where F: FnOnce(&Bot) + Send + 'static
+{
+    let job_bot = bot.clone();
+
+    let result = bot.get_workers().execute(name,
+                                           EVENT_JOB_LIMIT,
+                                           move || f(&job_bot),
+                                           move || warn!("The {} handlers timed out.", name));
 
-        thread::spawn(move || {
-                          bot.get_modules()[i].handle(&bot, &message, id, &text_copy);
-                      });
+    if let Err(err) = result {
+        warn!("Dropped a {} event: {:?}.", name, err);
     }
 }
 
+fn handle_message(bot: Arc<Bot>, message: Arc<Message>) {
+    dispatch_event(bot, "message", move |bot| {
+        bot.for_each_module(|module| module.handle_message(bot, &message));
+    });
+}
+
 fn handle_attachment(bot: Arc<Bot>, message: Arc<Message>) {
-    thread::spawn(move || {
-                      for module in bot.get_modules() {
-                          module.handle_attachment(&bot, &message);
-                      }
-                  });
+    dispatch_event(bot, "attachment", move |bot| {
+        bot.for_each_module(|module| module.handle_attachment(bot, &message));
+    });
 }
 
-fn handle_message_update(bot: Arc<Bot>, channel_id: ChannelId, id: MessageId) {
-    thread::spawn(move || {
-                      for module in bot.get_modules() {
-                          module.handle_message_update(&bot, channel_id, id);
-                      }
-                  });
+fn handle_message_update(bot: Arc<Bot>, channel_id: ChannelId, id: MessageId, content: String) {
+    dispatch_event(bot, "message update", move |bot| {
+        bot.for_each_module(|module| module.handle_message_update(bot, channel_id, id, &content));
+    });
 }
 
 fn handle_message_delete(bot: Arc<Bot>, channel_id: ChannelId, id: MessageId) {
-    thread::spawn(move || {
-                      for module in bot.get_modules() {
-                          module.handle_message_delete(&bot, channel_id, id);
-                      }
-                  });
+    dispatch_event(bot, "message delete", move |bot| {
+        bot.delete_command_replies(channel_id, id);
+
+        bot.for_each_module(|module| module.handle_message_delete(bot, channel_id, id));
+    });
+}
+
+fn handle_reaction_add(bot: Arc<Bot>, reaction: Reaction) {
+    dispatch_event(bot, "reaction add", move |bot| {
+        bot.handle_pagination_reaction(&reaction);
+
+        bot.for_each_module(|module| module.handle_reaction_add(bot, &reaction));
+    });
+}
+
+fn handle_reaction_remove(bot: Arc<Bot>, reaction: Reaction) {
+    dispatch_event(bot, "reaction remove", move |bot| {
+        bot.for_each_module(|module| module.handle_reaction_remove(bot, &reaction));
+    });
+}
+
+fn handle_server_member_add(bot: Arc<Bot>, server: ServerId, member: Member) {
+    dispatch_event(bot, "server member add", move |bot| {
+        bot.for_each_module(|module| module.handle_server_member_add(bot, server, &member));
+    });
+}
+
+fn handle_server_member_remove(bot: Arc<Bot>, server: ServerId, user: User) {
+    dispatch_event(bot, "server member remove", move |bot| {
+        bot.for_each_module(|module| module.handle_server_member_remove(bot, server, &user));
+    });
+}
+
+fn handle_server_create(bot: Arc<Bot>, server: LiveServer) {
+    dispatch_event(bot, "server create", move |bot| {
+        bot.for_each_module(|module| module.handle_server_create(bot, &server));
+    });
+}
+
+fn handle_server_delete(bot: Arc<Bot>, server: ServerId) {
+    dispatch_event(bot, "server delete", move |bot| {
+        bot.for_each_module(|module| module.handle_server_delete(bot, server));
+    });
+}
+
+// Discord sends the same event when the bot is removed from a server and when the server goes
+// down in an outage. The state has already forgotten the server in the first case, but keeps it
+// as unavailable in the second.
+fn is_removed(state: &State, server: ServerId) -> bool {
+    !state.servers().iter().any(|x| x.id == server)
+    && !state.unavailable_servers().contains(&server)
+}
+
+fn handle_reconnect(bot: Arc<Bot>) {
+    dispatch_event(bot, "reconnect", move |bot| {
+        bot.for_each_module(|module| module.on_reconnect(bot));
+    });
+}
+
+// Runs commands typed into stdin as the owner of a made-up server, printing what the bot does.
+fn run_console(modules: Vec<Box<Module>>, config: &Config) {
+    let (world, bot) = match console::bot_with(Console::new(), modules, config) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    let bot = Arc::new(bot);
+    let owner = console::owner();
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.expect("Couldn't read from stdin");
+
+        let message = Arc::new(console::message(MessageId(world.generate_id()),
+                                                console::CHANNEL_ID,
+                                                &owner,
+                                                &line));
+        world.add_message((*message).clone());
+
+        let prefix = bot.get_command_prefix(Some(console::SERVER_ID));
+
+        if let Some((command, text)) =
+            parse_command(&message.content, &prefix, Some(console::BOT_ID))
+        {
+            handle_command(bot.clone(),
+                           message.clone(),
+                           Some(console::SERVER_ID),
+                           command,
+                           text,
+                           Vec::new());
+        }
+
+        // Finish the command before reading the next one so that the output stays in order.
+        bot.get_workers().wait_idle();
+
+        if bot.get_shutdown().is_some() {
+            break;
+        }
+    }
+
+    bot.shut_down();
+}
+
+// Makes the module unless it's disabled in the config. Errors are logged.
+fn load_module<M: Module>(config: &Config, name: &str) -> Option<Box<Module>> {
+    if !config.module_enabled(name) {
+        return None;
+    }
+
+    match M::new(&config.module(name)) {
+        Ok(module) => Some(module),
+        Err(err) => {
+            error!("{}", err);
+            None
+        }
+    }
 }
 
 fn main() {
+    let mut console = false;
+    let mut config_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--console" => console = true,
+            "--config" => {
+                config_path = Some(PathBuf::from(args.next()
+                                                     .expect("--config needs a path to the \
+                                                              config file")))
+            }
+            _ => {
+                println!("Usage: yalter-bot [--console] [--config <path>]");
+                process::exit(1);
+            }
+        }
+    }
+
+    let config = match Config::load(config_path.as_ref().map(PathBuf::as_path)) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = logging::init(&config) {
+        println!("{}", err);
+        process::exit(1);
+    }
+
+    crashes::install_hook();
+
+    let modules = vec![load_module::<modules::hello::Module>(&config, "hello"),
+                       load_module::<modules::modules::Module>(&config, "modules"),
+                       load_module::<modules::owner::Module>(&config, "owner"),
+                       load_module::<modules::fun::Module>(&config, "fun"),
+                       load_module::<modules::speedruncom::Module>(&config, "speedruncom"),
+                       load_module::<modules::admin::Module>(&config, "admin"),
+                       load_module::<modules::automod::Module>(&config, "automod"),
+                       load_module::<modules::wolframalpha::Module>(&config, "wolframalpha"),
+                       load_module::<modules::invite::Module>(&config, "invite"),
+                       load_module::<modules::demos::Module>(&config, "demos"),].into_iter()
+                                                                              .filter_map(|m| m)
+                                                                              .collect();
+
+    if console {
+        run_console(modules, &config);
+        return;
+    }
+
     // Read the token.
-    let token =
-        env::var("YALTER_BOT_TOKEN").expect("Please set the YALTER_BOT_TOKEN environment variable");
+    let token = config.token
+                      .as_ref()
+                      .expect("Please set the token in the config file or the YALTER_BOT_TOKEN \
+                               environment variable");
 
     // Log in to the API.
-    let discord = Discord::from_bot_token(&token).expect("Login failed");
-
-    let modules = vec![modules::hello::Module::new(),
-                       modules::modules::Module::new(),
-                       modules::fun::Module::new(),
-                       modules::speedruncom::Module::new(),
-                       modules::admin::Module::new(),
-                       modules::wolframalpha::Module::new(),
-                       modules::invite::Module::new(),
-                       modules::demos::Module::new(),].into_iter()
-                                                      .filter_map(|m| match m {
-                                                                      Ok(m) => Some(m),
-                                                                      Err(err) => {
-                                                                          println!("{}", err);
-                                                                          None
-                                                                      }
-                                                                  })
-                                                      .collect();
-
-    let mut bot = BotThreadUnsafe::new(discord, modules);
+    let discord = Discord::from_bot_token(token).expect("Login failed");
+
+    let mut bot = match BotThreadUnsafe::new(discord, modules, &config) {
+        Ok(bot) => bot,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    let sync = bot.get_sync().clone();
+
+    let signal_bot = sync.clone();
+    ctrlc::set_handler(move || {
+                           info!("Got a signal, shutting down.");
+                           signal_bot.request_shutdown(Shutdown::Exit);
+                       }).expect("Couldn't set the signal handler");
+
+    // Ticks run one after another on their own thread, so a slow one delays the next instead of
+    // overlapping with it.
+    let tick_bot = sync.clone();
+    thread::spawn(move || loop {
+                      thread::sleep(Duration::from_secs(TICK_SECS));
+                      if tick_bot.get_shutdown().is_some() {
+                          break;
+                      }
+
+                      tick_bot.for_each_module(|module| module.on_tick(&tick_bot));
+                  });
+
+    // Receiving blocks until the next event, so it happens on its own thread and the main loop
+    // can notice a shutdown request right away. The gateway thread closes the connection once it
+    // stops.
+    let (event_sender, events) = mpsc::channel();
+    let (closed_sender, closed) = mpsc::channel();
+    let gateway_bot = sync.clone();
+    thread::spawn(move || {
+        while let Some(event) = bot.receive_event() {
+            if gateway_bot.get_shutdown().is_some() || event_sender.send(event).is_err() {
+                break;
+            }
+        }
+
+        bot.close();
+        let _ = closed_sender.send(());
+    });
 
     // Main loop.
-    while let Some(event) = bot.receive_event() {
+    while sync.get_shutdown().is_none() {
+        let event = match events.recv_timeout(Duration::from_millis(SHUTDOWN_POLL_MILLIS)) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // The connection is gone for good.
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
         match event {
             Event::MessageCreate(message) => {
-                let state = bot.get_sync().get_state().read().unwrap();
+                // The gateway thread needs to write the state, and commands can read it again, so
+                // it's only held long enough to copy out what's needed here.
+                let (server_id, bot_id) = {
+                    let state = sync.get_state().read().recover();
+
+                    // Skip the message if it comes from us.
+                    if message.author.id == state.user().id {
+                        continue;
+                    }
 
-                // Skip the message if it comes from us.
-                if message.author.id == state.user().id {
-                    continue;
-                }
+                    let server_id = match state.find_channel(message.channel_id) {
+                        Some(ChannelRef::Public(server, channel)) => {
+                            info!(target: MESSAGE_LOG_TARGET,
+                                  "[`{}` `#{}`] `{}`: `{}`",
+                                  server.name,
+                                  channel.name,
+                                  message.author.name,
+                                  message.content);
+                            Some(server.id)
+                        }
 
-                match state.find_channel(message.channel_id) {
-                    Some(ChannelRef::Public(server, channel)) => {
-                        println!("[`{}` `#{}`] `{}`: `{}`",
-                                 server.name, channel.name, message.author.name, message.content);
-                    }
+                        Some(ChannelRef::Group(group)) => {
+                            info!(target: MESSAGE_LOG_TARGET,
+                                  "[Group `{}`] `{}`: `{}`",
+                                  group.name(),
+                                  message.author.name,
+                                  message.content);
+                            None
+                        }
 
-                    Some(ChannelRef::Group(group)) => {
-                        println!("[Group `{}`] `{}`: `{}`",
-                                 group.name(),
-                                 message.author.name,
-                                 message.content);
-                    }
+                        Some(ChannelRef::Private(channel)) => {
+                            if message.author.name == channel.recipient.name {
+                                info!(target: MESSAGE_LOG_TARGET,
+                                      "[Private] `{}`: `{}`",
+                                      message.author.name,
+                                      message.content);
+                            } else {
+                                info!(target: MESSAGE_LOG_TARGET,
+                                      "[Private] To `{}`: `{}`",
+                                      channel.recipient.name,
+                                      message.content);
+                            }
+                            None
+                        }
 
-                    Some(ChannelRef::Private(channel)) => {
-                        if message.author.name == channel.recipient.name {
-                            println!("[Private] `{}`: `{}`", message.author.name, message.content);
-                        } else {
-                            println!("[Private] To `{}`: `{}`",
-                                     channel.recipient.name, message.content);
+                        None => {
+                            info!(target: MESSAGE_LOG_TARGET,
+                                  "[Unknown Channel] `{}`: `{}`",
+                                  message.author.name,
+                                  message.content);
+                            None
                         }
-                    }
+                    };
 
-                    None => println!("[Unknown Channel] `{}`: `{}`",
-                                     message.author.name, message.content),
-                }
+                    (server_id, state.user().id)
+                };
+
+                let prefix = sync.get_command_prefix(server_id);
 
                 let message_shared = Arc::new(message);
 
+                handle_message(sync.clone(), message_shared.clone());
+
                 // Handle the commands.
-                if let Some((command, text)) = parse_command(&message_shared.content) {
-                    handle_command(bot.get_sync().clone(),
+                if let Some((command, text)) =
+                    parse_command(&message_shared.content, &prefix, Some(bot_id))
+                {
+                    handle_command(sync.clone(),
                                    message_shared.clone(),
+                                   server_id,
                                    command,
-                                   text);
+                                   text,
+                                   Vec::new());
                 }
 
                 // Handle the attachments.
                 if !message_shared.attachments.is_empty() {
-                    handle_attachment(bot.get_sync().clone(), message_shared);
+                    handle_attachment(sync.clone(), message_shared);
                 }
             }
 
-            Event::MessageUpdate { id, channel_id, .. } => {
-                handle_message_update(bot.get_sync().clone(), channel_id, id);
+            Event::MessageUpdate { id,
+                                   channel_id,
+                                   content, .. } => {
+                // Updates without content, like added embeds, don't change the text.
+                if let Some(content) = content {
+                    handle_command_edit(sync.clone(), channel_id, id, &content);
+                    handle_message_update(sync.clone(), channel_id, id, content);
+                }
             }
 
             Event::MessageDelete { channel_id,
                                    message_id, } => {
-                handle_message_delete(bot.get_sync().clone(), channel_id, message_id);
+                handle_message_delete(sync.clone(), channel_id, message_id);
+            }
+
+            Event::ReactionAdd(reaction) => {
+                handle_reaction_add(sync.clone(), reaction);
+            }
+
+            Event::ReactionRemove(reaction) => {
+                handle_reaction_remove(sync.clone(), reaction);
+            }
+
+            Event::ServerMemberAdd(server_id, member) => {
+                handle_server_member_add(sync.clone(), server_id, member);
+            }
+
+            Event::ServerMemberRemove(server_id, user) => {
+                handle_server_member_remove(sync.clone(), server_id, user);
+            }
+
+            Event::ServerCreate(PossibleServer::Online(server)) => {
+                handle_server_create(sync.clone(), server);
+            }
+
+            Event::ServerDelete(server) => {
+                let server_id = match server {
+                    PossibleServer::Online(server) => server.id,
+                    PossibleServer::Offline(server_id) => server_id,
+                };
+
+                if is_removed(&sync.get_state().read().recover(), server_id) {
+                    handle_server_delete(sync.clone(), server_id);
+                }
+            }
+
+            // The first Ready is taken by BotThreadUnsafe::new(), so this is a new session.
+            Event::Ready(_) => {
+                handle_reconnect(sync.clone());
             }
 
             _ => {} // Discard other events.
         }
     }
+
+    sync.shut_down();
+
+    // Dropping the receiving end makes the gateway thread stop on its next event.
+    drop(events);
+    if let Err(RecvTimeoutError::Timeout) =
+        closed.recv_timeout(Duration::from_secs(SHUTDOWN_WAKE_TIMEOUT_SECS))
+    {
+        warn!("No gateway event arrived in time, exiting without closing the connection.");
+    }
+
+    finish(sync.get_shutdown().unwrap_or(Shutdown::Exit));
+}
+
+fn finish(what: Shutdown) -> ! {
+    match what {
+        Shutdown::Exit => {
+            info!("Bye!");
+            process::exit(0);
+        }
+        Shutdown::Restart => restart(),
+    }
+}
+
+// Replaces the process with a fresh one with the same arguments.
+#[cfg(unix)]
+fn restart() -> ! {
+    use std::os::unix::process::CommandExt;
+
+    info!("Restarting.");
+    let err = Command::new(env::current_exe().expect("Couldn't find the executable"))
+        .args(env::args_os().skip(1))
+        .exec();
+
+    error!("Couldn't restart: {}", err);
+    process::exit(1);
+}
+
+#[cfg(not(unix))]
+fn restart() -> ! {
+    info!("Restarting.");
+    match Command::new(env::current_exe().expect("Couldn't find the executable"))
+        .args(env::args_os().skip(1))
+        .spawn()
+    {
+        Ok(_) => process::exit(0),
+        Err(err) => {
+            error!("Couldn't restart: {}", err);
+            process::exit(1);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse_command;
+    use super::{handle_command, is_removed, modules, Shutdown};
+    use config::ModuleConfig;
+    use crashes::Recover;
+    use discord::model::{MessageId, ServerId, UserId};
+    use fake::{self, Action};
+    use module::Module;
+    use std::sync::Arc;
+
+    fn parse_command(message: &str) -> Option<(&str, &str)> {
+        super::parse_command(message, "!", Some(UserId(1234)))
+    }
 
     #[test]
     fn parse_command_noargs() {
@@ -278,4 +872,80 @@ mod tests {
         assert_eq!(Some(("КрутаяКоманда1337💖忠犬ハ", "チ公Да")),
                    parse_command("!КрутаяКоманда1337💖忠犬ハ チ公Да"));
     }
+
+    #[test]
+    fn parse_command_custom_prefix() {
+        assert_eq!(Some(("wr", "Half-Life")),
+                   super::parse_command("?wr Half-Life", "?", None));
+        assert_eq!(None, super::parse_command("!wr Half-Life", "?", None));
+    }
+
+    #[test]
+    fn parse_command_long_prefix() {
+        assert_eq!(Some(("help", "")), super::parse_command("yb!help", "yb!", None));
+        assert_eq!(None, super::parse_command("yb!", "yb!", None));
+    }
+
+    #[test]
+    fn parse_command_mention() {
+        assert_eq!(Some(("wr", "Half-Life")), parse_command("<@1234> wr Half-Life"));
+        assert_eq!(Some(("wr", "Half-Life")), parse_command("<@!1234>  wr Half-Life"));
+    }
+
+    #[test]
+    fn parse_command_mention_only() {
+        assert_eq!(None, parse_command("<@1234>"));
+        assert_eq!(None, parse_command("<@1234>   "));
+    }
+
+    #[test]
+    fn parse_command_other_mention() {
+        assert_eq!(None, parse_command("<@4321> wr Half-Life"));
+    }
+
+    #[test]
+    fn removed_server() {
+        let (_, bot) = fake::bot(Vec::new());
+        let state = bot.get_state().read().recover();
+
+        assert!(!is_removed(&state, fake::SERVER_ID));
+        assert!(is_removed(&state, ServerId(99)));
+    }
+
+    #[test]
+    fn edited_command_while_shutting_down() {
+        let (fake, bot) =
+            fake::bot(vec![modules::hello::Module::new(&ModuleConfig::default()).unwrap()]);
+        let message = fake::message(MessageId(1), fake::CHANNEL_ID, &fake::owner(), "!hello");
+
+        let mut ids = Vec::new();
+        bot.track_replies(&message, Vec::new(), || {
+                              for text in &["one", "two"] {
+                                  ids.push(bot.send_and_get(fake::CHANNEL_ID, text).unwrap().id);
+                              }
+                          });
+        fake.take_actions();
+
+        bot.request_shutdown(Shutdown::Exit);
+        let (message, earlier) = bot.take_edited_command(fake::CHANNEL_ID, MessageId(1), "!hi")
+                                    .unwrap();
+        handle_command(bot.clone(),
+                       Arc::new(message),
+                       Some(fake::SERVER_ID),
+                       "hi",
+                       "",
+                       earlier);
+        assert_eq!(vec![Action::Edit { channel: fake::CHANNEL_ID,
+                                       message: ids[0],
+                                       text: "I'm shutting down, try again in a bit.".to_owned(), },
+                        Action::DeleteMessage { channel: fake::CHANNEL_ID,
+                                                message: ids[1], }],
+                   fake.take_actions());
+
+        // Still tracked.
+        bot.delete_command_replies(fake::CHANNEL_ID, MessageId(1));
+        assert_eq!(vec![Action::DeleteMessage { channel: fake::CHANNEL_ID,
+                                                message: ids[0], }],
+                   fake.take_actions());
+    }
 }
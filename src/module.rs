@@ -1,12 +1,18 @@
+use args::{Arguments, Schema};
 use bot::Bot;
-use discord::model::{ChannelId, Message, MessageId};
+use config::ModuleConfig;
+use cooldowns::Cooldown;
+use discord::model::{
+    ChannelId, LiveServer, Member, Message, MessageId, Reaction, ServerId, User, UserId,
+};
 use std::{
     collections::hash_map::HashMap,
     marker::{Send, Sync},
 };
 
 pub trait Module: Send + Sync {
-    fn new() -> Result<Box<Module>, String>
+    // Makes the module from its section of the config file.
+    fn new(config: &ModuleConfig) -> Result<Box<Module>, String>
         where Self: Sized;
 
     // Module name.
@@ -26,15 +32,105 @@ pub trait Module: Send + Sync {
     // A help message which describes how the command works.
     fn command_help_message(&self, id: u32) -> &str;
 
+    // Cooldowns of the given command. Admins aren't affected by them.
+    fn command_cooldowns(&self, _id: u32) -> &'static [Cooldown] {
+        &[]
+    }
+
+    // The arguments the command accepts, if it wants them parsed before handling.
+    fn command_arguments(&self, _id: u32) -> Option<&'static Schema> {
+        None
+    }
+
     // A function that handles the given command.
     fn handle(&self, bot: &Bot, message: &Message, id: u32, text: &str);
 
+    // A function that handles the given command with its arguments already parsed.
+    // Called instead of handle() for commands that have an argument schema.
+    fn handle_with_arguments(&self,
+                             _bot: &Bot,
+                             _message: &Message,
+                             id: u32,
+                             _arguments: &Arguments) {
+        warn!("{} has an argument schema for command {} but doesn't handle it.",
+              self.name(),
+              id);
+    }
+
+    // A function that gets called for every message someone sends, commands included. The bot's
+    // own messages don't count.
+    fn handle_message(&self, _bot: &Bot, _message: &Message) {}
+
     // A function that gets called when someone sends a message with an attachment.
     fn handle_attachment(&self, _bot: &Bot, _message: &Message) {}
 
-    // A function that gets called when someone edits a message.
-    fn handle_message_update(&self, _bot: &Bot, _channel_id: ChannelId, _id: MessageId) {}
+    // A function that gets called when someone edits the text of a message.
+    fn handle_message_update(&self,
+                             _bot: &Bot,
+                             _channel_id: ChannelId,
+                             _id: MessageId,
+                             _content: &str) {
+    }
 
     // A function that gets called when someone deletes a message.
     fn handle_message_delete(&self, _bot: &Bot, _channel_id: ChannelId, _id: MessageId) {}
+
+    // A function that gets called when someone adds a reaction to a message.
+    fn handle_reaction_add(&self, _bot: &Bot, _reaction: &Reaction) {}
+
+    // A function that gets called when someone removes a reaction from a message.
+    fn handle_reaction_remove(&self, _bot: &Bot, _reaction: &Reaction) {}
+
+    // A function that gets called when someone joins a server.
+    fn handle_server_member_add(&self, _bot: &Bot, _server: ServerId, _member: &Member) {}
+
+    // A function that gets called when someone leaves or gets kicked from a server.
+    fn handle_server_member_remove(&self, _bot: &Bot, _server: ServerId, _user: &User) {}
+
+    // A function that gets called when a server becomes available. This happens for every
+    // server right after connecting as well as when the bot is added to a new one.
+    fn handle_server_create(&self, _bot: &Bot, _server: &LiveServer) {}
+
+    // A function that gets called when the bot is removed from a server.
+    // Servers going unavailable during an outage don't count.
+    fn handle_server_delete(&self, _bot: &Bot, _server: ServerId) {}
+
+    // A function that gets called every few seconds, for things which have to happen at a
+    // certain time.
+    fn on_tick(&self, _bot: &Bot) {}
+
+    // A function that gets called when the bot had to start a new gateway session. The events
+    // from while it was disconnected are lost, so anything built from them should be refreshed.
+    fn on_reconnect(&self, _bot: &Bot) {}
+
+    // A function that gets called once before the bot exits or restarts, after the running
+    // handlers have finished or the grace period ran out.
+    fn on_shutdown(&self, _bot: &Bot) {}
+
+    // How many of this module's handlers can be queued or running at once.
+    fn max_concurrent_handlers(&self) -> usize {
+        4
+    }
+
+    // The command prefix set for the given server, if this module keeps track of one.
+    fn command_prefix(&self, _bot: &Bot, _server: ServerId) -> Option<String> {
+        None
+    }
+
+    // Whether this module considers the user an admin of the server.
+    fn is_admin(&self, _bot: &Bot, _server: ServerId, _user: UserId) -> bool {
+        false
+    }
+
+    // Whether this module has the command turned off in the channel. The command is given by its
+    // first name.
+    fn is_command_turned_off(&self,
+                             _bot: &Bot,
+                             _server: ServerId,
+                             _channel: ChannelId,
+                             _module: &str,
+                             _command: &str)
+                             -> bool {
+        false
+    }
 }
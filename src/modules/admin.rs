@@ -1,176 +1,286 @@
-use bot::Bot;
+use args::{self, Arg, Arguments, Arity, Flag, Kind, Schema};
+use bot::{Bot, DEFAULT_COMMAND_PREFIX};
+use chrono::{TimeZone, Utc};
+use config::ModuleConfig;
+use crashes::Recover;
 use discord::{model::*, *};
+use embed::Embed;
+use hyper::status::StatusCode;
 use module;
 use regex::Regex;
-use serde_json;
 use std::{
     self,
-    collections::{hash_map::HashMap, BTreeMap},
-    error, fmt,
-    fs::File,
-    io,
-    sync::{RwLock, RwLockReadGuard},
+    collections::{hash_map::HashMap, VecDeque},
+    mem,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Serialize, Deserialize)]
-struct Memory {
-    // The map is from ServerId into an array of RoleIds.
-    admin_roles: BTreeMap<String, Vec<u64>>,
-}
-
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
-    memory: RwLock<Memory>,
-}
-
-lazy_static! {
-    static ref NUKE_REGEX: Regex = Regex::new(r"\s*(([0-9]+)(\s|$)).*").unwrap();
-    static ref ADMIN_REGEX: Regex = Regex::new(r"\s*(list|add|remove)(\s|$).*").unwrap();
-}
-const MEMORY_FILENAME: &str = "memory.json";
-
-enum Commands {
-    Admin = 0,
-    Nuke = 1,
+    // Recent messages on servers with a mod log, to show what was edited or deleted.
+    recent: Mutex<HashMap<(ServerId, ChannelId), VecDeque<Message>>>,
 }
 
-impl Memory {
-    fn load_from_file() -> MyResult<Self> {
-        let file = File::open(MEMORY_FILENAME)?;
-        let mut memory: Memory = serde_json::de::from_reader(file)?;
+static ADMIN_ARGUMENTS: Schema = Schema { args: &[Arg { name: "action",
+                                                        kind: Kind::Text,
+                                                        arity: Arity::Required, },
+                                                  Arg { name: "roles",
+                                                        kind: Kind::Role,
+                                                        arity: Arity::Many, }],
+                                          flags: &[], };
 
-        let mut keys_to_remove = Vec::new();
-        for (server, roles) in &mut memory.admin_roles {
-            if roles.is_empty() {
-                keys_to_remove.push(server.clone());
-            } else {
-                roles.sort();
-                roles.dedup();
-            }
-        }
+static NUKE_ARGUMENTS: Schema = Schema { args: &[Arg { name: "amount",
+                                                       kind: Kind::Integer,
+                                                       arity: Arity::Required, },
+                                                 Arg { name: "whose",
+                                                       kind: Kind::User,
+                                                       arity: Arity::Many, }],
+                                         flags: &[Flag { name: "matching",
+                                                         kind: Some(Kind::Text), },
+                                                  Flag { name: "bots",
+                                                         kind: None, },
+                                                  Flag { name: "attachments",
+                                                         kind: None, },
+                                                  Flag { name: "links",
+                                                         kind: None, },
+                                                  Flag { name: "newer-than",
+                                                         kind: Some(Kind::Duration), },
+                                                  Flag { name: "after",
+                                                         kind: Some(Kind::Integer), },
+                                                  Flag { name: "dry-run",
+                                                         kind: None, }], };
 
-        for key in keys_to_remove {
-            memory.admin_roles.remove(&key);
-        }
+static SWITCH_ARGUMENTS: Schema = Schema { args: &[Arg { name: "module or command",
+                                                         kind: Kind::Text,
+                                                         arity: Arity::Required, },
+                                                   Arg { name: "channel",
+                                                         kind: Kind::Channel,
+                                                         arity: Arity::Optional, }],
+                                           flags: &[], };
 
-        Ok(memory)
-    }
+static KICK_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                       kind: Kind::User,
+                                                       arity: Arity::Required, },
+                                                 Arg { name: "reason",
+                                                       kind: Kind::Rest,
+                                                       arity: Arity::Optional, }],
+                                         flags: &[], };
 
-    fn save_to_file(&self) -> MyResult<()> {
-        let mut file = File::create(MEMORY_FILENAME)?;
-        serde_json::ser::to_writer(&mut file, &self)?;
+static BAN_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                      kind: Kind::User,
+                                                      arity: Arity::Required, },
+                                                Arg { name: "duration",
+                                                      kind: Kind::Duration,
+                                                      arity: Arity::Optional, },
+                                                Arg { name: "reason",
+                                                      kind: Kind::Rest,
+                                                      arity: Arity::Optional, }],
+                                        flags: &[], };
 
-        Ok(())
-    }
+static MUTE_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                       kind: Kind::User,
+                                                       arity: Arity::Required, },
+                                                 Arg { name: "duration",
+                                                       kind: Kind::Duration,
+                                                       arity: Arity::Required, },
+                                                 Arg { name: "reason",
+                                                       kind: Kind::Rest,
+                                                       arity: Arity::Optional, }],
+                                         flags: &[], };
 
-    pub fn get_admin_roles(&self, server: ServerId) -> Option<&Vec<u64>> {
-        self.admin_roles.get(&server.0.to_string())
-    }
+static USER_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                       kind: Kind::User,
+                                                       arity: Arity::Required, }],
+                                         flags: &[], };
 
-    pub fn remove_admin_roles(&mut self, server: ServerId, roles: &[RoleId]) {
-        let mut remove = false;
+static WARN_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                       kind: Kind::User,
+                                                       arity: Arity::Required, },
+                                                 Arg { name: "reason",
+                                                       kind: Kind::Rest,
+                                                       arity: Arity::Required, }],
+                                         flags: &[], };
 
-        if let Some(server_admin_roles) = self.admin_roles.get_mut(&server.0.to_string()) {
-            server_admin_roles.retain(|x| roles.iter().find(|r| r.0 == *x).is_none());
+static CLEARWARN_ARGUMENTS: Schema = Schema { args: &[Arg { name: "user",
+                                                            kind: Kind::User,
+                                                            arity: Arity::Required, },
+                                                      Arg { name: "number",
+                                                            kind: Kind::Integer,
+                                                            arity: Arity::Optional, }],
+                                              flags: &[], };
 
-            if server_admin_roles.is_empty() {
-                remove = true;
-            }
-        }
+static ESCALATION_ARGUMENTS: Schema = Schema { args: &[Arg { name: "warnings",
+                                                             kind: Kind::Integer,
+                                                             arity: Arity::Optional, },
+                                                       Arg { name: "action",
+                                                             kind: Kind::Text,
+                                                             arity: Arity::Optional, },
+                                                       Arg { name: "duration",
+                                                             kind: Kind::Duration,
+                                                             arity: Arity::Optional, }],
+                                               flags: &[], };
 
-        if remove {
-            self.admin_roles.remove(&server.0.to_string());
-        }
+static MOD_LOG_ARGUMENTS: Schema = Schema { args: &[Arg { name: "channel",
+                                                          kind: Kind::Channel,
+                                                          arity: Arity::Optional, },
+                                                    Arg { name: "off",
+                                                          kind: Kind::Text,
+                                                          arity: Arity::Optional, }],
+                                            flags: &[], };
 
-        if let Err(err) = self.save_to_file() {
-            println!("[CRITICAL] Could not save memory to file: {}", err);
-        }
-    }
+const STORAGE_NAMESPACE: &str = "admin";
+// An array of RoleIds.
+const ADMIN_ROLES_KEY: &str = "admin_roles";
+const PREFIX_KEY: &str = "prefix";
+// Switches for the whole server.
+const SWITCHES_KEY: &str = "switches";
+// Switches by channel ID.
+const CHANNEL_SWITCHES_KEY: &str = "channel_switches";
+// An array of TimedActions.
+const TIMED_ACTIONS_KEY: &str = "timed_actions";
 
-    pub fn add_admin_roles(&mut self, server: ServerId, roles: &[RoleId]) {
-        {
-            let server_admin_roles = self.admin_roles
-                                         .entry(server.0.to_string())
-                                         .or_insert_with(Vec::new);
+// Warnings by user ID.
+const WARNINGS_KEY: &str = "warnings";
+// An array of Escalations.
+const ESCALATION_KEY: &str = "escalation";
+// The channel ID of the mod log.
+const MOD_LOG_KEY: &str = "mod_log";
 
-            for role in roles {
-                server_admin_roles.push(role.0);
-            }
+// Muting someone gives them the role with this name, which the server sets up to not be able to
+// talk.
+const MUTED_ROLE_NAME: &str = "Muted";
+const MAX_PREFIX_LENGTH: usize = 8;
+// How many of the latest messages in every channel are kept for the mod log.
+const RECENT_MESSAGES_PER_CHANNEL: usize = 100;
+// !nuke gives up after looking through this many messages.
+const MAX_NUKE_SCANNED: usize = 5000;
+// Discord only bulk deletes messages younger than two weeks. A few minutes less, to be safe.
+const BULK_DELETE_MAX_AGE_SECS: u64 = 14 * 24 * 60 * 60 - 5 * 60;
+// How many of the latest warnings !warnings shows.
+const MAX_WARNINGS_SHOWN: usize = 20;
 
-            server_admin_roles.sort();
-            server_admin_roles.dedup();
-        }
+const ADMIN_ROLES_COLOR: u64 = 0xe67e22;
+const SWITCHES_COLOR: u64 = 0xe67e22;
+const WARNINGS_COLOR: u64 = 0xe67e22;
+const MOD_LOG_COLOR: u64 = 0x95a5a6;
 
-        if let Err(err) = self.save_to_file() {
-            println!("[CRITICAL] Could not save memory to file: {}", err);
-        }
-    }
+enum Commands {
+    Admin = 0,
+    Nuke = 1,
+    Prefix = 2,
+    Enable = 3,
+    Disable = 4,
+    Disabled = 5,
+    Kick = 6,
+    Ban = 7,
+    Mute = 8,
+    Unban = 9,
+    Unmute = 10,
+    Warn = 11,
+    Warnings = 12,
+    ClearWarn = 13,
+    Escalation = 14,
+    ModLog = 15,
 }
 
-#[derive(Debug)]
-enum MyError {
-    IO(io::Error),
-    Json(serde_json::error::Error),
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Timed {
+    Ban,
+    Mute,
 }
 
-impl fmt::Display for MyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            MyError::IO(ref err) => write!(f, "IO error: {}", err),
-            MyError::Json(ref err) => write!(f, "JSON error: {}", err),
-        }
-    }
+// A ban or a mute which gets lifted by on_tick() once its time is up.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct TimedAction {
+    what: Timed,
+    user: u64,
+    // Seconds since the Unix epoch.
+    until: u64,
 }
 
-impl error::Error for MyError {
-    fn description(&self) -> &str {
-        match *self {
-            MyError::IO(ref err) => err.description(),
-            MyError::Json(ref err) => err.description(),
-        }
-    }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Warning {
+    moderator: u64,
+    // Seconds since the Unix epoch.
+    time: u64,
+    reason: String,
+}
 
-    fn cause(&self) -> Option<&error::Error> {
-        match *self {
-            MyError::IO(ref err) => Some(err),
-            MyError::Json(ref err) => Some(err),
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Penalty {
+    Mute,
+    Kick,
+    Ban,
 }
 
-impl From<io::Error> for MyError {
-    fn from(err: io::Error) -> MyError {
-        MyError::IO(err)
-    }
+// What happens to someone once they have this many warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Escalation {
+    warnings: u64,
+    penalty: Penalty,
+    // Seconds. Mutes always have one, bans without one are for good.
+    #[serde(default)]
+    duration: Option<u64>,
 }
 
-impl From<serde_json::error::Error> for MyError {
-    fn from(err: serde_json::error::Error) -> MyError {
-        MyError::Json(err)
-    }
+// Modules and commands turned on or off, overriding the broader settings. Modules go by their
+// lowercase name and commands by their first name. Empty maps are left out when saving, so that
+// switches with nothing left in them get removed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Switches {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    modules: HashMap<String, bool>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    commands: HashMap<String, bool>,
 }
 
-type MyResult<T> = std::result::Result<T, MyError>;
+// What gets turned on or off.
+struct Target {
+    module: &'static str,
+    command: Option<String>,
+}
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> std::result::Result<Box<module::Module>, String> {
+    fn new(_config: &ModuleConfig) -> std::result::Result<Box<module::Module>, String> {
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         static ADMIN: [&'static str; 1] = ["admin"];
         map.insert(Commands::Admin as u32, &ADMIN);
         static NUKE: [&'static str; 1] = ["nuke"];
         map.insert(Commands::Nuke as u32, &NUKE);
-
-        let memory = match Memory::load_from_file() {
-            Ok(m) => m,
-
-            Err(err) => {
-                println!("[CRITICAL] Failed to load memory: {}", err);
-                Memory { admin_roles: BTreeMap::new(), }
-            }
-        };
+        static PREFIX: [&'static str; 1] = ["prefix"];
+        map.insert(Commands::Prefix as u32, &PREFIX);
+        static ENABLE: [&'static str; 1] = ["enable"];
+        map.insert(Commands::Enable as u32, &ENABLE);
+        static DISABLE: [&'static str; 1] = ["disable"];
+        map.insert(Commands::Disable as u32, &DISABLE);
+        static DISABLED: [&'static str; 1] = ["disabled"];
+        map.insert(Commands::Disabled as u32, &DISABLED);
+        static KICK: [&'static str; 1] = ["kick"];
+        map.insert(Commands::Kick as u32, &KICK);
+        static BAN: [&'static str; 1] = ["ban"];
+        map.insert(Commands::Ban as u32, &BAN);
+        static MUTE: [&'static str; 1] = ["mute"];
+        map.insert(Commands::Mute as u32, &MUTE);
+        static UNBAN: [&'static str; 1] = ["unban"];
+        map.insert(Commands::Unban as u32, &UNBAN);
+        static UNMUTE: [&'static str; 1] = ["unmute"];
+        map.insert(Commands::Unmute as u32, &UNMUTE);
+        static WARN: [&'static str; 1] = ["warn"];
+        map.insert(Commands::Warn as u32, &WARN);
+        static WARNINGS: [&'static str; 1] = ["warnings"];
+        map.insert(Commands::Warnings as u32, &WARNINGS);
+        static CLEARWARN: [&'static str; 1] = ["clearwarn"];
+        map.insert(Commands::ClearWarn as u32, &CLEARWARN);
+        static ESCALATION: [&'static str; 1] = ["escalation"];
+        map.insert(Commands::Escalation as u32, &ESCALATION);
+        static MOD_LOG: [&'static str; 1] = ["modlog"];
+        map.insert(Commands::ModLog as u32, &MOD_LOG);
 
         Ok(Box::new(Module { commands: map,
-                             memory: RwLock::new(memory), }))
+                             recent: Mutex::new(HashMap::new()), }))
     }
 
     fn name(&self) -> &'static str {
@@ -189,6 +299,26 @@ impl<'a> module::Module for Module<'a> {
         match id {
             x if x == Commands::Admin as u32 => "Manage the admin roles.",
             x if x == Commands::Nuke as u32 => "Deletes past messages.",
+            x if x == Commands::Prefix as u32 => "Changes the command prefix on this server.",
+            x if x == Commands::Enable as u32 => "Turns a module or a command back on.",
+            x if x == Commands::Disable as u32 => "Turns a module or a command off.",
+            x if x == Commands::Disabled as u32 => {
+                "Lists the modules and commands turned on or off on this server."
+            }
+            x if x == Commands::Kick as u32 => "Kicks someone from the server.",
+            x if x == Commands::Ban as u32 => "Bans someone, for good or for a while.",
+            x if x == Commands::Mute as u32 => "Stops someone from talking for a while.",
+            x if x == Commands::Unban as u32 => "Lifts a ban.",
+            x if x == Commands::Unmute as u32 => "Lifts a mute.",
+            x if x == Commands::Warn as u32 => "Gives someone a warning.",
+            x if x == Commands::Warnings as u32 => "Lists someone's warnings.",
+            x if x == Commands::ClearWarn as u32 => "Removes someone's warnings.",
+            x if x == Commands::Escalation as u32 => {
+                "Sets up what happens after a number of warnings."
+            }
+            x if x == Commands::ModLog as u32 => {
+                "Sets the channel where admin actions and message edits get logged."
+            }
             _ => panic!("Admin::command_description - invalid id."),
         }
     }
@@ -201,177 +331,2067 @@ impl<'a> module::Module for Module<'a> {
                  `!admin remove <role mention(-s)>` - Remove a role (roles) from the admin roles."
             }
             x if x == Commands::Nuke as u32 => {
-                "`!nuke <how many> [whose]` - Deletes the specified number of messages in the current channel. If any user mentions are present after the count, deletes the specified number of messages written by each of the people mentioned, and only theirs."
+                "`!nuke <how many> [whose]` - Deletes the specified number of messages in the current channel. If any user mentions are present after the count, deletes that many messages written by the people mentioned, and only theirs.\n\
+                 These flags narrow it down further:\n\
+                 `--matching <regex>` - messages with text matching the regex,\n\
+                 `--bots` - messages from bots,\n\
+                 `--attachments` - messages with attachments,\n\
+                 `--links` - messages with links,\n\
+                 `--newer-than <duration>` - messages sent within that time, like `--newer-than 1h`,\n\
+                 `--after <message ID>` - messages sent after that one,\n\
+                 `--dry-run` - only says what would be deleted.\n\
+                 For example, `!nuke 20 @someone --links --newer-than 1h`. At most 5000 messages are looked through."
+            }
+            x if x == Commands::Prefix as u32 => {
+                "`!prefix` - Shows the command prefix used on this server.\n\
+                 `!prefix <new prefix>` - Sets the command prefix for this server.\n\
+                 `!prefix reset` - Resets the command prefix back to the default.\n\
+                 Mentioning the bot instead of the prefix always works, for example `@yalter-bot help`."
+            }
+            x if x == Commands::Enable as u32 => {
+                "`!enable <module or command>` - Turns the module or the command back on on this server.\n\
+                 `!enable <module or command> <channel mention>` - Turns it on in that channel only, even if it's off on the rest of the server."
+            }
+            x if x == Commands::Disable as u32 => {
+                "`!disable <module or command>` - Turns the module or the command off on this server.\n\
+                 `!disable <module or command> <channel mention>` - Turns it off in that channel only.\n\
+                 Settings for a channel win over the ones for the server, and settings for a command win over the ones for its module."
+            }
+            x if x == Commands::Disabled as u32 => {
+                "`!disabled` - Lists the modules and commands turned on or off on this server and in its channels."
+            }
+            x if x == Commands::Kick as u32 => {
+                "`!kick <user mention> [reason]` - Kicks the user from the server. They can join again with an invite."
+            }
+            x if x == Commands::Ban as u32 => {
+                "`!ban <user mention> [reason]` - Bans the user from the server.\n\
+                 `!ban <user mention> <duration> [reason]` - Bans the user for that long, for example `!ban @someone 1d spam`."
+            }
+            x if x == Commands::Mute as u32 => {
+                "`!mute <user mention> <duration> [reason]` - Gives the user the `Muted` role for that long, for example `!mute @someone 30m`. The server needs a `Muted` role which can't send messages."
+            }
+            x if x == Commands::Unban as u32 => {
+                "`!unban <user mention or ID>` - Lifts the user's ban before it runs out."
+            }
+            x if x == Commands::Unmute as u32 => {
+                "`!unmute <user mention>` - Lifts the user's mute before it runs out."
+            }
+            x if x == Commands::Warn as u32 => {
+                "`!warn <user mention> <reason>` - Gives the user a warning. If they reach a number of warnings set up with `!escalation`, they also get muted, kicked or banned."
+            }
+            x if x == Commands::Warnings as u32 => {
+                "`!warnings <user mention>` - Lists the user's warnings, who gave them, when and why."
+            }
+            x if x == Commands::ClearWarn as u32 => {
+                "`!clearwarn <user mention>` - Removes all of the user's warnings.\n\
+                 `!clearwarn <user mention> <number>` - Removes one warning, numbered like in `!warnings`."
+            }
+            x if x == Commands::Escalation as u32 => {
+                "`!escalation` - Lists what happens after a number of warnings.\n\
+                 `!escalation <warnings> mute <duration>` - Mutes people for that long when they get that many warnings, for example `!escalation 3 mute 1h`.\n\
+                 `!escalation <warnings> kick` - Kicks them.\n\
+                 `!escalation <warnings> ban [duration]` - Bans them, for good or for that long.\n\
+                 `!escalation <warnings> off` - Removes the step.\n\
+                 Every warning past the last step gets that step's action again."
+            }
+            x if x == Commands::ModLog as u32 => {
+                "`!modlog` - Shows the mod log channel.\n\
+                 `!modlog <channel mention>` - Logs the admin commands, the automatic mutes, kicks and bans, and edited and deleted messages to that channel.\n\
+                 `!modlog off` - Stops logging."
             }
             _ => panic!("Admin::command_help_message - invalid id."),
         }
     }
 
+    fn command_arguments(&self, id: u32) -> Option<&'static Schema> {
+        match id {
+            x if x == Commands::Admin as u32 => Some(&ADMIN_ARGUMENTS),
+            x if x == Commands::Nuke as u32 => Some(&NUKE_ARGUMENTS),
+            x if x == Commands::Prefix as u32 => None,
+            x if x == Commands::Enable as u32 => Some(&SWITCH_ARGUMENTS),
+            x if x == Commands::Disable as u32 => Some(&SWITCH_ARGUMENTS),
+            x if x == Commands::Disabled as u32 => None,
+            x if x == Commands::Kick as u32 => Some(&KICK_ARGUMENTS),
+            x if x == Commands::Ban as u32 => Some(&BAN_ARGUMENTS),
+            x if x == Commands::Mute as u32 => Some(&MUTE_ARGUMENTS),
+            x if x == Commands::Unban as u32 => Some(&USER_ARGUMENTS),
+            x if x == Commands::Unmute as u32 => Some(&USER_ARGUMENTS),
+            x if x == Commands::Warn as u32 => Some(&WARN_ARGUMENTS),
+            x if x == Commands::Warnings as u32 => Some(&USER_ARGUMENTS),
+            x if x == Commands::ClearWarn as u32 => Some(&CLEARWARN_ARGUMENTS),
+            x if x == Commands::Escalation as u32 => Some(&ESCALATION_ARGUMENTS),
+            x if x == Commands::ModLog as u32 => Some(&MOD_LOG_ARGUMENTS),
+            _ => panic!("Admin::command_arguments - invalid id."),
+        }
+    }
+
     fn handle(&self, bot: &Bot, message: &Message, id: u32, text: &str) {
-        let state = bot.get_state().read().unwrap();
+        if !self.check_permission(bot, message) {
+            return;
+        }
+
+        match id {
+            x if x == Commands::Prefix as u32 => self.handle_prefix(bot, message, text),
+            x if x == Commands::Disabled as u32 => self.handle_disabled(bot, message),
+            _ => panic!("Admin::handle - invalid id."),
+        }
+    }
+
+    fn handle_with_arguments(&self, bot: &Bot, message: &Message, id: u32, args: &Arguments) {
+        if !self.check_permission(bot, message) {
+            return;
+        }
+
+        match id {
+            x if x == Commands::Admin as u32 => self.handle_admin(bot, message, args),
+            x if x == Commands::Nuke as u32 => self.handle_nuke(bot, message, args),
+            x if x == Commands::Enable as u32 => self.handle_switch(bot, message, args, true),
+            x if x == Commands::Disable as u32 => self.handle_switch(bot, message, args, false),
+            x if x == Commands::Kick as u32 => self.handle_kick(bot, message, args),
+            x if x == Commands::Ban as u32 => self.handle_ban(bot, message, args),
+            x if x == Commands::Mute as u32 => self.handle_mute(bot, message, args),
+            x if x == Commands::Unban as u32 => self.handle_unban(bot, message, args),
+            x if x == Commands::Unmute as u32 => self.handle_unmute(bot, message, args),
+            x if x == Commands::Warn as u32 => self.handle_warn(bot, message, args),
+            x if x == Commands::Warnings as u32 => self.handle_warnings(bot, message, args),
+            x if x == Commands::ClearWarn as u32 => self.handle_clearwarn(bot, message, args),
+            x if x == Commands::Escalation as u32 => self.handle_escalation(bot, message, args),
+            x if x == Commands::ModLog as u32 => self.handle_mod_log(bot, message, args),
+            _ => panic!("Admin::handle_with_arguments - invalid id."),
+        }
+    }
+
+    fn command_prefix(&self, bot: &Bot, server: ServerId) -> Option<String> {
+        bot.get_storage(STORAGE_NAMESPACE).get(server, PREFIX_KEY)
+    }
+
+    fn is_admin(&self, bot: &Bot, server: ServerId, user: UserId) -> bool {
+        let owner = match bot.get_state()
+                             .read()
+                             .recover()
+                             .servers()
+                             .iter()
+                             .find(|x| x.id == server)
+        {
+            Some(server) => server.owner_id,
+            None => return false,
+        };
+
+        self.member_is_admin(bot, server, owner, user).unwrap_or(false)
+    }
+
+    fn is_command_turned_off(&self,
+                             bot: &Bot,
+                             server: ServerId,
+                             channel: ChannelId,
+                             module: &str,
+                             command: &str)
+                             -> bool {
+        let switches = get_switches(bot, server);
+        let channel_switches = get_channel_switches(bot, server);
+
+        let mut levels = Vec::new();
+        if let Some(x) = channel_switches.get(&channel.0) {
+            levels.push(x);
+        }
+        levels.push(&switches);
+
+        !is_on(&levels, &module.to_lowercase(), Some(command))
+    }
+
+    fn handle_message(&self, bot: &Bot, message: &Message) {
+        let server_id = match bot.get_server_id(message.channel_id) {
+            Some(server_id) => server_id,
+            None => return,
+        };
+
+        // Edits in the log itself don't need logging.
+        match get_mod_log(bot, server_id) {
+            Some(channel) if channel != message.channel_id => {}
+            _ => return,
+        }
+
+        let mut recent = self.recent.lock().recover();
+        let messages = recent.entry((server_id, message.channel_id))
+                             .or_insert_with(VecDeque::new);
 
-        let has_permission = match state.find_channel(message.channel_id) {
-            Some(ChannelRef::Private(_)) => {
-                bot.send(message.channel_id, "Sorry, but you cannot use the admin commands through PMs. They don't make much sense here anyways.");
+        if messages.len() == RECENT_MESSAGES_PER_CHANNEL {
+            messages.pop_front();
+        }
+
+        messages.push_back(message.clone());
+    }
+
+    fn handle_message_update(&self,
+                             bot: &Bot,
+                             channel_id: ChannelId,
+                             id: MessageId,
+                             content: &str) {
+        let server_id = match bot.get_server_id(channel_id) {
+            Some(server_id) => server_id,
+            None => return,
+        };
+
+        let (author, before) = {
+            let mut recent = self.recent.lock().recover();
+            let message = match recent.get_mut(&(server_id, channel_id))
+                                      .and_then(|x| x.iter_mut().find(|x| x.id == id))
+            {
+                Some(message) => message,
+                None => return,
+            };
+
+            // Discord also sends updates when it adds link previews.
+            if message.content == content {
                 return;
             }
 
-            Some(ChannelRef::Public(server, _)) => {
-                if message.author.id.0 == server.owner_id.0 {
-                    true
-                } else if let Some(admin_roles) =
-                    self.memory.read().unwrap().get_admin_roles(server.id)
-                {
-                    if let Ok(member) = bot.get_member(server.id, message.author.id) {
-                        let mut found = false;
+            (message.author.id, mem::replace(&mut message.content, content.to_owned()))
+        };
 
-                        for role in member.roles {
-                            if admin_roles.contains(&role.0) {
-                                found = true;
-                            }
-                        }
+        mod_log(bot,
+                server_id,
+                Embed::new().title("Message edited")
+                            .description(&format!("By {} in {}",
+                                                  author.mention(),
+                                                  channel_id.mention()))
+                            .field("Before", &before, false)
+                            .field("After", content, false));
+    }
 
-                        found
-                    } else {
-                        bot.send(message.channel_id,
-                                 "Sorry, I couldn't get your member info.");
-                        return;
-                    }
-                } else {
-                    false
-                }
-            }
+    fn handle_message_delete(&self, bot: &Bot, channel_id: ChannelId, id: MessageId) {
+        let server_id = match bot.get_server_id(channel_id) {
+            Some(server_id) => server_id,
+            None => return,
+        };
 
-            Some(ChannelRef::Group(_)) => {
-                bot.send(message.channel_id, "Admin commands in groups? Hm.");
-                return;
+        let message = {
+            let mut recent = self.recent.lock().recover();
+            let messages = match recent.get_mut(&(server_id, channel_id)) {
+                Some(messages) => messages,
+                None => return,
+            };
+
+            match messages.iter().position(|x| x.id == id) {
+                Some(index) => messages.remove(index).unwrap(),
+                None => return,
             }
+        };
+
+        mod_log(bot,
+                server_id,
+                Embed::new().title("Message deleted")
+                            .description(&format!("By {} in {}",
+                                                  message.author.mention(),
+                                                  channel_id.mention()))
+                            .field("Content", &message.content, false));
+    }
+
+    fn on_tick(&self, bot: &Bot) {
+        lift_due(bot);
+    }
+
+    fn on_reconnect(&self, _bot: &Bot) {
+        // Edits and deletions from while the bot was away were missed, so the kept messages
+        // can't be trusted anymore.
+        self.recent.lock().recover().clear();
+    }
+
+    fn on_shutdown(&self, bot: &Bot) {
+        // The ticks have stopped, so lift what ran out during the grace period now rather than
+        // after the restart.
+        lift_due(bot);
+    }
+
+    fn handle_server_delete(&self, bot: &Bot, server: ServerId) {
+        // We've been kicked or the server is gone, no point in keeping its settings around.
+        bot.get_storage(STORAGE_NAMESPACE).clear(server);
+        self.recent.lock().recover().retain(|&(x, _), _| x != server);
+    }
+}
+
+impl<'a> Module<'a> {
+    // Returns None if the member info couldn't be retrieved.
+    fn member_is_admin(&self,
+                       bot: &Bot,
+                       server: ServerId,
+                       owner: UserId,
+                       user: UserId)
+                       -> Option<bool> {
+        if user == owner {
+            return Some(true);
+        }
+
+        let admin_roles = get_admin_roles(bot, server);
+        if admin_roles.is_empty() {
+            return Some(false);
+        }
 
+        bot.get_member(server, user)
+           .ok()
+           .map(|member| member.roles.iter().any(|role| admin_roles.contains(&role.0)))
+    }
+
+    fn check_permission(&self, bot: &Bot, message: &Message) -> bool {
+        // The state is let go before making any requests, the gateway thread needs to write it.
+        let server = match bot.get_state()
+                              .read()
+                              .recover()
+                              .find_channel(message.channel_id)
+        {
+            Some(ChannelRef::Public(server, _)) => Ok((server.id, server.owner_id)),
+            Some(ChannelRef::Private(_)) => Err("Sorry, but you cannot use the admin commands through PMs. They don't make much sense here anyways."),
+            Some(ChannelRef::Group(_)) => Err("Admin commands in groups? Hm."),
             None => {
-                bot.send(
-                    message.channel_id,
-                    "Huh, I couldn't get this channel's info for some reason. Try again I guess?",
-                );
-                return;
+                Err("Huh, I couldn't get this channel's info for some reason. Try again I guess?")
             }
         };
 
-        if !has_permission {
-            return;
-        }
+        let (server_id, owner_id) = match server {
+            Ok(x) => x,
+            Err(reply) => {
+                bot.send(message.channel_id, reply);
+                return false;
+            }
+        };
 
-        match id {
-            x if x == Commands::Admin as u32 => self.handle_admin(bot, message, text, &state),
-            x if x == Commands::Nuke as u32 => self.handle_nuke(bot, message, text),
-            _ => panic!("Admin::handle - invalid id."),
+        match self.member_is_admin(bot, server_id, owner_id, message.author.id) {
+            Some(x) => x,
+            None => {
+                bot.send(message.channel_id, "Sorry, I couldn't get your member info.");
+                false
+            }
         }
     }
-}
 
-impl<'a> Module<'a> {
-    fn handle_admin(&self,
-                    bot: &Bot,
-                    message: &Message,
-                    text: &str,
-                    state: &RwLockReadGuard<State>) {
-        if let Some(caps) = ADMIN_REGEX.captures(&text.to_lowercase()) {
-            // No need to recheck, we did that in handle().
-            let server = match state.find_channel(message.channel_id).unwrap() {
-                ChannelRef::Public(server, _) => server,
-                _ => {
-                    panic!("Did I just witness some memory corruption?");
-                }
-            };
+    fn handle_admin(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let (server_id, server_roles) = match bot.get_state()
+                                                 .read()
+                                                 .recover()
+                                                 .find_channel(message.channel_id)
+                                                 .unwrap()
+        {
+            ChannelRef::Public(server, _) => (server.id, server.roles.clone()),
+            _ => {
+                panic!("Did I just witness some memory corruption?");
+            }
+        };
 
-            match caps.get(1).unwrap().as_str() {
-                "list" => {
-                    if let Some(admin_roles) =
-                        self.memory.read().unwrap().get_admin_roles(server.id)
-                    {
-                        let mut buf = "Admin roles:".to_owned();
-
-                        for role_id in admin_roles {
-                            buf.push_str(&format!("\n- {} ", role_id));
-
-                            buf.push_str(&if let Some(role) =
-                                             server.roles.iter().find(|x| x.id.0 == *role_id)
-                                         {
-                                             format!("`{}`", role.name)
-                                         } else {
-                                             " this role was removed".to_owned()
-                                         });
-                        }
-
-                        bot.send(message.channel_id, &buf);
-                    } else {
-                        bot.send(message.channel_id, "There are no admin roles yet.");
+        let roles = args.roles("roles");
+
+        match args.text("action").unwrap().to_lowercase().as_str() {
+            "list" => {
+                let admin_roles = get_admin_roles(bot, server_id);
+
+                if !admin_roles.is_empty() {
+                    let mut lines = Vec::new();
+                    for role_id in admin_roles {
+                        lines.push(match server_roles.iter().find(|x| x.id.0 == role_id) {
+                                       Some(role) => format!("- {} `{}`", role_id, role.name),
+                                       None => format!("- {} this role was removed", role_id),
+                                   });
                     }
+
+                    bot.send_embed(message.channel_id,
+                                   &Embed::new().title("Admin roles")
+                                                .color(ADMIN_ROLES_COLOR)
+                                                .description(&lines.join("\n")));
+                } else {
+                    bot.send(message.channel_id, "There are no admin roles yet.");
                 }
+            }
 
-                "add" => {
-                    if !message.mention_roles.is_empty() {
-                        self.memory
-                            .write()
-                            .unwrap()
-                            .add_admin_roles(server.id, &message.mention_roles);
-                    } else {
-                        bot.send(message.channel_id, "You didn't mention any roles.");
-                    }
+            "add" => {
+                if roles.is_empty() {
+                    bot.send(message.channel_id, "You didn't mention any roles.");
+                    return;
                 }
 
-                "remove" => {
-                    if !message.mention_roles.is_empty() {
-                        self.memory
-                            .write()
-                            .unwrap()
-                            .remove_admin_roles(server.id, &message.mention_roles);
-                    } else {
-                        bot.send(message.channel_id, "You didn't mention any roles.");
-                    }
+                let saved = update_admin_roles(bot, server_id, |admin_roles| {
+                    admin_roles.extend(roles.iter().map(|x| x.0));
+                });
+                if saved.is_none() {
+                    bot.send(message.channel_id, "Sorry, I couldn't save the admin roles.");
+                    return;
                 }
 
-                _ => {
-                    bot.send(message.channel_id,
-                             <Module as module::Module>::command_help_message(&self,
-                                                                              Commands::Admin
-                                                                              as u32));
+                log_action(bot,
+                           server_id,
+                           &format!("{} added {} to the admin roles.",
+                                    message.author.mention(),
+                                    join(roles.iter().map(|x| x.mention().to_string()))));
+            }
+
+            "remove" => {
+                if roles.is_empty() {
+                    bot.send(message.channel_id, "You didn't mention any roles.");
+                    return;
+                }
+
+                let saved = update_admin_roles(bot, server_id, |admin_roles| {
+                    admin_roles.retain(|x| roles.iter().find(|r| r.0 == *x).is_none());
+                });
+                if saved.is_none() {
+                    bot.send(message.channel_id, "Sorry, I couldn't save the admin roles.");
+                    return;
                 }
+
+                log_action(bot,
+                           server_id,
+                           &format!("{} removed {} from the admin roles.",
+                                    message.author.mention(),
+                                    join(roles.iter().map(|x| x.mention().to_string()))));
             }
-        } else {
+
+            _ => {
+                bot.send(message.channel_id,
+                         <Module as module::Module>::command_help_message(&self,
+                                                                          Commands::Admin
+                                                                          as u32));
+            }
+        }
+    }
+
+    fn handle_nuke(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        let amount = args.integer("amount").unwrap();
+        if amount <= 0 {
             bot.send(message.channel_id,
                      <Module as module::Module>::command_help_message(&self,
-                                                                      Commands::Admin as u32));
+                                                                      Commands::Nuke as u32));
+            return;
         }
-    }
 
-    fn handle_nuke(&self, bot: &Bot, message: &Message, text: &str) {
-        if let Some(amount) = NUKE_REGEX.captures(text)
-                                        .and_then(|x| x.get(2))
-                                        .and_then(|x| x.as_str().parse::<u64>().ok())
-                                        .map(|x| x + 1)
-                                        .and_then(|x| if x <= 1 { None } else { Some(x) })
-        {
-            let mentioned_user_ids: Vec<UserId> = message.mentions.iter().map(|x| x.id).collect();
-
-            if let Ok(recent_message_ids) =
-                bot.get_messages(message.channel_id, GetMessages::MostRecent, amount)
-                   .map(|x| {
-                            x.into_iter()
-                             .filter(|msg| {
-                                         mentioned_user_ids.is_empty()
-                                         || mentioned_user_ids.contains(&msg.author.id)
-                                     })
-                             .map(|msg| msg.id)
-                             .collect::<Vec<MessageId>>()
-                        }) {
-                bot.delete_messages(message.channel_id, &recent_message_ids);
-            } else {
+        let filter = match NukeFilter::new(args) {
+            Ok(filter) => filter,
+            Err(err) => {
+                bot.send(message.channel_id, &err);
+                return;
+            }
+        };
+
+        let found = match find_messages(bot, message, &filter, amount as usize) {
+            Ok(found) => found,
+            Err(_) => {
                 bot.send(message.channel_id, "Error getting the recent messages.");
+                return;
             }
-        } else {
+        };
+
+        if found.is_empty() {
+            bot.send(message.channel_id, "No messages matched.");
+            return;
+        }
+
+        if args.has("dry-run") {
+            bot.send(message.channel_id, &describe_nuke(&found, "I would delete"));
+            return;
+        }
+
+        let cutoff = unix_time().saturating_sub(BULK_DELETE_MAX_AGE_SECS) as i64;
+        let (recent, old): (Vec<&Message>, Vec<&Message>) =
+            found.iter().partition(|x| x.timestamp.timestamp() > cutoff);
+
+        // The command goes too.
+        let recent = recent.iter()
+                           .map(|x| x.id)
+                           .chain(Some(message.id))
+                           .collect::<Vec<_>>();
+
+        bot.delete_messages(message.channel_id, &recent);
+        for old in old {
+            bot.delete_message(message.channel_id, old.id);
+        }
+
+        if let Some(server_id) = bot.get_server_id(message.channel_id) {
+            log_action(bot,
+                       server_id,
+                       &format!("{} nuked messages in {}. {}",
+                                message.author.mention(),
+                                message.channel_id.mention(),
+                                describe_nuke(&found, "Deleted")));
+        }
+    }
+
+    fn handle_switch(&self, bot: &Bot, message: &Message, args: &Arguments, on: bool) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+
+        let channel = args.channel("channel");
+        if let Some(channel) = channel {
+            if bot.get_server_id(channel) != Some(server_id) {
+                bot.send(message.channel_id, "That channel isn't on this server.");
+                return;
+            }
+        }
+
+        let what = args.text("module or command").unwrap();
+        let target = match find_target(bot, server_id, what) {
+            Some(target) => target,
+            None => {
+                bot.send(message.channel_id,
+                         &format!("There's no module or command called `{}`.", what));
+                return;
+            }
+        };
+
+        if !on && is_protected(&target) {
             bot.send(message.channel_id,
-                     <Module as module::Module>::command_help_message(&self,
-                                                                      Commands::Nuke as u32));
+                     "The `Admin` module and its `!enable` and `!disable` commands can't be \
+                      turned off, there would be no way to turn things back on.");
+            return;
+        }
+
+        let module = target.module.to_lowercase();
+        let command = target.command.as_ref().map(String::as_str);
+
+        let saved = match channel {
+            Some(channel) => {
+                let switches = get_switches(bot, server_id);
+                update_channel_switches(bot, server_id, |channel_switches| {
+                    {
+                        let level = channel_switches.entry(channel.0)
+                                                    .or_insert_with(Switches::default);
+                        switch(level, &[&switches], &module, command, on);
+                    }
+
+                    channel_switches.retain(|_, x| !x.is_empty());
+                })
+            }
+
+            None => {
+                update_switches(bot, server_id, |switches| {
+                    switch(switches, &[], &module, command, on)
+                })
+            }
+        };
+
+        if saved.is_none() {
+            bot.send(message.channel_id, "Sorry, I couldn't save the change.");
+            return;
         }
+
+        let prefix = bot.get_command_prefix(Some(server_id));
+        let what = match target.command {
+            Some(ref command) => format!("`{}{}`", prefix, command),
+            None => format!("the `{}` module", target.module),
+        };
+        let place = match channel {
+            Some(channel) => format!("in {}", channel.mention()),
+            None => "on this server".to_owned(),
+        };
+
+        let on = if on { "on" } else { "off" };
+        log_action(bot,
+                   server_id,
+                   &format!("{} turned {} {} {}.",
+                            message.author.mention(),
+                            on,
+                            what,
+                            place));
+        bot.send(message.channel_id,
+                 &format!("Turned {} {} {}.", on, what, place));
+    }
+
+    fn handle_disabled(&self, bot: &Bot, message: &Message) {
+        // No need to recheck, we did that in handle().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let prefix = bot.get_command_prefix(Some(server_id));
+
+        let mut lines = describe(&get_switches(bot, server_id), &prefix);
+        if !lines.is_empty() {
+            lines.insert(0, "**On this server**".to_owned());
+        }
+
+        let mut channel_switches = get_channel_switches(bot, server_id).into_iter()
+                                                                         .collect::<Vec<_>>();
+        channel_switches.sort_by_key(|x| x.0);
+
+        for (channel, switches) in channel_switches {
+            lines.push(format!("**In {}**", ChannelId(channel).mention()));
+            lines.extend(describe(&switches, &prefix));
+        }
+
+        if lines.is_empty() {
+            bot.send(message.channel_id, "Everything is turned on everywhere on this server.");
+        } else {
+            bot.send_embed(message.channel_id,
+                           &Embed::new().title("Turned on and off")
+                                        .color(SWITCHES_COLOR)
+                                        .description(&lines.join("\n")));
+        }
+    }
+
+    // Admins can't use the moderation commands on each other or on the bot.
+    fn check_target(&self, bot: &Bot, message: &Message, server: ServerId, user: UserId) -> bool {
+        if user == bot.get_state().read().recover().user().id {
+            bot.send(message.channel_id, "I'm not doing that to myself.");
+            return false;
+        }
+
+        if bot.is_admin(server, user) {
+            bot.send(message.channel_id, "Sorry, you can't do that to an admin.");
+            return false;
+        }
+
+        true
+    }
+
+    fn handle_kick(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        if !self.check_target(bot, message, server_id, user) {
+            return;
+        }
+
+        if let Err(err) = kick(bot, server_id, user) {
+            bot.send(message.channel_id, &err);
+            return;
+        }
+
+        info!("{} kicked {} from {}.", message.author.id, user, server_id);
+        log_action(bot,
+                   server_id,
+                   &format!("{} kicked {}.{}",
+                            message.author.mention(),
+                            user.mention(),
+                            reason(args)));
+        bot.send(message.channel_id,
+                 &format!("Kicked {}.{}", user.mention(), reason(args)));
+    }
+
+    fn handle_ban(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+        let duration = args.duration("duration");
+
+        if !self.check_target(bot, message, server_id, user) {
+            return;
+        }
+
+        if let Err(err) = ban(bot, server_id, user, duration) {
+            bot.send(message.channel_id, &err);
+            return;
+        }
+
+        info!("{} banned {} from {} for {:?}.",
+              message.author.id, user, server_id, duration);
+        log_action(bot,
+                   server_id,
+                   &format!("{} banned {}{}.{}",
+                            message.author.mention(),
+                            user.mention(),
+                            for_duration(duration),
+                            reason(args)));
+        bot.send(message.channel_id,
+                 &format!("Banned {}{}.{}",
+                          user.mention(),
+                          for_duration(duration),
+                          reason(args)));
+    }
+
+    fn handle_mute(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+        let duration = args.duration("duration").unwrap();
+
+        if !self.check_target(bot, message, server_id, user) {
+            return;
+        }
+
+        if let Err(err) = mute(bot, server_id, user, duration) {
+            bot.send(message.channel_id, &err);
+            return;
+        }
+
+        info!("{} muted {} on {} for {:?}.",
+              message.author.id, user, server_id, duration);
+        log_action(bot,
+                   server_id,
+                   &format!("{} muted {}{}.{}",
+                            message.author.mention(),
+                            user.mention(),
+                            for_duration(Some(duration)),
+                            reason(args)));
+        bot.send(message.channel_id,
+                 &format!("Muted {}{}.{}",
+                          user.mention(),
+                          for_duration(Some(duration)),
+                          reason(args)));
+    }
+
+    fn handle_unban(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        unschedule(bot, server_id, Timed::Ban, user);
+
+        if bot.unban_member(server_id, user).is_err() {
+            bot.send(message.channel_id,
+                     "I couldn't unban them. Are they banned, and do I have the Ban Members \
+                      permission?");
+            return;
+        }
+
+        info!("{} unbanned {} on {}.", message.author.id, user, server_id);
+        log_action(bot,
+                   server_id,
+                   &format!("{} unbanned {}.", message.author.mention(), user.mention()));
+        bot.send(message.channel_id, &format!("Unbanned {}.", user.mention()));
+    }
+
+    fn handle_unmute(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        unschedule(bot, server_id, Timed::Mute, user);
+
+        match unmute(bot, server_id, user) {
+            Ok(true) => {
+                info!("{} unmuted {} on {}.", message.author.id, user, server_id);
+                log_action(bot,
+                           server_id,
+                           &format!("{} unmuted {}.", message.author.mention(), user.mention()));
+                bot.send(message.channel_id, &format!("Unmuted {}.", user.mention()));
+            }
+            Ok(false) => bot.send(message.channel_id, "They aren't muted."),
+            Err(_) => bot.send(message.channel_id, "Sorry, I couldn't unmute them."),
+        }
+    }
+
+    fn handle_warn(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        if !self.check_target(bot, message, server_id, user) {
+            return;
+        }
+
+        let text = args.text("reason").unwrap();
+        let count = match warn(bot, server_id, user, message.author.id, text) {
+            Some(count) => count,
+            None => {
+                bot.send(message.channel_id, "Sorry, I couldn't save the warning.");
+                return;
+            }
+        };
+        info!("{} warned {} on {}, that's {} warnings.",
+              message.author.id, user, server_id, count);
+        log_action(bot,
+                   server_id,
+                   &format!("{} warned {}, that's {}.{}",
+                            message.author.mention(),
+                            user.mention(),
+                            warning_count(count),
+                            reason(args)));
+
+        bot.send(message.channel_id,
+                 &format!("Warned {}. They have {} now.{}",
+                          user.mention(),
+                          warning_count(count),
+                          reason(args)));
+
+        if let Some(reply) = escalate(bot, server_id, user, count) {
+            bot.send(message.channel_id, &reply);
+        }
+    }
+
+    fn handle_warnings(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        let warnings = get_warnings(bot, server_id).remove(&user.0)
+                                                   .unwrap_or_default();
+        if warnings.is_empty() {
+            bot.send(message.channel_id,
+                     &format!("{} has no warnings.", user.mention()));
+            return;
+        }
+
+        let skipped = warnings.len().saturating_sub(MAX_WARNINGS_SHOWN);
+        let mut lines = vec![format!("{} has {}:", user.mention(), warning_count(warnings.len()))];
+        for (i, warning) in warnings.iter().enumerate().skip(skipped) {
+            lines.push(format!("{}. {} by {}: {}",
+                               i + 1,
+                               format_time(warning.time),
+                               UserId(warning.moderator).mention(),
+                               warning.reason));
+        }
+
+        let mut embed = Embed::new().title("Warnings")
+                                    .color(WARNINGS_COLOR)
+                                    .description(&lines.join("\n"));
+        if skipped > 0 {
+            embed = embed.footer(&format!("The {} older ones aren't shown.", skipped));
+        }
+
+        bot.send_embed(message.channel_id, &embed);
+    }
+
+    fn handle_clearwarn(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+        let user = args.user("user").unwrap();
+
+        let result = update_warnings(bot, server_id, |all| {
+            let reply = {
+                let warnings = all.entry(user.0).or_insert_with(Vec::new);
+
+                match args.integer("number") {
+                    Some(number) if number < 1 || number as usize > warnings.len() => {
+                        Err(format!("{} doesn't have a warning number {}.",
+                                    user.mention(),
+                                    number))
+                    }
+                    Some(number) => {
+                        warnings.remove(number as usize - 1);
+                        Ok(format!("Removed warning number {} of {}. They have {} now.",
+                                   number,
+                                   user.mention(),
+                                   warning_count(warnings.len())))
+                    }
+                    None => {
+                        warnings.clear();
+                        Ok(format!("Removed all warnings of {}.", user.mention()))
+                    }
+                }
+            };
+
+            all.retain(|_, x| !x.is_empty());
+            reply
+        });
+
+        let reply = match result {
+            Some(Ok(reply)) => reply,
+            Some(Err(reply)) => {
+                bot.send(message.channel_id, &reply);
+                return;
+            }
+            None => {
+                bot.send(message.channel_id, "Sorry, I couldn't save the warnings.");
+                return;
+            }
+        };
+
+        info!("{} cleared warnings of {} on {}.",
+              message.author.id, user, server_id);
+        log_action(bot,
+                   server_id,
+                   &format!("{}: {}", message.author.mention(), reply));
+        bot.send(message.channel_id, &reply);
+    }
+
+    fn handle_escalation(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+
+        let count = match args.integer("warnings") {
+            Some(count) if count < 1 => {
+                bot.send(message.channel_id, "The number of warnings must be at least 1.");
+                return;
+            }
+            Some(count) => count as u64,
+            None => {
+                let escalation = get_escalation(bot, server_id);
+                if escalation.is_empty() {
+                    bot.send(message.channel_id,
+                             "Nothing happens after any number of warnings yet.");
+                } else {
+                    let lines = escalation.iter()
+                                          .map(|x| {
+                                                   format!("- {}: {}",
+                                                           warning_count(x.warnings as usize),
+                                                           describe_penalty(x))
+                                               })
+                                          .collect::<Vec<_>>();
+                    bot.send_embed(message.channel_id,
+                                   &Embed::new().title("Escalation")
+                                                .color(WARNINGS_COLOR)
+                                                .description(&lines.join("\n")));
+                }
+                return;
+            }
+        };
+
+        let duration = args.duration("duration").map(|x| x.as_secs());
+        let action = args.text("action").map(str::to_lowercase);
+        let penalty = match action.as_ref().map(String::as_str) {
+            Some("mute") if duration.is_none() => {
+                bot.send(message.channel_id, "Mutes need a duration, like `1h`.");
+                return;
+            }
+            Some("mute") => Some(Penalty::Mute),
+            Some("kick") if duration.is_some() => {
+                bot.send(message.channel_id, "Kicks don't have a duration.");
+                return;
+            }
+            Some("kick") => Some(Penalty::Kick),
+            Some("ban") => Some(Penalty::Ban),
+            Some("off") => None,
+            _ => {
+                bot.send(message.channel_id,
+                         "The action must be `mute`, `kick`, `ban` or `off`.");
+                return;
+            }
+        };
+
+        let reply = update_escalation(bot, server_id, |escalation| {
+            escalation.retain(|x| x.warnings != count);
+
+            match penalty {
+                Some(penalty) => {
+                    let step = Escalation { warnings: count,
+                                            penalty,
+                                            duration, };
+                    escalation.push(step);
+                    escalation.sort_by_key(|x| x.warnings);
+                    format!("At {}: {}.", warning_count(count as usize), describe_penalty(&step))
+                }
+                None => format!("Nothing happens at {} anymore.", warning_count(count as usize)),
+            }
+        });
+        let reply = match reply {
+            Some(reply) => reply,
+            None => {
+                bot.send(message.channel_id, "Sorry, I couldn't save the escalation.");
+                return;
+            }
+        };
+
+        log_action(bot,
+                   server_id,
+                   &format!("{} changed the escalation. {}", message.author.mention(), reply));
+        bot.send(message.channel_id, &reply);
+    }
+
+    fn handle_mod_log(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        // No need to recheck, we did that in handle_with_arguments().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+
+        if let Some(off) = args.text("off") {
+            if args.has("channel") || off.to_lowercase() != "off" {
+                bot.send(message.channel_id,
+                         <Module as module::Module>::command_help_message(&self,
+                                                                          Commands::ModLog
+                                                                          as u32));
+                return;
+            }
+
+            log_action(bot,
+                       server_id,
+                       &format!("{} turned the mod log off.", message.author.mention()));
+            bot.get_storage(STORAGE_NAMESPACE).remove(server_id, MOD_LOG_KEY);
+            bot.send(message.channel_id, "Turned the mod log off.");
+            return;
+        }
+
+        let channel = match args.channel("channel") {
+            Some(channel) => channel,
+            None => {
+                match get_mod_log(bot, server_id) {
+                    Some(channel) => {
+                        bot.send(message.channel_id,
+                                 &format!("The mod log is in {}.", channel.mention()))
+                    }
+                    None => bot.send(message.channel_id, "There's no mod log on this server."),
+                }
+                return;
+            }
+        };
+
+        if bot.get_server_id(channel) != Some(server_id) {
+            bot.send(message.channel_id, "That channel isn't on this server.");
+            return;
+        }
+
+        bot.get_storage(STORAGE_NAMESPACE).set(server_id, MOD_LOG_KEY, &channel.0);
+        log_action(bot,
+                   server_id,
+                   &format!("{} moved the mod log here.", message.author.mention()));
+        bot.send(message.channel_id,
+                 &format!("The mod log is now in {}.", channel.mention()));
+    }
+
+    fn handle_prefix(&self, bot: &Bot, message: &Message, text: &str) {
+        // No need to recheck, we did that in handle().
+        let server_id = bot.get_server_id(message.channel_id).unwrap();
+
+        let text = text.trim();
+
+        if text.is_empty() {
+            let prefix = bot.get_command_prefix(Some(server_id));
+
+            bot.send(message.channel_id,
+                     &format!("The command prefix on this server is `{}`.", prefix));
+            return;
+        }
+
+        if text.to_lowercase() == "reset" {
+            bot.get_storage(STORAGE_NAMESPACE).remove(server_id, PREFIX_KEY);
+            log_action(bot,
+                       server_id,
+                       &format!("{} reset the command prefix to `{}`.",
+                                message.author.mention(),
+                                DEFAULT_COMMAND_PREFIX));
+            bot.send(message.channel_id,
+                     &format!("The command prefix is back to `{}`.",
+                              DEFAULT_COMMAND_PREFIX));
+            return;
+        }
+
+        if text.chars().count() > MAX_PREFIX_LENGTH
+           || text.chars().any(char::is_whitespace)
+           || text.contains('`')
+        {
+            bot.send(message.channel_id,
+                     &format!("The prefix must be at most {} characters long and cannot \
+                               contain whitespace or backticks.",
+                              MAX_PREFIX_LENGTH));
+            return;
+        }
+
+        bot.get_storage(STORAGE_NAMESPACE).set(server_id, PREFIX_KEY, &text);
+        log_action(bot,
+                   server_id,
+                   &format!("{} changed the command prefix to `{}`.",
+                            message.author.mention(),
+                            text));
+        bot.send(message.channel_id,
+                 &format!("The command prefix on this server is now `{}`.", text));
+    }
+}
+
+fn get_admin_roles(bot: &Bot, server: ServerId) -> Vec<u64> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, ADMIN_ROLES_KEY)
+       .unwrap_or_default()
+}
+
+// Keeps the roles sorted and without duplicates.
+fn update_admin_roles<F: FnOnce(&mut Vec<u64>)>(bot: &Bot, server: ServerId, f: F) -> Option<()> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .update(server, ADMIN_ROLES_KEY, |roles: &mut Vec<u64>| {
+           f(roles);
+           roles.sort();
+           roles.dedup();
+       })
+}
+
+impl Switches {
+    fn is_empty(&self) -> bool {
+        self.modules.is_empty() && self.commands.is_empty()
+    }
+
+    fn get(&self, module: &str, command: Option<&str>) -> Option<bool> {
+        command.and_then(|x| self.commands.get(x))
+               .or_else(|| self.modules.get(module))
+               .cloned()
+    }
+}
+
+// Goes through the levels from the most specific to the broadest, the first setting found wins.
+// Within a level, the command's setting wins over its module's. Everything is on by default.
+fn is_on(levels: &[&Switches], module: &str, command: Option<&str>) -> bool {
+    levels.iter()
+          .filter_map(|x| x.get(module, command))
+          .next()
+          .unwrap_or(true)
+}
+
+// Turns the module or command on or off on the level, leaving the setting out if the broader
+// ones already give the same result.
+fn switch(level: &mut Switches,
+          broader: &[&Switches],
+          module: &str,
+          command: Option<&str>,
+          on: bool) {
+    let inherited = match command.and_then(|_| level.modules.get(module)) {
+        Some(&x) => x,
+        None => is_on(broader, module, command),
+    };
+
+    let (map, key) = match command {
+        Some(command) => (&mut level.commands, command),
+        None => (&mut level.modules, module),
+    };
+
+    if on == inherited {
+        map.remove(key);
+    } else {
+        map.insert(key.to_owned(), on);
+    }
+}
+
+fn describe(switches: &Switches, prefix: &str) -> Vec<String> {
+    let state = |on: bool| if on { "on" } else { "off" };
+
+    let mut modules = Vec::new();
+    for (name, &on) in &switches.modules {
+        modules.push(format!("- the `{}` module is {}", name, state(on)));
+    }
+    modules.sort();
+
+    let mut commands = Vec::new();
+    for (name, &on) in &switches.commands {
+        commands.push(format!("- `{}{}` is {}", prefix, name, state(on)));
+    }
+    commands.sort();
+
+    modules.extend(commands);
+    modules
+}
+
+// Finds the module with the name or the command with the name as one of its names, with or
+// without the prefix.
+fn find_target(bot: &Bot, server: ServerId, what: &str) -> Option<Target> {
+    let what = what.to_lowercase();
+
+    for m in bot.get_modules() {
+        if m.name().to_lowercase() == what {
+            return Some(Target { module: m.name(),
+                                 command: None, });
+        }
+    }
+
+    let prefix = bot.get_command_prefix(Some(server)).to_lowercase();
+    let name = if what.starts_with(&prefix) {
+        &what[prefix.len()..]
+    } else {
+        what.trim_left_matches(DEFAULT_COMMAND_PREFIX)
+    };
+
+    for m in bot.get_modules() {
+        for names in m.commands().values() {
+            if names.contains(&name) {
+                return Some(Target { module: m.name(),
+                                     command: Some(names[0].to_owned()), });
+            }
+        }
+    }
+
+    None
+}
+
+// Turning these off would leave the admins with no way to undo it.
+fn is_protected(target: &Target) -> bool {
+    target.module == "Admin"
+    && target.command
+             .as_ref()
+             .map_or(true, |x| x == "enable" || x == "disable")
+}
+
+// Which messages !nuke deletes. All of the given conditions must hold.
+struct NukeFilter {
+    users: Vec<UserId>,
+    regex: Option<Regex>,
+    bots: bool,
+    attachments: bool,
+    links: bool,
+    // Seconds since the Unix epoch.
+    newer_than: Option<i64>,
+    after: Option<MessageId>,
+}
+
+impl NukeFilter {
+    // Returns the reply to send if a flag is invalid.
+    fn new(args: &Arguments) -> std::result::Result<Self, String> {
+        let regex = match args.text("matching") {
+            Some(text) => {
+                match Regex::new(text) {
+                    Ok(regex) => Some(regex),
+                    Err(err) => return Err(format!("`{}` isn't a valid regex: {}", text, err)),
+                }
+            }
+            None => None,
+        };
+
+        let after = match args.integer("after") {
+            Some(id) if id <= 0 => return Err(format!("`{}` isn't a message ID.", id)),
+            Some(id) => Some(MessageId(id as u64)),
+            None => None,
+        };
+
+        Ok(NukeFilter { users: args.users("whose"),
+                        regex,
+                        bots: args.has("bots"),
+                        attachments: args.has("attachments"),
+                        links: args.has("links"),
+                        newer_than: args.duration("newer-than")
+                                        .map(|x| unix_time().saturating_sub(x.as_secs()) as i64),
+                        after, })
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        (self.users.is_empty() || self.users.contains(&message.author.id))
+        && self.regex.as_ref().map_or(true, |x| x.is_match(&message.content))
+        && (!self.bots || message.author.bot)
+        && (!self.attachments || !message.attachments.is_empty())
+        && (!self.links || has_link(&message.content))
+    }
+
+    // Whether this message and all older ones are out of the range.
+    fn is_past_range(&self, message: &Message) -> bool {
+        self.newer_than.map_or(false, |x| message.timestamp.timestamp() < x)
+        || self.after.map_or(false, |x| message.id <= x)
+    }
+}
+
+fn has_link(text: &str) -> bool {
+    text.contains("http://") || text.contains("https://")
+}
+
+// Goes back through the messages before the command until enough of them match, newest first.
+fn find_messages(bot: &Bot,
+                 command: &Message,
+                 filter: &NukeFilter,
+                 amount: usize)
+                 -> Result<Vec<Message>> {
+    let mut found = Vec::new();
+    let mut before = command.id;
+    let mut scanned = 0;
+
+    while scanned < MAX_NUKE_SCANNED {
+        let page = bot.get_messages(command.channel_id, GetMessages::Before(before), 100)?;
+        if page.is_empty() {
+            break;
+        }
+
+        for message in page {
+            if filter.is_past_range(&message) {
+                return Ok(found);
+            }
+
+            scanned += 1;
+            before = message.id;
+
+            if filter.matches(&message) {
+                found.push(message);
+
+                if found.len() == amount {
+                    return Ok(found);
+                }
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+// "Deleted 3 messages: 2 from @a, 1 from @b."
+fn describe_nuke(messages: &[Message], what: &str) -> String {
+    let mut counts: Vec<(UserId, usize)> = Vec::new();
+    for message in messages {
+        match counts.iter_mut().find(|x| x.0 == message.author.id) {
+            Some(count) => count.1 += 1,
+            None => counts.push((message.author.id, 1)),
+        }
+    }
+
+    let counts = counts.into_iter()
+                       .map(|(user, count)| format!("{} from {}", count, user.mention()));
+    format!("{} {}: {}.",
+            what,
+            if messages.len() == 1 {
+                "1 message".to_owned()
+            } else {
+                format!("{} messages", messages.len())
+            },
+            join(counts))
+}
+
+// Posts to the server's mod log, if it has one.
+fn mod_log(bot: &Bot, server: ServerId, embed: Embed) {
+    if let Some(channel) = get_mod_log(bot, server) {
+        bot.send_embed(channel, &embed.color(MOD_LOG_COLOR));
+    }
+}
+
+pub fn log_action(bot: &Bot, server: ServerId, text: &str) {
+    mod_log(bot, server, Embed::new().description(text));
+}
+
+fn join<I: Iterator<Item = String>>(items: I) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+// The reason given to a moderation command, for the end of the reply.
+fn reason(args: &Arguments) -> String {
+    args.text("reason")
+        .map_or_else(String::new, |x| format!(" Reason: {}", x))
+}
+
+fn muted_role(bot: &Bot, server: ServerId) -> Option<RoleId> {
+    let state = bot.get_state().read().recover();
+    let server = state.servers().iter().find(|x| x.id == server)?;
+
+    server.roles
+          .iter()
+          .find(|x| x.name.eq_ignore_ascii_case(MUTED_ROLE_NAME))
+          .map(|x| x.id)
+}
+
+// Adds a warning and returns how many the user has now, or None if it couldn't be saved.
+pub fn warn(bot: &Bot,
+            server: ServerId,
+            user: UserId,
+            moderator: UserId,
+            reason: &str)
+            -> Option<usize> {
+    let warning = Warning { moderator: moderator.0,
+                            time: unix_time(),
+                            reason: reason.to_owned(), };
+
+    update_warnings(bot, server, |all| {
+        let warnings = all.entry(user.0).or_insert_with(Vec::new);
+        warnings.push(warning);
+        warnings.len()
+    })
+}
+
+// Applies the escalation step the user has reached, if any, and returns the reply to send.
+pub fn escalate(bot: &Bot, server: ServerId, user: UserId, count: usize) -> Option<String> {
+    let step = get_escalation(bot, server).into_iter()
+                                          .filter(|x| x.warnings <= count as u64)
+                                          .last()?;
+    let duration = step.duration.map(Duration::from_secs);
+
+    let result = match step.penalty {
+        Penalty::Mute => mute(bot, server, user, duration.unwrap_or_default()),
+        Penalty::Kick => kick(bot, server, user),
+        Penalty::Ban => ban(bot, server, user, duration),
+    };
+
+    info!("Escalated {} on {} after {} warnings: {:?}",
+          user, server, count, result);
+
+    Some(match result {
+             Ok(()) => {
+                 let done = match step.penalty {
+                     Penalty::Mute => "muted",
+                     Penalty::Kick => "kicked",
+                     Penalty::Ban => "banned",
+                 };
+                 log_action(bot,
+                            server,
+                            &format!("{} got {}{} after {}.",
+                                     user.mention(),
+                                     done,
+                                     for_duration(duration),
+                                     warning_count(count)));
+                 format!("That's {}, so I {} them{}.",
+                         warning_count(count),
+                         done,
+                         for_duration(duration))
+             }
+             Err(err) => err,
+         })
+}
+
+// "1 warning" or "3 warnings".
+fn warning_count(count: usize) -> String {
+    if count == 1 {
+        "1 warning".to_owned()
+    } else {
+        format!("{} warnings", count)
+    }
+}
+
+fn describe_penalty(step: &Escalation) -> String {
+    let duration = for_duration(step.duration.map(Duration::from_secs));
+
+    match step.penalty {
+        Penalty::Mute => format!("mute{}", duration),
+        Penalty::Kick => "kick".to_owned(),
+        Penalty::Ban if step.duration.is_none() => "ban for good".to_owned(),
+        Penalty::Ban => format!("ban{}", duration),
+    }
+}
+
+fn format_time(time: u64) -> String {
+    Utc.timestamp(time as i64, 0)
+       .format("%Y-%m-%d %H:%M UTC")
+       .to_string()
+}
+
+// The moderation actions return the reply to send if they fail.
+fn kick(bot: &Bot, server: ServerId, user: UserId) -> std::result::Result<(), String> {
+    bot.kick_member(server, user)
+       .map_err(|_| "I couldn't kick them. Do I have the Kick Members permission?".to_owned())
+}
+
+// Bans for good if there's no duration.
+fn ban(bot: &Bot,
+       server: ServerId,
+       user: UserId,
+       duration: Option<Duration>)
+       -> std::result::Result<(), String> {
+    bot.ban_member(server, user)
+       .map_err(|_| "I couldn't ban them. Do I have the Ban Members permission?".to_owned())?;
+
+    match duration {
+        Some(duration) => schedule(bot, server, Timed::Ban, user, duration),
+        // A ban for good replaces a timed one.
+        None => unschedule(bot, server, Timed::Ban, user),
+    }
+
+    Ok(())
+}
+
+pub fn mute(bot: &Bot,
+            server: ServerId,
+            user: UserId,
+            duration: Duration)
+            -> std::result::Result<(), String> {
+    let role = match muted_role(bot, server) {
+        Some(role) => role,
+        None => {
+            return Err(format!("There's no `{}` role on this server. Make one which can't send \
+                                messages and I'll hand it out.",
+                               MUTED_ROLE_NAME))
+        }
+    };
+
+    let mut roles = match bot.get_member(server, user) {
+        Ok(member) => member.roles,
+        Err(_) => return Err("Sorry, I couldn't get their member info.".to_owned()),
+    };
+
+    if !roles.contains(&role) {
+        roles.push(role);
+
+        if bot.set_member_roles(server, user, &roles).is_err() {
+            return Err("I couldn't mute them. Do I have the Manage Roles permission, and is my \
+                        role above the `Muted` role?".to_owned());
+        }
+    }
+
+    schedule(bot, server, Timed::Mute, user, duration);
+    Ok(())
+}
+
+// " for 1h", or nothing for good.
+fn for_duration(duration: Option<Duration>) -> String {
+    duration.map_or_else(String::new,
+                         |x| format!(" for {}", args::format_duration(x)))
+}
+
+// Takes the muted role away. Returns false if they didn't have it.
+fn unmute(bot: &Bot, server: ServerId, user: UserId) -> Result<bool> {
+    let role = match muted_role(bot, server) {
+        Some(role) => role,
+        None => return Ok(false),
+    };
+
+    let mut roles = bot.get_member(server, user)?.roles;
+    if !roles.contains(&role) {
+        return Ok(false);
+    }
+
+    roles.retain(|&x| x != role);
+    bot.set_member_roles(server, user, &roles).map(|_| true)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+                     .map(|x| x.as_secs())
+                     .unwrap_or(0)
+}
+
+// Lifts the bans and the mutes whose time is up. The ones which fail for a reason which might go
+// away are tried again on the next tick.
+fn lift_due(bot: &Bot) {
+    let now = unix_time();
+
+    for server in bot.get_storage(STORAGE_NAMESPACE).servers() {
+        let due = get_timed_actions(bot, server).into_iter().filter(|x| x.until <= now);
+
+        for action in due {
+            let user = UserId(action.user);
+            let result = match action.what {
+                Timed::Ban => bot.unban_member(server, user).map(|_| true),
+                Timed::Mute => unmute(bot, server, user),
+            };
+
+            match result {
+                Ok(_) => {
+                    info!("Lifted the {:?} of {} on {}.", action.what, user, server);
+
+                    let what = match action.what {
+                        Timed::Ban => "ban",
+                        Timed::Mute => "mute",
+                    };
+                    log_action(bot,
+                               server,
+                               &format!("The {} of {} ran out.", what, user.mention()));
+                }
+                Err(ref err) if is_gone(err) => {
+                    info!("Not lifting the {:?} of {} on {}, they're gone: {:?}",
+                          action.what, user, server, err)
+                }
+                Err(err) => {
+                    warn!("Couldn't lift the {:?} of {} on {}, will try again: {:?}",
+                          action.what, user, server, err);
+                    continue;
+                }
+            }
+
+            // Only this one, it might have been replaced in the meantime.
+            update_timed_actions(bot, server, |actions| actions.retain(|x| *x != action));
+        }
+    }
+}
+
+// Whether Discord says the member or the ban isn't there, which isn't going to change.
+fn is_gone(err: &Error) -> bool {
+    match *err {
+        Error::Status(StatusCode::NotFound, _) => true,
+        _ => false,
+    }
+}
+
+// Remembers to lift the ban or the mute after the duration, replacing an earlier one.
+fn schedule(bot: &Bot, server: ServerId, what: Timed, user: UserId, duration: Duration) {
+    let until = unix_time().saturating_add(duration.as_secs());
+
+    update_timed_actions(bot, server, |actions| {
+        actions.retain(|x| x.what != what || x.user != user.0);
+        actions.push(TimedAction { what,
+                                   user: user.0,
+                                   until, });
+    });
+}
+
+fn unschedule(bot: &Bot, server: ServerId, what: Timed, user: UserId) {
+    update_timed_actions(bot, server, |actions| {
+        actions.retain(|x| x.what != what || x.user != user.0)
+    });
+}
+
+fn get_timed_actions(bot: &Bot, server: ServerId) -> Vec<TimedAction> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, TIMED_ACTIONS_KEY)
+       .unwrap_or_default()
+}
+
+fn update_timed_actions<F: FnOnce(&mut Vec<TimedAction>)>(bot: &Bot, server: ServerId, f: F) {
+    bot.get_storage(STORAGE_NAMESPACE).update(server, TIMED_ACTIONS_KEY, f);
+}
+
+fn get_warnings(bot: &Bot, server: ServerId) -> HashMap<u64, Vec<Warning>> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, WARNINGS_KEY)
+       .unwrap_or_default()
+}
+
+fn update_warnings<F, R>(bot: &Bot, server: ServerId, f: F) -> Option<R>
+    where F: FnOnce(&mut HashMap<u64, Vec<Warning>>) -> R
+{
+    bot.get_storage(STORAGE_NAMESPACE).update(server, WARNINGS_KEY, f)
+}
+
+// Sorted by the number of warnings.
+fn get_escalation(bot: &Bot, server: ServerId) -> Vec<Escalation> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, ESCALATION_KEY)
+       .unwrap_or_default()
+}
+
+fn update_escalation<F, R>(bot: &Bot, server: ServerId, f: F) -> Option<R>
+    where F: FnOnce(&mut Vec<Escalation>) -> R
+{
+    bot.get_storage(STORAGE_NAMESPACE).update(server, ESCALATION_KEY, f)
+}
+
+fn get_mod_log(bot: &Bot, server: ServerId) -> Option<ChannelId> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, MOD_LOG_KEY)
+       .map(ChannelId)
+}
+
+fn get_switches(bot: &Bot, server: ServerId) -> Switches {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, SWITCHES_KEY)
+       .unwrap_or_default()
+}
+
+fn update_switches<F: FnOnce(&mut Switches)>(bot: &Bot, server: ServerId, f: F) -> Option<()> {
+    bot.get_storage(STORAGE_NAMESPACE).update(server, SWITCHES_KEY, f)
+}
+
+fn get_channel_switches(bot: &Bot, server: ServerId) -> HashMap<u64, Switches> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, CHANNEL_SWITCHES_KEY)
+       .unwrap_or_default()
+}
+
+fn update_channel_switches<F>(bot: &Bot, server: ServerId, f: F) -> Option<()>
+    where F: FnOnce(&mut HashMap<u64, Switches>)
+{
+    bot.get_storage(STORAGE_NAMESPACE)
+       .update(server, CHANNEL_SWITCHES_KEY, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{self, Action};
+    use module::Module as ModuleTrait;
+    use modules::fun;
+    use transport::Transport;
+
+    #[test]
+    fn prefix() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!prefix");
+        fake::run(&fake, &bot, "!prefix way_too_long");
+        assert_eq!(vec!["The command prefix on this server is `!`.",
+                        "The prefix must be at most 8 characters long and cannot contain \
+                         whitespace or backticks."],
+                   fake.take_replies());
+    }
+
+    #[test]
+    fn admin_list() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!admin list");
+        assert_eq!(vec!["There are no admin roles yet."], fake.take_replies());
+
+        fake::run(&fake, &bot, "!admin add <@&30>");
+        fake::run(&fake, &bot, "!admin list");
+        assert_eq!(vec![Embed::new().title("Admin roles")
+                                    .color(ADMIN_ROLES_COLOR)
+                                    .description("- 30 `Moderators`")],
+                   fake.take_embeds());
+
+        assert_eq!(Some(vec![30u64]),
+                   bot.get_storage(STORAGE_NAMESPACE).get(fake::SERVER_ID, ADMIN_ROLES_KEY));
+
+        fake::run(&fake, &bot, "!admin remove <@&30>");
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+    }
+
+    #[test]
+    fn nuke() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        let old = MessageId(fake.generate_id());
+        fake.add_message(fake::message(old, fake::CHANNEL_ID, &fake::someone(), "old"));
+        let mine = MessageId(fake.generate_id());
+        fake.add_message(fake::message(mine, fake::CHANNEL_ID, &fake::owner(), "mine"));
+        let theirs = MessageId(fake.generate_id());
+        fake.add_message(fake::message(theirs, fake::CHANNEL_ID, &fake::someone(), "theirs"));
+
+        // Looks past mine to find two of theirs, and deletes the command too.
+        fake::run(&fake, &bot, "!nuke 2 <@3>");
+
+        match fake.take_actions().as_slice() {
+            &[Action::DeleteMessages { channel,
+                                       ref messages, }] => {
+                assert_eq!(fake::CHANNEL_ID, channel);
+                assert_eq!(&[theirs, old], &messages[..2]);
+                assert_eq!(3, messages.len());
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+    }
+
+    #[test]
+    fn nuke_100() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        for _ in 0..100 {
+            let id = MessageId(fake.generate_id());
+            fake.add_message(fake::message(id, fake::CHANNEL_ID, &fake::someone(), "spam"));
+        }
+
+        // The command doesn't fit into the bulk delete, and a bulk delete of one would fail.
+        fake::run(&fake, &bot, "!nuke 100");
+
+        match fake.take_actions().as_slice() {
+            &[Action::DeleteMessages { ref messages, .. },
+              Action::DeleteMessage { message, .. }] => {
+                assert_eq!(100, messages.len());
+                assert!(!messages.contains(&message));
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+    }
+
+    #[test]
+    fn nuke_filters() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        let ancient = MessageId(fake.generate_id());
+        let mut message = fake::message(ancient, fake::CHANNEL_ID, &fake::someone(), "ancient");
+        message.timestamp = message.timestamp - ::chrono::Duration::days(30);
+        fake.add_message(message);
+        let attachment = MessageId(fake.generate_id());
+        fake.add_message(fake::message_with_attachments(attachment,
+                                                        fake::CHANNEL_ID,
+                                                        &fake::someone(),
+                                                        "",
+                                                        &[("a.png", "https://a.png")]));
+        let link = MessageId(fake.generate_id());
+        fake.add_message(fake::message(link,
+                                       fake::CHANNEL_ID,
+                                       &fake::someone(),
+                                       "see https://example.com"));
+        let beep = MessageId(fake.generate_id());
+        fake.add_message(fake::message(beep, fake::CHANNEL_ID, &fake::bot_user(), "beep"));
+
+        fake::run(&fake, &bot, "!nuke 5 --links --dry-run");
+        fake::run(&fake, &bot, "!nuke 5 <@3> --newer-than 1h --dry-run");
+        fake::run(&fake, &bot, &format!("!nuke 5 <@3> --after {} --dry-run", attachment.0));
+        fake::run(&fake, &bot, "!nuke 5 --matching example --dry-run");
+        assert_eq!(vec!["I would delete 1 message: 1 from <@3>.",
+                        "I would delete 2 messages: 2 from <@3>.",
+                        "I would delete 1 message: 1 from <@3>.",
+                        "I would delete 1 message: 1 from <@3>."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!nuke 5 --matching (");
+        assert!(fake.take_replies()[0].starts_with("`(` isn't a valid regex"));
+
+        // Returns the messages deleted in bulk apart from the command, and the ones deleted one
+        // by one.
+        let deleted = || {
+            let mut bulk = Vec::new();
+            let mut one_by_one = Vec::new();
+
+            for action in fake.take_actions() {
+                match action {
+                    Action::DeleteMessages { mut messages, .. } => {
+                        messages.pop();
+                        bulk.extend(messages);
+                    }
+                    Action::DeleteMessage { message, .. } => one_by_one.push(message),
+                    x => panic!("unexpected action {:?}", x),
+                }
+            }
+
+            (bulk, one_by_one)
+        };
+
+        fake::run(&fake, &bot, "!nuke 1 --bots --matching beep");
+        assert_eq!((vec![beep], vec![]), deleted());
+
+        fake::run(&fake, &bot, "!nuke 5 <@3> --attachments");
+        assert_eq!((vec![attachment], vec![]), deleted());
+
+        // Too old for bulk deletion.
+        fake::run(&fake, &bot, "!nuke 5 <@3>");
+        assert_eq!((vec![link], vec![ancient]), deleted());
+
+        fake::run(&fake, &bot, "!nuke 5 <@3>");
+        assert_eq!(vec!["No messages matched."], fake.take_replies());
+    }
+
+    #[test]
+    fn not_an_admin() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake.add_message(fake::message(MessageId(fake.generate_id()),
+                                       fake::CHANNEL_ID,
+                                       &fake::owner(),
+                                       "important"));
+
+        fake::run_as(&fake, &bot, &fake::someone(), fake::CHANNEL_ID, "!nuke 5");
+        assert_eq!(Vec::<Action>::new(), fake.take_actions());
+    }
+
+    #[test]
+    fn switches() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap(),
+                                         fun::Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!disable fun");
+        assert!(!fake::run(&fake, &bot, "!roll 1"));
+
+        fake::run(&fake, &bot, "!enable !roll <#20>");
+        assert!(fake::run(&fake, &bot, "!roll 1"));
+        assert!(!fake::run_as(&fake, &bot, &fake::owner(), fake::NO_EMBEDS_CHANNEL_ID, "!roll"));
+        assert_eq!(vec!["Turned off the `Fun` module on this server.",
+                        "Turned on `!roll` in <#20>.",
+                        "<@2> rolled **0**!"],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!disabled");
+        assert_eq!(Some("**On this server**\n\
+                         - the `fun` module is off\n\
+                         **In <#20>**\n\
+                         - `!roll` is on"
+                               .to_owned()),
+                   fake.take_embeds()[0].description);
+
+        fake::run(&fake, &bot, "!disable admin");
+        fake::run(&fake, &bot, "!disable nothing");
+        fake::run(&fake, &bot, "!enable roll <#99>");
+        assert_eq!(vec!["The `Admin` module and its `!enable` and `!disable` commands can't be \
+                         turned off, there would be no way to turn things back on.",
+                        "There's no module or command called `nothing`.",
+                        "That channel isn't on this server."],
+                   fake.take_replies());
+
+        // Settings which don't change anything are left out.
+        fake::run(&fake, &bot, "!enable fun");
+        fake::run(&fake, &bot, "!enable roll <#20>");
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+    }
+
+    #[test]
+    fn switch_levels() {
+        let mut server = Switches::default();
+        let mut channel = Switches::default();
+
+        switch(&mut server, &[], "fun", Some("roll"), false);
+        switch(&mut channel, &[&server], "fun", None, false);
+        switch(&mut channel, &[&server], "fun", Some("pick"), true);
+
+        assert!(!is_on(&[&server], "fun", Some("roll")));
+        assert!(is_on(&[&server], "fun", Some("pick")));
+        assert!(!is_on(&[&channel, &server], "fun", Some("roll")));
+        assert!(is_on(&[&channel, &server], "fun", Some("pick")));
+        assert!(!is_on(&[&channel, &server], "fun", Some("info")));
+
+        // With nothing left about the module in the channel, the server's setting for the
+        // command applies again.
+        switch(&mut channel, &[&server], "fun", None, true);
+        assert!(channel.modules.is_empty());
+        assert!(!is_on(&[&channel, &server], "fun", Some("roll")));
+    }
+
+    // Makes the timed actions due right away.
+    fn expire(bot: &Bot) {
+        update_timed_actions(bot, fake::SERVER_ID, |actions| {
+            for action in actions {
+                action.until = 0;
+            }
+        });
+    }
+
+    #[test]
+    fn kick_and_ban() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!kick <@3> spam");
+        fake::run(&fake, &bot, "!ban <@3> 1d being rude");
+        assert_eq!(vec!["Kicked <@3>. Reason: spam",
+                        "Banned <@3> for 1d. Reason: being rude"],
+                   fake.take_replies());
+        assert_eq!(vec![Action::Kick { server: fake::SERVER_ID,
+                                       user: fake::USER_ID, },
+                        Action::Ban { server: fake::SERVER_ID,
+                                      user: fake::USER_ID, }],
+                   fake.take_actions());
+
+        // Not due yet.
+        bot.get_modules()[0].on_tick(&bot);
+        assert!(fake.take_actions().is_empty());
+
+        expire(&bot);
+        bot.get_modules()[0].on_tick(&bot);
+        assert_eq!(vec![Action::Unban { server: fake::SERVER_ID,
+                                        user: fake::USER_ID, }],
+                   fake.take_actions());
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+
+        // A ban for good isn't lifted.
+        fake::run(&fake, &bot, "!ban <@3> 1d");
+        fake::run(&fake, &bot, "!ban <@3>");
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+
+        fake::run(&fake, &bot, "!ban <@2>");
+        fake::run(&fake, &bot, "!kick <@1>");
+        assert_eq!(vec!["Banned <@3> for 1d.",
+                        "Banned <@3>.",
+                        "Sorry, you can't do that to an admin.",
+                        "I'm not doing that to myself."],
+                   fake.take_replies());
+    }
+
+    #[test]
+    fn mute() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+        fake.add_member(fake::member(&fake::someone(), &[fake::ROLE_ID]));
+
+        fake::run(&fake, &bot, "!mute <@3> 10m");
+        assert_eq!(vec!["Muted <@3> for 10m."], fake.take_replies());
+        assert_eq!(vec![Action::EditMemberRoles { server: fake::SERVER_ID,
+                                                  user: fake::USER_ID,
+                                                  roles: vec![fake::ROLE_ID,
+                                                              fake::MUTED_ROLE_ID], }],
+                   fake.take_actions());
+
+        expire(&bot);
+        bot.get_modules()[0].on_tick(&bot);
+        assert_eq!(vec![Action::EditMemberRoles { server: fake::SERVER_ID,
+                                                  user: fake::USER_ID,
+                                                  roles: vec![fake::ROLE_ID], }],
+                   fake.take_actions());
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+
+        fake::run(&fake, &bot, "!unmute <@3>");
+        assert_eq!(vec!["They aren't muted."], fake.take_replies());
+
+        // Someone who left can't be unmuted, so that one is dropped. Other errors might go away.
+        schedule(&bot, fake::SERVER_ID, Timed::Mute, UserId(99), Duration::from_secs(60));
+        expire(&bot);
+        bot.get_modules()[0].on_tick(&bot);
+        assert!(fake.take_actions().is_empty());
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+        assert!(!is_gone(&Error::Status(StatusCode::BadGateway, None)));
+
+        // What runs out during the grace period is lifted on shutdown.
+        fake::run(&fake, &bot, "!mute <@3> 10m");
+        expire(&bot);
+        fake.take_actions();
+        bot.shut_down();
+        assert_eq!(vec![Action::EditMemberRoles { server: fake::SERVER_ID,
+                                                  user: fake::USER_ID,
+                                                  roles: vec![fake::ROLE_ID], }],
+                   fake.take_actions());
+    }
+
+    #[test]
+    fn warnings() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+        fake.add_member(fake::member(&fake::someone(), &[]));
+
+        fake::run(&fake, &bot, "!escalation 3 kick");
+        fake::run(&fake, &bot, "!escalation 2 mute 1h");
+        fake::run(&fake, &bot, "!escalation 4 mute");
+        fake::run(&fake, &bot, "!escalation 0 ban");
+        assert_eq!(vec!["At 3 warnings: kick.",
+                        "At 2 warnings: mute for 1h.",
+                        "Mutes need a duration, like `1h`.",
+                        "The number of warnings must be at least 1."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!escalation");
+        assert_eq!(Some("- 2 warnings: mute for 1h\n- 3 warnings: kick".to_owned()),
+                   fake.take_embeds()[0].description);
+
+        fake::run(&fake, &bot, "!warn <@3> spam");
+        fake::run(&fake, &bot, "!warn <@3> more spam");
+        assert_eq!(vec!["Warned <@3>. They have 1 warning now. Reason: spam",
+                        "Warned <@3>. They have 2 warnings now. Reason: more spam",
+                        "That's 2 warnings, so I muted them for 1h."],
+                   fake.take_replies());
+        assert_eq!(vec![Action::EditMemberRoles { server: fake::SERVER_ID,
+                                                  user: fake::USER_ID,
+                                                  roles: vec![fake::MUTED_ROLE_ID], }],
+                   fake.take_actions());
+
+        fake::run(&fake, &bot, "!warnings <@3>");
+        let description = fake.take_embeds()[0].description.clone().unwrap();
+        assert!(description.starts_with("<@3> has 2 warnings:\n1. "));
+        assert!(description.ends_with(" by <@2>: more spam"));
+
+        fake::run(&fake, &bot, "!clearwarn <@3> 5");
+        fake::run(&fake, &bot, "!clearwarn <@3> 1");
+        assert_eq!(vec!["<@3> doesn't have a warning number 5.",
+                        "Removed warning number 1 of <@3>. They have 1 warning now."],
+                   fake.take_replies());
+        assert_eq!("more spam", get_warnings(&bot, fake::SERVER_ID)[&3][0].reason);
+
+        fake::run(&fake, &bot, "!clearwarn <@3>");
+        fake::run(&fake, &bot, "!warnings <@3>");
+        assert_eq!(vec!["Removed all warnings of <@3>.", "<@3> has no warnings."],
+                   fake.take_replies());
+        assert!(get_warnings(&bot, fake::SERVER_ID).is_empty());
+
+        fake::run(&fake, &bot, "!escalation 3 off");
+        fake::run(&fake, &bot, "!escalation 2 off");
+        assert_eq!(vec!["Nothing happens at 3 warnings anymore.",
+                        "Nothing happens at 2 warnings anymore."],
+                   fake.take_replies());
+        assert!(get_escalation(&bot, fake::SERVER_ID).is_empty());
+    }
+
+    fn logged(text: &str) -> Embed {
+        Embed::new().color(MOD_LOG_COLOR).description(text)
+    }
+
+    #[test]
+    fn mod_log() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+        let module = &bot.get_modules()[0];
+
+        fake::run(&fake, &bot, "!modlog");
+        fake::run(&fake, &bot, "!modlog <#21>");
+        assert_eq!(vec!["There's no mod log on this server.",
+                        "That channel isn't on this server."],
+                   fake.take_replies());
+
+        let move_here = format!("!modlog {}", fake::LOG_CHANNEL_ID.mention());
+        fake::run(&fake, &bot, &move_here);
+        match fake.take_actions().as_slice() {
+            &[Action::SendEmbed { channel,
+                                  ref embed, .. },
+              Action::Send { .. }] => {
+                assert_eq!(fake::LOG_CHANNEL_ID, channel);
+                assert_eq!(&logged("<@2> moved the mod log here."), embed);
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+
+        fake::run(&fake, &bot, "!kick <@3> spam");
+        fake::run(&fake, &bot, "!disable nuke <#20>");
+        assert_eq!(vec![logged("<@2> kicked <@3>. Reason: spam"),
+                        logged("<@2> turned off `!nuke` in <#20>.")],
+                   fake.take_embeds());
+
+        let id = MessageId(fake.generate_id());
+        let message = fake::message(id, fake::CHANNEL_ID, &fake::someone(), "hi");
+        fake.add_message(message.clone());
+        module.handle_message(&bot, &message);
+
+        // A link preview doesn't change anything.
+        module.handle_message_update(&bot, fake::CHANNEL_ID, id, "hi");
+        module.handle_message_update(&bot, fake::CHANNEL_ID, id, "hello");
+        module.handle_message_delete(&bot, fake::CHANNEL_ID, id);
+        module.handle_message_delete(&bot, fake::CHANNEL_ID, id);
+        assert_eq!(vec![Embed::new().title("Message edited")
+                                    .color(MOD_LOG_COLOR)
+                                    .description("By <@3> in <#20>")
+                                    .field("Before", "hi", false)
+                                    .field("After", "hello", false),
+                        Embed::new().title("Message deleted")
+                                    .color(MOD_LOG_COLOR)
+                                    .description("By <@3> in <#20>")
+                                    .field("Content", "hello", false)],
+                   fake.take_embeds());
+
+        fake::run(&fake, &bot, "!modlog off");
+        assert_eq!(vec![logged("<@2> turned the mod log off.")], fake.take_embeds());
+
+        // Nor once the bot is off the server.
+        module.handle_message(&bot, &message);
+        module.handle_server_delete(&bot, fake::SERVER_ID);
+        fake::run(&fake, &bot, &move_here);
+        fake.take_actions();
+        module.handle_message_delete(&bot, fake::CHANNEL_ID, id);
+        assert!(fake.take_actions().is_empty());
+
+        // Nor after a reconnect.
+        module.handle_message(&bot, &message);
+        module.on_reconnect(&bot);
+        module.handle_message_delete(&bot, fake::CHANNEL_ID, id);
+        assert!(fake.take_actions().is_empty());
+
+        // Messages aren't kept without a mod log.
+        module.handle_message(&bot, &message);
+        module.handle_message_delete(&bot, fake::CHANNEL_ID, id);
+        assert!(fake.take_actions().is_empty());
     }
 }
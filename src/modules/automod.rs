@@ -0,0 +1,964 @@
+use args::{self, Arg, Arguments, Arity, Kind, Schema};
+use bot::Bot;
+use config::ModuleConfig;
+use crashes::Recover;
+use discord::model::*;
+use embed::Embed;
+use module;
+use modules::admin;
+use regex::{self, Regex};
+use std::{collections::hash_map::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+pub struct Module<'a> {
+    commands: HashMap<u32, &'a [&'a str]>,
+    // Everyone's last message on every server and how many times in a row they sent it.
+    last_messages: Mutex<HashMap<(ServerId, UserId), (String, u64)>>,
+    // The settings of the servers which have sent messages, forgotten when they change.
+    settings: Mutex<HashMap<ServerId, Arc<Settings>>>,
+}
+
+lazy_static! {
+    static ref INVITE_REGEX: Regex =
+        Regex::new(r"(?i)(discord\.gg|discord(app)?\.com/invite)/[a-z0-9-]+").unwrap();
+}
+
+static AUTOMOD_ARGUMENTS: Schema = Schema { args: &[Arg { name: "rule",
+                                                          kind: Kind::Text,
+                                                          arity: Arity::Optional, },
+                                                    Arg { name: "action",
+                                                          kind: Kind::Text,
+                                                          arity: Arity::Optional, },
+                                                    Arg { name: "duration",
+                                                          kind: Kind::Duration,
+                                                          arity: Arity::Optional, }],
+                                            flags: &[], };
+
+static LIMIT_ARGUMENTS: Schema = Schema { args: &[Arg { name: "rule",
+                                                        kind: Kind::Text,
+                                                        arity: Arity::Required, },
+                                                  Arg { name: "limit",
+                                                        kind: Kind::Integer,
+                                                        arity: Arity::Required, }],
+                                          flags: &[], };
+
+static EXEMPT_ARGUMENTS: Schema = Schema { args: &[Arg { name: "rule",
+                                                         kind: Kind::Text,
+                                                         arity: Arity::Required, },
+                                                   Arg { name: "action",
+                                                         kind: Kind::Text,
+                                                         arity: Arity::Required, },
+                                                   Arg { name: "roles",
+                                                         kind: Kind::Role,
+                                                         arity: Arity::Many, }],
+                                           flags: &[], };
+
+static BANNED_WORDS_ARGUMENTS: Schema = Schema { args: &[Arg { name: "action",
+                                                               kind: Kind::Text,
+                                                               arity: Arity::Optional, },
+                                                         Arg { name: "word",
+                                                               kind: Kind::Rest,
+                                                               arity: Arity::Optional, }],
+                                                 flags: &[], };
+
+const STORAGE_NAMESPACE: &str = "automod";
+// Rules by name.
+const RULES_KEY: &str = "rules";
+// An array of banned words, and regexes between slashes.
+const BANNED_WORDS_KEY: &str = "banned_words";
+
+// Caps only count in messages with at least this many letters.
+const MIN_CAPS_LETTERS: usize = 10;
+// The repeated messages are forgotten once this many people are tracked.
+const MAX_TRACKED_AUTHORS: usize = 10_000;
+const DEFAULT_MUTE_SECS: u64 = 10 * 60;
+
+const RULES_COLOR: u64 = 0xe67e22;
+
+enum Commands {
+    AutoMod = 0,
+    Limit = 1,
+    Exempt = 2,
+    BannedWords = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleKind {
+    Mentions,
+    Repeats,
+    Invites,
+    Words,
+    Caps,
+}
+
+// In the order they're checked.
+static RULE_KINDS: [RuleKind; 5] = [RuleKind::Mentions,
+                                    RuleKind::Repeats,
+                                    RuleKind::Invites,
+                                    RuleKind::Words,
+                                    RuleKind::Caps];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Delete,
+    Warn,
+    Mute,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Rule {
+    // The message always gets deleted, warnings and mutes come on top of that.
+    action: Action,
+    // Seconds, for mutes.
+    #[serde(default)]
+    duration: Option<u64>,
+    // The most mentions in a message, the most times in a row the same message can be sent, or
+    // the percentage of capital letters.
+    #[serde(default)]
+    limit: Option<u64>,
+    // Role IDs the rule doesn't apply to. Admins are always left alone.
+    #[serde(default)]
+    exempt: Vec<u64>,
+}
+
+// A server's rules along with its banned words ready for matching, so that they aren't read and
+// compiled again for every message.
+struct Settings {
+    rules: HashMap<String, Rule>,
+    banned: Vec<Regex>,
+}
+
+impl RuleKind {
+    fn name(self) -> &'static str {
+        match self {
+            RuleKind::Mentions => "mentions",
+            RuleKind::Repeats => "repeats",
+            RuleKind::Invites => "invites",
+            RuleKind::Words => "words",
+            RuleKind::Caps => "caps",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        RULE_KINDS.iter().cloned().find(|x| x.name() == name)
+    }
+
+    fn default_limit(self) -> Option<u64> {
+        match self {
+            RuleKind::Mentions => Some(5),
+            RuleKind::Repeats => Some(3),
+            RuleKind::Caps => Some(70),
+            RuleKind::Invites | RuleKind::Words => None,
+        }
+    }
+
+    // For "removed because of ...".
+    fn reason(self) -> &'static str {
+        match self {
+            RuleKind::Mentions => "too many mentions",
+            RuleKind::Repeats => "repeated messages",
+            RuleKind::Invites => "an invite link",
+            RuleKind::Words => "a banned word",
+            RuleKind::Caps => "too many caps",
+        }
+    }
+
+    fn describe_limit(self, limit: u64) -> String {
+        match self {
+            RuleKind::Mentions => format!("more than {} mentions", limit),
+            RuleKind::Repeats => format!("the same message more than {} times in a row", limit),
+            RuleKind::Caps => format!("{}% caps or more", limit),
+            RuleKind::Invites | RuleKind::Words => String::new(),
+        }
+    }
+}
+
+impl Rule {
+    fn limit(&self, kind: RuleKind) -> u64 {
+        self.limit.or_else(|| kind.default_limit()).unwrap_or(0)
+    }
+
+    fn mute_duration(&self) -> Duration {
+        Duration::from_secs(self.duration.unwrap_or(DEFAULT_MUTE_SECS))
+    }
+
+    fn describe_action(&self) -> String {
+        match self.action {
+            Action::Delete => "delete".to_owned(),
+            Action::Warn => "delete and warn".to_owned(),
+            Action::Mute => {
+                format!("delete and mute for {}",
+                        args::format_duration(self.mute_duration()))
+            }
+        }
+    }
+}
+
+impl<'a> module::Module for Module<'a> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
+        let mut map: HashMap<u32, &[&str]> = HashMap::new();
+        static AUTOMOD: [&'static str; 1] = ["automod"];
+        map.insert(Commands::AutoMod as u32, &AUTOMOD);
+        static LIMIT: [&'static str; 1] = ["automodlimit"];
+        map.insert(Commands::Limit as u32, &LIMIT);
+        static EXEMPT: [&'static str; 1] = ["automodexempt"];
+        map.insert(Commands::Exempt as u32, &EXEMPT);
+        static BANNED_WORDS: [&'static str; 1] = ["bannedwords"];
+        map.insert(Commands::BannedWords as u32, &BANNED_WORDS);
+
+        Ok(Box::new(Module { commands: map,
+                             last_messages: Mutex::new(HashMap::new()),
+                             settings: Mutex::new(HashMap::new()), }))
+    }
+
+    fn name(&self) -> &'static str {
+        "AutoMod"
+    }
+
+    fn description(&self) -> &'static str {
+        "Removes spam and other unwanted messages."
+    }
+
+    fn commands(&self) -> &HashMap<u32, &[&str]> {
+        &self.commands
+    }
+
+    fn command_description(&self, id: u32) -> &'static str {
+        match id {
+            x if x == Commands::AutoMod as u32 => "Turns the automod rules on and off.",
+            x if x == Commands::Limit as u32 => "Changes the limit of an automod rule.",
+            x if x == Commands::Exempt as u32 => "Lets roles break an automod rule.",
+            x if x == Commands::BannedWords as u32 => "Manages the banned words.",
+            _ => panic!("AutoMod::command_description - invalid id."),
+        }
+    }
+
+    fn command_help_message(&self, id: u32) -> &'static str {
+        match id {
+            x if x == Commands::AutoMod as u32 => {
+                "`!automod` - Lists the automod rules turned on.\n\
+                 `!automod <rule> <delete|warn|mute> [duration]` - Turns the rule on. Messages breaking it get deleted, and with `warn` or `mute` the author also gets a warning or a mute, 10m unless a duration is given.\n\
+                 `!automod <rule> off` - Turns the rule off.\n\
+                 The rules are `mentions` (too many mentions in a message), `repeats` (the same message over and over), `invites` (Discord invite links), `words` (see `!bannedwords`) and `caps` (too many capital letters). Admins are never affected."
+            }
+            x if x == Commands::Limit as u32 => {
+                "`!automodlimit <rule> <number>` - Sets the most mentions in a message for `mentions` (5 by default), how many times in a row the same message can be sent for `repeats` (3 by default), or the percentage of capital letters for `caps` (70 by default)."
+            }
+            x if x == Commands::Exempt as u32 => {
+                "`!automodexempt <rule> add <role mentions>` - Lets people with those roles break the rule.\n\
+                 `!automodexempt <rule> remove <role mentions>` - Takes that back."
+            }
+            x if x == Commands::BannedWords as u32 => {
+                "`!bannedwords` - Lists the banned words.\n\
+                 `!bannedwords add <word>` - Bans a word or phrase, in any case. Put it between slashes to make it a regex, like `/fr[e3]{2} n[i1]tro/`; regexes don't care about case either.\n\
+                 `!bannedwords remove <word>` - Unbans it.\n\
+                 The `words` rule has to be turned on with `!automod words <action>` for them to be removed."
+            }
+            _ => panic!("AutoMod::command_help_message - invalid id."),
+        }
+    }
+
+    fn command_arguments(&self, id: u32) -> Option<&'static Schema> {
+        match id {
+            x if x == Commands::AutoMod as u32 => Some(&AUTOMOD_ARGUMENTS),
+            x if x == Commands::Limit as u32 => Some(&LIMIT_ARGUMENTS),
+            x if x == Commands::Exempt as u32 => Some(&EXEMPT_ARGUMENTS),
+            x if x == Commands::BannedWords as u32 => Some(&BANNED_WORDS_ARGUMENTS),
+            _ => None,
+        }
+    }
+
+    fn handle(&self, _bot: &Bot, _message: &Message, _id: u32, _text: &str) {
+        panic!("AutoMod::handle - invalid id.");
+    }
+
+    fn handle_with_arguments(&self, bot: &Bot, message: &Message, id: u32, args: &Arguments) {
+        let server_id = match bot.get_server_id(message.channel_id) {
+            Some(server_id) => server_id,
+            None => {
+                bot.send(message.channel_id, "The automod only works on servers.");
+                return;
+            }
+        };
+
+        if !bot.is_admin(server_id, message.author.id) {
+            return;
+        }
+
+        let reply = match id {
+            x if x == Commands::AutoMod as u32 => self.handle_automod(bot, server_id, args),
+            x if x == Commands::Limit as u32 => self.handle_limit(bot, server_id, args),
+            x if x == Commands::Exempt as u32 => self.handle_exempt(bot, server_id, args),
+            x if x == Commands::BannedWords as u32 => {
+                self.handle_banned_words(bot, server_id, args)
+            }
+            _ => panic!("AutoMod::handle_with_arguments - invalid id."),
+        };
+
+        match reply {
+            Reply::Changed(text) => {
+                self.settings.lock().recover().remove(&server_id);
+                admin::log_action(bot,
+                                  server_id,
+                                  &format!("{} changed the automod. {}",
+                                           message.author.mention(),
+                                           text));
+                bot.send(message.channel_id, &text);
+            }
+            Reply::Text(text) => bot.send(message.channel_id, &text),
+            Reply::Embed(embed) => bot.send_embed(message.channel_id, &embed),
+            Reply::Help => {
+                bot.send(message.channel_id,
+                         <Module as module::Module>::command_help_message(&self, id))
+            }
+        }
+    }
+
+    fn handle_message(&self, bot: &Bot, message: &Message) {
+        if message.author.bot {
+            return;
+        }
+
+        let server_id = match bot.get_server_id(message.channel_id) {
+            Some(server_id) => server_id,
+            None => return,
+        };
+
+        let settings = self.settings(bot, server_id);
+        let rules = &settings.rules;
+        if rules.is_empty() {
+            return;
+        }
+
+        let repeats = if rules.contains_key(RuleKind::Repeats.name()) {
+            self.count_repeats(server_id, message)
+        } else {
+            0
+        };
+
+        let broken = RULE_KINDS.iter()
+                               .filter_map(|&kind| rules.get(kind.name()).map(|x| (kind, x)))
+                               .find(|&(kind, rule)| {
+                                         breaks(kind,
+                                                rule.limit(kind),
+                                                message,
+                                                repeats,
+                                                &settings.banned)
+                                         && !is_exempt(bot, server_id, message.author.id, rule)
+                                     });
+
+        if let Some((kind, rule)) = broken {
+            enforce(bot, server_id, message, kind, rule);
+        }
+    }
+
+    fn handle_server_delete(&self, bot: &Bot, server: ServerId) {
+        bot.get_storage(STORAGE_NAMESPACE).clear(server);
+        self.settings.lock().recover().remove(&server);
+        self.last_messages
+            .lock()
+            .recover()
+            .retain(|&(x, _), _| x != server);
+    }
+}
+
+// What to reply to a settings command.
+enum Reply {
+    // The settings were changed, which also goes to the mod log.
+    Changed(String),
+    Text(String),
+    Embed(Embed),
+    Help,
+}
+
+impl<'a> Module<'a> {
+    fn settings(&self, bot: &Bot, server: ServerId) -> Arc<Settings> {
+        // Loaded under the lock, so that a change made in the meantime can't get overwritten
+        // with what was there before it.
+        self.settings
+            .lock()
+            .recover()
+            .entry(server)
+            .or_insert_with(|| {
+                let rules = get_rules(bot, server);
+                let banned = if rules.contains_key(RuleKind::Words.name()) {
+                    get_banned_words(bot, server).iter()
+                                                 .filter_map(|x| compile_banned_word(x).ok())
+                                                 .collect()
+                } else {
+                    Vec::new()
+                };
+
+                Arc::new(Settings { rules, banned })
+            })
+            .clone()
+    }
+
+    // How many times in a row the author has sent this message, counting this one.
+    fn count_repeats(&self, server: ServerId, message: &Message) -> u64 {
+        let content = message.content.trim().to_lowercase();
+        if content.is_empty() {
+            return 0;
+        }
+
+        let key = (server, message.author.id);
+        let mut last_messages = self.last_messages.lock().recover();
+        if last_messages.len() >= MAX_TRACKED_AUTHORS && !last_messages.contains_key(&key) {
+            last_messages.clear();
+        }
+
+        let last = last_messages.entry(key)
+                                .or_insert_with(|| (String::new(), 0));
+        if last.0 == content {
+            last.1 += 1;
+        } else {
+            *last = (content, 1);
+        }
+
+        last.1
+    }
+
+    fn handle_automod(&self, bot: &Bot, server: ServerId, args: &Arguments) -> Reply {
+        let name = match args.text("rule") {
+            Some(name) => name,
+            None => return list_rules(&get_rules(bot, server)),
+        };
+        let kind = match RuleKind::from_name(name) {
+            Some(kind) => kind,
+            None => return no_such_rule(name),
+        };
+        let action = match args.text("action") {
+            Some(action) => action.to_lowercase(),
+            None => return Reply::Help,
+        };
+
+        let duration = args.duration("duration").map(|x| x.as_secs());
+        let action = match action.as_str() {
+            "delete" => Action::Delete,
+            "warn" => Action::Warn,
+            "mute" => Action::Mute,
+            "off" => {
+                return update_rules(bot, server, |rules| {
+                    if rules.remove(kind.name()).is_none() {
+                        return Reply::Text(format!("The `{}` rule is already off.", kind.name()));
+                    }
+
+                    Reply::Changed(format!("Turned the `{}` rule off.", kind.name()))
+                })
+            }
+            _ => {
+                return Reply::Text("The action must be `delete`, `warn`, `mute` or `off`."
+                                   .to_owned())
+            }
+        };
+
+        if duration.is_some() && action != Action::Mute {
+            return Reply::Text("Only mutes have a duration.".to_owned());
+        }
+
+        update_rules(bot, server, |rules| {
+            // Keep the limit and the exempt roles.
+            let rule = rules.entry(kind.name().to_owned())
+                            .or_insert_with(|| {
+                                                Rule { action,
+                                                       duration: None,
+                                                       limit: None,
+                                                       exempt: Vec::new(), }
+                                            });
+            rule.action = action;
+            rule.duration = duration;
+
+            Reply::Changed(format!("The `{}` rule is on: {}.",
+                                   kind.name(),
+                                   rule.describe_action()))
+        })
+    }
+
+    fn handle_limit(&self, bot: &Bot, server: ServerId, args: &Arguments) -> Reply {
+        let name = args.text("rule").unwrap();
+        let kind = match RuleKind::from_name(name) {
+            Some(kind) => kind,
+            None => return no_such_rule(name),
+        };
+
+        if kind.default_limit().is_none() {
+            return Reply::Text(format!("The `{}` rule doesn't have a limit.", kind.name()));
+        }
+
+        let limit = args.integer("limit").unwrap();
+        if kind == RuleKind::Caps && (limit < 1 || limit > 100) {
+            return Reply::Text("The limit must be from 1 to 100.".to_owned());
+        } else if limit < 1 {
+            return Reply::Text("The limit must be at least 1.".to_owned());
+        }
+
+        update_rules(bot, server, |rules| {
+            match rules.get_mut(kind.name()) {
+                Some(rule) => rule.limit = Some(limit as u64),
+                None => return rule_is_off(kind),
+            }
+
+            Reply::Changed(format!("The `{}` rule is now about {}.",
+                                   kind.name(),
+                                   kind.describe_limit(limit as u64)))
+        })
+    }
+
+    fn handle_exempt(&self, bot: &Bot, server: ServerId, args: &Arguments) -> Reply {
+        let name = args.text("rule").unwrap();
+        let kind = match RuleKind::from_name(name) {
+            Some(kind) => kind,
+            None => return no_such_rule(name),
+        };
+
+        let roles = args.roles("roles");
+        if roles.is_empty() {
+            return Reply::Text("You didn't mention any roles.".to_owned());
+        }
+        let mentions = roles.iter()
+                            .map(|x| x.mention().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+        update_rules(bot, server, |rules| {
+            let rule = match rules.get_mut(kind.name()) {
+                Some(rule) => rule,
+                None => return rule_is_off(kind),
+            };
+
+            match args.text("action").unwrap().to_lowercase().as_str() {
+                "add" => {
+                    for role in &roles {
+                        if !rule.exempt.contains(&role.0) {
+                            rule.exempt.push(role.0);
+                        }
+                    }
+
+                    Reply::Changed(format!("The `{}` rule doesn't apply to {} anymore.",
+                                           kind.name(),
+                                           mentions))
+                }
+                "remove" => {
+                    rule.exempt.retain(|x| roles.iter().all(|r| r.0 != *x));
+                    Reply::Changed(format!("The `{}` rule applies to {} again.",
+                                           kind.name(),
+                                           mentions))
+                }
+                _ => Reply::Help,
+            }
+        })
+    }
+
+    fn handle_banned_words(&self, bot: &Bot, server: ServerId, args: &Arguments) -> Reply {
+        let action = args.text("action").map(str::to_lowercase);
+        let word = args.text("word");
+
+        match (action.as_ref().map(String::as_str), word) {
+            (None, _) | (Some("list"), None) => {
+                let words = get_banned_words(bot, server);
+                if words.is_empty() {
+                    return Reply::Text("There are no banned words yet.".to_owned());
+                }
+
+                let lines = words.iter()
+                                 .map(|x| format!("- `{}`", x))
+                                 .collect::<Vec<_>>();
+                Reply::Embed(Embed::new().title("Banned words")
+                                         .color(RULES_COLOR)
+                                         .description(&lines.join("\n")))
+            }
+
+            (Some("add"), Some(word)) => {
+                if let Err(err) = compile_banned_word(word) {
+                    return Reply::Text(format!("`{}` isn't a valid regex: {}", word, err));
+                }
+
+                update_banned_words(bot, server, |words| {
+                    if words.iter().any(|x| x == word) {
+                        return Reply::Text(format!("`{}` is already banned.", word));
+                    }
+
+                    words.push(word.to_owned());
+                    Reply::Changed(format!("Banned `{}`.", word))
+                })
+            }
+
+            (Some("remove"), Some(word)) => {
+                update_banned_words(bot, server, |words| {
+                    let count = words.len();
+                    words.retain(|x| x != word);
+                    if words.len() == count {
+                        return Reply::Text(format!("`{}` isn't banned.", word));
+                    }
+
+                    Reply::Changed(format!("Unbanned `{}`.", word))
+                })
+            }
+
+            _ => Reply::Help,
+        }
+    }
+}
+
+fn list_rules(rules: &HashMap<String, Rule>) -> Reply {
+    let lines = RULE_KINDS.iter()
+                          .filter_map(|&kind| {
+                                          rules.get(kind.name()).map(|x| describe_rule(kind, x))
+                                      })
+                          .collect::<Vec<_>>();
+
+    if lines.is_empty() {
+        Reply::Text("There are no automod rules on this server.".to_owned())
+    } else {
+        Reply::Embed(Embed::new().title("Automod rules")
+                                 .color(RULES_COLOR)
+                                 .description(&lines.join("\n")))
+    }
+}
+
+// "- `caps` (70% caps or more): delete, except for @Moderators".
+fn describe_rule(kind: RuleKind, rule: &Rule) -> String {
+    let limit = kind.describe_limit(rule.limit(kind));
+    let limit = if limit.is_empty() {
+        limit
+    } else {
+        format!(" ({})", limit)
+    };
+
+    let exempt = if rule.exempt.is_empty() {
+        String::new()
+    } else {
+        let roles = rule.exempt
+                        .iter()
+                        .map(|&x| RoleId(x).mention().to_string())
+                        .collect::<Vec<_>>();
+        format!(", except for {}", roles.join(", "))
+    };
+
+    format!("- `{}`{}: {}{}", kind.name(), limit, rule.describe_action(), exempt)
+}
+
+fn no_such_rule(name: &str) -> Reply {
+    Reply::Text(format!("There's no rule called `{}`. The rules are `mentions`, `repeats`, \
+                         `invites`, `words` and `caps`.",
+                        name))
+}
+
+fn rule_is_off(kind: RuleKind) -> Reply {
+    Reply::Text(format!("Turn the `{}` rule on first.", kind.name()))
+}
+
+// Words match whole words, `/regex/` is a regex. Neither cares about case.
+fn compile_banned_word(word: &str) -> Result<Regex, regex::Error> {
+    if word.len() > 2 && word.starts_with('/') && word.ends_with('/') {
+        Regex::new(&format!("(?i){}", &word[1..word.len() - 1]))
+    } else {
+        Regex::new(&format!(r"(?i)\b{}\b", regex::escape(word)))
+    }
+}
+
+fn is_shouting(text: &str, percent: u64) -> bool {
+    let (letters, capitals) = text.chars()
+                                  .filter(|x| x.is_alphabetic())
+                                  .fold((0, 0), |(letters, capitals), x| {
+                                      (letters + 1, capitals + x.is_uppercase() as usize)
+                                  });
+
+    letters >= MIN_CAPS_LETTERS && capitals as u64 * 100 >= percent * letters as u64
+}
+
+// Repeats are counted by the module, since they depend on the earlier messages.
+fn breaks(kind: RuleKind, limit: u64, message: &Message, repeats: u64, banned: &[Regex]) -> bool {
+    match kind {
+        RuleKind::Mentions => (message.mentions.len() + message.mention_roles.len()) as u64 > limit,
+        RuleKind::Repeats => repeats > limit,
+        RuleKind::Invites => INVITE_REGEX.is_match(&message.content),
+        RuleKind::Words => banned.iter().any(|x| x.is_match(&message.content)),
+        RuleKind::Caps => is_shouting(&message.content, limit),
+    }
+}
+
+fn is_exempt(bot: &Bot, server: ServerId, user: UserId, rule: &Rule) -> bool {
+    if bot.is_admin(server, user) {
+        return true;
+    }
+
+    if rule.exempt.is_empty() {
+        return false;
+    }
+
+    bot.get_member(server, user)
+       .map(|member| member.roles.iter().any(|x| rule.exempt.contains(&x.0)))
+       .unwrap_or(false)
+}
+
+fn enforce(bot: &Bot, server: ServerId, message: &Message, kind: RuleKind, rule: &Rule) {
+    let user = message.author.id;
+    info!("Automod: {} broke the {} rule on {}.", user, kind.name(), server);
+
+    bot.delete_message(message.channel_id, message.id);
+
+    let removed = format!("{}, your message was removed because of {}",
+                          user.mention(),
+                          kind.reason());
+    let logged = format!("Removed a message from {} in {} because of {}",
+                         user.mention(),
+                         message.channel_id.mention(),
+                         kind.reason());
+
+    match rule.action {
+        Action::Delete => {
+            admin::log_action(bot, server, &format!("{}.", logged));
+            bot.send(message.channel_id, &format!("{}.", removed));
+        }
+
+        Action::Warn => {
+            let bot_id = bot.get_state().read().recover().user().id;
+            let count = admin::warn(bot,
+                                    server,
+                                    user,
+                                    bot_id,
+                                    &format!("Automod: {}", kind.reason()));
+
+            admin::log_action(bot, server, &format!("{} and warned them.", logged));
+            bot.send(message.channel_id,
+                     &format!("{}, and you got a warning.", removed));
+
+            if let Some(reply) = count.and_then(|x| admin::escalate(bot, server, user, x)) {
+                bot.send(message.channel_id, &reply);
+            }
+        }
+
+        Action::Mute => {
+            let duration = rule.mute_duration();
+
+            match admin::mute(bot, server, user, duration) {
+                Ok(()) => {
+                    let duration = args::format_duration(duration);
+                    admin::log_action(bot,
+                                      server,
+                                      &format!("{} and muted them for {}.", logged, duration));
+                    bot.send(message.channel_id,
+                             &format!("{}, and you're muted for {}.", removed, duration));
+                }
+                Err(err) => {
+                    admin::log_action(bot, server, &format!("{}.", logged));
+                    bot.send(message.channel_id, &format!("{}.", removed));
+                    bot.send(message.channel_id, &err);
+                }
+            }
+        }
+    }
+}
+
+fn get_rules(bot: &Bot, server: ServerId) -> HashMap<String, Rule> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, RULES_KEY)
+       .unwrap_or_default()
+}
+
+// Changes the rules in one go and returns f's reply.
+fn update_rules<F>(bot: &Bot, server: ServerId, f: F) -> Reply
+    where F: FnOnce(&mut HashMap<String, Rule>) -> Reply
+{
+    bot.get_storage(STORAGE_NAMESPACE)
+       .update(server, RULES_KEY, f)
+       .unwrap_or_else(not_saved)
+}
+
+fn get_banned_words(bot: &Bot, server: ServerId) -> Vec<String> {
+    bot.get_storage(STORAGE_NAMESPACE)
+       .get(server, BANNED_WORDS_KEY)
+       .unwrap_or_default()
+}
+
+fn update_banned_words<F>(bot: &Bot, server: ServerId, f: F) -> Reply
+    where F: FnOnce(&mut Vec<String>) -> Reply
+{
+    bot.get_storage(STORAGE_NAMESPACE)
+       .update(server, BANNED_WORDS_KEY, f)
+       .unwrap_or_else(not_saved)
+}
+
+fn not_saved() -> Reply {
+    Reply::Text("Sorry, I couldn't save the automod settings.".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{self, Fake};
+    use module::Module as ModuleTrait;
+
+    fn bot() -> (::std::sync::Arc<Fake>, ::std::sync::Arc<Bot>) {
+        fake::bot(vec![admin::Module::new(&ModuleConfig::default()).unwrap(),
+                       Module::new(&ModuleConfig::default()).unwrap()])
+    }
+
+    // Sends the message like it came from Discord and returns what the bot did about it.
+    fn send(fake: &Fake, bot: &Bot, author: &User, text: &str) -> Vec<fake::Action> {
+        let message = fake::message(MessageId(fake.generate_id()), fake::CHANNEL_ID, author, text);
+        fake.add_message(message.clone());
+
+        bot.for_each_module(|module| module.handle_message(bot, &message));
+        fake.take_actions()
+    }
+
+    fn deleted(actions: &[fake::Action]) -> bool {
+        actions.iter().any(|x| match *x {
+                                   fake::Action::DeleteMessage { .. } => true,
+                                   _ => false,
+                               })
+    }
+
+    fn replies(actions: Vec<fake::Action>) -> Vec<String> {
+        actions.into_iter()
+               .filter_map(|x| match x {
+                               fake::Action::Send { text, .. } => Some(text),
+                               _ => None,
+                           })
+               .collect()
+    }
+
+    #[test]
+    fn checks() {
+        assert!(is_shouting("WHY IS EVERYTHING ON FIRE", 70));
+        assert!(!is_shouting("WHY", 70));
+        assert!(!is_shouting("Why Is Everything On Fire", 70));
+
+        assert!(INVITE_REGEX.is_match("join us at https://discord.gg/abc-123"));
+        assert!(INVITE_REGEX.is_match("discordapp.com/invite/abc"));
+        assert!(!INVITE_REGEX.is_match("the discord is great"));
+
+        let word = compile_banned_word("heck").unwrap();
+        assert!(word.is_match("what the HECK"));
+        assert!(!word.is_match("heckin"));
+        let regex = compile_banned_word("/fr[e3]{2}/").unwrap();
+        assert!(regex.is_match("fr33 stuff"));
+        assert!(compile_banned_word("/FR[e3]{2}/").unwrap().is_match("fr33"));
+        assert!(compile_banned_word("/(/").is_err());
+    }
+
+    #[test]
+    fn message_stream() {
+        let (fake, bot) = bot();
+        fake.add_member(fake::member(&fake::someone(), &[]));
+
+        fake::run(&fake, &bot, "!automod caps delete");
+        fake::run(&fake, &bot, "!automod invites warn");
+        fake::run(&fake, &bot, "!automod repeats mute 5m");
+        fake::run(&fake, &bot, "!automod mentions delete");
+        fake::run(&fake, &bot, "!automodlimit mentions 2");
+        fake::run(&fake, &bot, "!automod words delete");
+        fake::run(&fake, &bot, "!bannedwords add heck");
+        assert_eq!(vec!["The `caps` rule is on: delete.",
+                        "The `invites` rule is on: delete and warn.",
+                        "The `repeats` rule is on: delete and mute for 5m.",
+                        "The `mentions` rule is on: delete.",
+                        "The `mentions` rule is now about more than 2 mentions.",
+                        "The `words` rule is on: delete.",
+                        "Banned `heck`."],
+                   fake.take_replies());
+
+        let someone = fake::someone();
+        assert!(send(&fake, &bot, &someone, "hello there").is_empty());
+        assert!(send(&fake, &bot, &someone, "heckin good").is_empty());
+        assert!(send(&fake, &bot, &someone, "<@1> <@2>").is_empty());
+
+        let actions = send(&fake, &bot, &someone, "WHY IS EVERYTHING ON FIRE");
+        assert!(deleted(&actions));
+        assert_eq!(vec!["<@3>, your message was removed because of too many caps."],
+                   replies(actions));
+
+        let actions = send(&fake, &bot, &someone, "<@1> <@2> <@4>");
+        assert_eq!(vec!["<@3>, your message was removed because of too many mentions."],
+                   replies(actions));
+
+        let actions = send(&fake, &bot, &someone, "what the heck");
+        assert_eq!(vec!["<@3>, your message was removed because of a banned word."],
+                   replies(actions));
+        fake::run(&fake, &bot, "!bannedwords remove heck");
+        assert_eq!(vec!["Unbanned `heck`."], fake.take_replies());
+        assert!(send(&fake, &bot, &someone, "what the heck").is_empty());
+
+        let actions = send(&fake, &bot, &someone, "join discord.gg/abc");
+        assert_eq!(vec!["<@3>, your message was removed because of an invite link, and you \
+                         got a warning."],
+                   replies(actions));
+
+        assert!(send(&fake, &bot, &someone, "spam").is_empty());
+        assert!(send(&fake, &bot, &someone, "Spam").is_empty());
+        assert!(send(&fake, &bot, &someone, "spam").is_empty());
+        let actions = send(&fake, &bot, &someone, "spam");
+        let muted = fake::Action::EditMemberRoles { server: fake::SERVER_ID,
+                                                    user: fake::USER_ID,
+                                                    roles: vec![fake::MUTED_ROLE_ID], };
+        assert!(actions.contains(&muted));
+        assert_eq!(vec!["<@3>, your message was removed because of repeated messages, and \
+                         you're muted for 5m."],
+                   replies(actions));
+
+        // Admins are left alone.
+        assert!(send(&fake, &bot, &fake::owner(), "WHY IS EVERYTHING ON FIRE").is_empty());
+    }
+
+    #[test]
+    fn exempt_roles() {
+        let (fake, bot) = bot();
+        let moderator = fake::user(UserId(4), "Moderator");
+        fake.add_member(fake::member(&moderator, &[fake::ROLE_ID]));
+
+        fake::run(&fake, &bot, "!automod caps delete");
+        fake::run(&fake, &bot, "!automodexempt caps add <@&30>");
+        fake.take_actions();
+        assert!(send(&fake, &bot, &moderator, "WHY IS EVERYTHING ON FIRE").is_empty());
+
+        fake::run(&fake, &bot, "!automodexempt caps remove <@&30>");
+        fake.take_actions();
+        assert!(deleted(&send(&fake, &bot, &moderator, "WHY IS EVERYTHING ON FIRE")));
+    }
+
+    #[test]
+    fn settings() {
+        let (fake, bot) = bot();
+
+        fake::run(&fake, &bot, "!automod");
+        fake::run(&fake, &bot, "!automod spam delete");
+        fake::run(&fake, &bot, "!automod caps explode");
+        fake::run(&fake, &bot, "!automod caps delete 5m");
+        fake::run(&fake, &bot, "!automodlimit caps 10");
+        fake::run(&fake, &bot, "!automodlimit invites 10");
+        assert_eq!(vec!["There are no automod rules on this server.",
+                        "There's no rule called `spam`. The rules are `mentions`, `repeats`, \
+                         `invites`, `words` and `caps`.",
+                        "The action must be `delete`, `warn`, `mute` or `off`.",
+                        "Only mutes have a duration.",
+                        "Turn the `caps` rule on first.",
+                        "The `invites` rule doesn't have a limit."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!bannedwords add /(/");
+        assert!(fake.take_replies()[0].starts_with("`/(/` isn't a valid regex: "));
+
+        fake::run(&fake, &bot, "!automod caps mute");
+        fake::run(&fake, &bot, "!automodlimit caps 101");
+        fake::run(&fake, &bot, "!automodexempt caps add <@&30>");
+        assert_eq!(vec!["The `caps` rule is on: delete and mute for 10m.",
+                        "The limit must be from 1 to 100.",
+                        "The `caps` rule doesn't apply to <@&30> anymore."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!automod");
+        let expected = "- `caps` (70% caps or more): delete and mute for 10m, except for <@&30>";
+        assert_eq!(Some(expected.to_owned()), fake.take_embeds().remove(0).description);
+
+        // Not for everyone.
+        fake::run_as(&fake, &bot, &fake::someone(), fake::CHANNEL_ID, "!automod caps off");
+        assert!(fake.take_actions().is_empty());
+
+        fake::run(&fake, &bot, "!automod caps off");
+        assert_eq!(vec!["Turned the `caps` rule off."], fake.take_replies());
+        assert_eq!(Vec::<ServerId>::new(), bot.get_storage(STORAGE_NAMESPACE).servers());
+    }
+}
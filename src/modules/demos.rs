@@ -1,10 +1,14 @@
 use bot::Bot;
+use config::ModuleConfig;
 use discord::model::Message;
+use embed::Embed;
 use hldemo;
 use hyper::Client;
 use module;
 use std::{collections::hash_map::HashMap, io::Read};
 
+const DEMO_COLOR: u64 = 0xff8c00;
+
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
 }
@@ -13,7 +17,7 @@ pub struct Module<'a> {
 // }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         let map: HashMap<u32, &[&str]> = HashMap::new();
         Ok(Box::new(Module { commands: map }))
     }
@@ -47,15 +51,15 @@ impl<'a> module::Module for Module<'a> {
                                  .iter()
                                  .filter(|x| x.filename.ends_with(".dem"))
         {
-            match process_demo_url(&attachment.url) {
-                Ok(string) => bot.send(message.channel_id, &string),
-                Err(err) => println!("Demos::handle_attachment error: {}", err),
+            match process_demo_url(&attachment.filename, &attachment.url) {
+                Ok(embed) => bot.send_embed(message.channel_id, &embed),
+                Err(err) => warn!("Couldn't process {}: {}", attachment.filename, err),
             }
         }
     }
 }
 
-fn process_demo_url(url: &str) -> Result<String, String> {
+fn process_demo_url(filename: &str, url: &str) -> Result<Embed, String> {
     let client = Client::new();
     let mut res = client.get(url)
                         .send()
@@ -75,23 +79,36 @@ fn process_demo_url(url: &str) -> Result<String, String> {
                    .filter(|e| e.entry_type != 0)
                    .fold(0f32, |acc, e| acc + e.track_time);
 
-    let mut result = "```\n".to_string();
-
-    result.push_str(&format!("Game: {}\n",
-                             String::from_utf8_lossy(demo.header
-                                                         .game_dir
-                                                         .split(|&x| x == 0)
-                                                         .next()
-                                                         .unwrap())));
-    result.push_str(&format!("Map:  {}\n",
-                             String::from_utf8_lossy(demo.header
-                                                         .map_name
-                                                         .split(|&x| x == 0)
-                                                         .next()
-                                                         .unwrap())));
-    result.push_str(&format!("Time: {:.3}s\n", time));
-
-    result.push_str("```");
-
-    Ok(result)
+    // The header strings are padded with zeros.
+    let string = |bytes: &[u8]| {
+        String::from_utf8_lossy(bytes.split(|&x| x == 0).next().unwrap()).into_owned()
+    };
+
+    Ok(Embed::new().title(filename)
+                   .color(DEMO_COLOR)
+                   .field("Game", &string(&demo.header.game_dir), true)
+                   .field("Map", &string(&demo.header.map_name), true)
+                   .field("Time", &format!("{:.3}s", time), true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discord::model::MessageId;
+    use fake;
+    use module::Module as ModuleTrait;
+
+    #[test]
+    fn ignores_other_attachments() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+        let message = fake::message_with_attachments(MessageId(fake.generate_id()),
+                                                     fake::CHANNEL_ID,
+                                                     &fake::owner(),
+                                                     "",
+                                                     &[("screenshot.png",
+                                                        "http://localhost/screenshot.png")]);
+
+        bot.get_modules()[0].handle_attachment(&bot, &message);
+        assert!(fake.take_actions().is_empty());
+    }
 }
@@ -1,32 +1,24 @@
+use args::{Arg, Arguments, Arity, Kind, Schema};
 use bot::Bot;
-use circular_queue::CircularQueue;
+use config::ModuleConfig;
+use cooldowns::{Cooldown, Scope};
+use crashes::Recover;
 use discord::{model::*, ChannelRef};
+use embed::Embed;
 use module;
 use rand::{self, Rng};
 use regex::Regex;
-use std::{char, collections::hash_map::HashMap, sync::RwLock};
-
-const COMMAND_MESSAGE_QUEUE_SIZE: usize = 64;
-
-#[derive(Debug, Clone, Copy)]
-struct CommandMessage {
-    /// Message with the command.
-    command: MessageId,
-    /// Author of the message with the command.
-    author: UserId,
-    /// Message the bot sent as a response.
-    output: MessageId,
-}
+use std::{char, collections::hash_map::HashMap};
+
+const INFO_COLOR: u64 = 0x7289da;
 
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
-    command_messages: RwLock<HashMap<ChannelId, CircularQueue<CommandMessage>>>,
 }
 
 lazy_static! {
     static ref TEMPERATURE_REGEX: Regex =
         Regex::new(r"\s*([+-]?[0-9]+(\.[0-9]*)?)\s*([CcFf]).*").unwrap();
-    static ref ROLL_REGEX: Regex = Regex::new(r"\s*(([0-9]+)(\s|$))?.*").unwrap();
     static ref ROOM_ALLOW_PERMS: Permissions = permissions::VOICE_CONNECT
                                                | permissions::VOICE_SPEAK
                                                | permissions::MANAGE_CHANNELS
@@ -34,6 +26,17 @@ lazy_static! {
     static ref ROOM_DENY_PERMS: Permissions = permissions::VOICE_CONNECT;
 }
 
+static ROLL_ARGUMENTS: Schema = Schema { args: &[Arg { name: "high",
+                                                       kind: Kind::Integer,
+                                                       arity: Arity::Optional, }],
+                                         flags: &[], };
+
+// Every room is a new voice channel on the server.
+static ROOM_COOLDOWNS: [Cooldown; 2] = [Cooldown { scope: Scope::User,
+                                                   seconds: 5 * 60, },
+                                        Cooldown { scope: Scope::Server,
+                                                   seconds: 30, }];
+
 enum Commands {
     Fraktur = 0,
     Temperature = 1,
@@ -46,7 +49,7 @@ enum Commands {
 }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         static FRAKTUR: [&'static str; 1] = ["fraktur"];
         map.insert(Commands::Fraktur as u32, &FRAKTUR);
@@ -64,9 +67,7 @@ impl<'a> module::Module for Module<'a> {
         map.insert(Commands::Aesthetic as u32, &AESTHETIC);
         static SMALLCAPS: [&'static str; 1] = ["smallcaps"];
         map.insert(Commands::Smallcaps as u32, &SMALLCAPS);
-        Ok(Box::new(Module { commands: map,
-                             command_messages:
-                                 RwLock::new(HashMap::new()), }))
+        Ok(Box::new(Module { commands: map }))
     }
 
     fn name(&self) -> &'static str {
@@ -133,11 +134,24 @@ impl<'a> module::Module for Module<'a> {
         }
     }
 
+    fn command_cooldowns(&self, id: u32) -> &'static [Cooldown] {
+        match id {
+            x if x == Commands::Room as u32 => &ROOM_COOLDOWNS,
+            _ => &[],
+        }
+    }
+
+    fn command_arguments(&self, id: u32) -> Option<&'static Schema> {
+        match id {
+            x if x == Commands::Roll as u32 => Some(&ROLL_ARGUMENTS),
+            _ => None,
+        }
+    }
+
     fn handle(&self, bot: &Bot, message: &Message, id: u32, text: &str) {
         match id {
             x if x == Commands::Fraktur as u32 => self.handle_fraktur(bot, message, text),
             x if x == Commands::Temperature as u32 => self.handle_temperature(bot, message, text),
-            x if x == Commands::Roll as u32 => self.handle_roll(bot, message, text),
             x if x == Commands::Pick as u32 => self.handle_pick(bot, message, text),
             x if x == Commands::Info as u32 => self.handle_info(bot, message, text),
             x if x == Commands::Room as u32 => self.handle_room(bot, message, text),
@@ -147,88 +161,28 @@ impl<'a> module::Module for Module<'a> {
         }
     }
 
-    fn handle_message_update(&self, bot: &Bot, channel_id: ChannelId, id: MessageId) {
-        if let Some(output) = self.find_command_message(channel_id, id) {
-            if let Ok(message) = bot.get_message(channel_id, output.output) {
-                bot.edit(channel_id,
-                         output.output,
-                         &format!("{:.2000}",
-                                  &format!("{} said: {}",
-                                           output.author.mention(),
-                                           message.content)));
-            }
-        }
-    }
-
-    fn handle_message_delete(&self, bot: &Bot, channel_id: ChannelId, id: MessageId) {
-        if let Some(output) = self.find_command_message(channel_id, id) {
-            if let Ok(message) = bot.get_message(channel_id, output.output) {
-                bot.edit(channel_id,
-                         output.output,
-                         &format!("{:.2000}",
-                                  &format!("{} said: {}",
-                                           output.author.mention(),
-                                           message.content)));
-            }
+    fn handle_with_arguments(&self, bot: &Bot, message: &Message, id: u32, args: &Arguments) {
+        match id {
+            x if x == Commands::Roll as u32 => self.handle_roll(bot, message, args),
+            _ => panic!("Fun::handle_with_arguments - invalid id."),
         }
     }
 }
 
 impl<'a> Module<'a> {
-    fn remember_command_message(&self,
-                                channel_id: ChannelId,
-                                command_id: MessageId,
-                                author: UserId,
-                                output_id: MessageId) {
-        self.command_messages
-            .write()
-            .unwrap()
-            .entry(channel_id)
-            .or_insert_with(|| CircularQueue::with_capacity(COMMAND_MESSAGE_QUEUE_SIZE))
-            .push(CommandMessage { command: command_id,
-                                   author,
-                                   output: output_id, });
-    }
-
-    fn find_command_message(&self,
-                            channel_id: ChannelId,
-                            command_id: MessageId)
-                            -> Option<CommandMessage> {
-        self.command_messages
-            .read()
-            .unwrap()
-            .get(&channel_id)
-            .and_then(|x| x.iter().find(|x| x.command == command_id).cloned())
-    }
-
     fn handle_fraktur(&self, bot: &Bot, message: &Message, text: &str) {
         let reply = text.chars().map(frakturize).collect::<String>();
-        if let Some(output) = bot.send_and_get(message.channel_id, &reply) {
-            self.remember_command_message(message.channel_id,
-                                          message.id,
-                                          message.author.id,
-                                          output.id);
-        }
+        bot.send(message.channel_id, &reply);
     }
 
     fn handle_aesthetic(&self, bot: &Bot, message: &Message, text: &str) {
         let reply = text.chars().map(make_fullwidth).collect::<String>();
-        if let Some(output) = bot.send_and_get(message.channel_id, &reply) {
-            self.remember_command_message(message.channel_id,
-                                          message.id,
-                                          message.author.id,
-                                          output.id);
-        }
+        bot.send(message.channel_id, &reply);
     }
 
     fn handle_smallcaps(&self, bot: &Bot, message: &Message, text: &str) {
         let reply = text.chars().map(make_smallcaps).collect::<String>();
-        if let Some(output) = bot.send_and_get(message.channel_id, &reply) {
-            self.remember_command_message(message.channel_id,
-                                          message.id,
-                                          message.author.id,
-                                          output.id);
-        }
+        bot.send(message.channel_id, &reply);
     }
 
     fn handle_temperature(&self, bot: &Bot, message: &Message, text: &str) {
@@ -262,11 +216,9 @@ impl<'a> Module<'a> {
         }
     }
 
-    fn handle_roll(&self, bot: &Bot, message: &Message, text: &str) {
-        let caps = ROLL_REGEX.captures(text).unwrap();
-        let max = caps.get(2)
-                      .and_then(|x| x.as_str().parse::<u64>().ok())
-                      .map(|x| if x == 0 { 100 } else { x })
+    fn handle_roll(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        let max = args.integer("high")
+                      .map(|x| if x <= 0 { 100 } else { x as u64 })
                       .unwrap_or(100);
 
         let number = rand::thread_rng().gen_range(0, max);
@@ -291,45 +243,50 @@ impl<'a> Module<'a> {
     }
 
     fn handle_info(&self, bot: &Bot, message: &Message, _text: &str) {
-        match bot.get_state()
-                 .read()
-                 .unwrap()
-                 .find_channel(message.channel_id)
+        let embed = match bot.get_state()
+                             .read()
+                             .recover()
+                             .find_channel(message.channel_id)
         {
             Some(ChannelRef::Private(channel)) => {
                 bot.send(message.channel_id, &format!("```{:#?}```", channel));
+                return;
             }
 
             Some(ChannelRef::Public(server, channel)) => {
-                let mut buf = format!("```Server ID: {},\n\
-                                       Owner ID: {},\n\
-                                       Member count: {},\n\
-                                       Icon: {},\n\
-                                       Roles:",
-                                      server.id.0,
-                                      server.owner_id.0,
-                                      server.member_count,
-                                      if let Some(ref icon) = server.icon {
-                                          &icon
-                                      } else {
-                                          "N/A"
-                                      });
-
-                if server.roles.is_empty() {
-                    buf.push_str(" N/A");
+                let roles = if server.roles.is_empty() {
+                    "N/A".to_owned()
                 } else {
-                    for role in &server.roles {
-                        buf.push_str(&format!("\n- {} '{}'", role.id.0, role.name));
+                    server.roles
+                          .iter()
+                          .map(|role| format!("{} '{}'", role.id.0, role.name))
+                          .collect::<Vec<_>>()
+                          .join("\n")
+                };
+
+                let embed = Embed::new().title(&server.name)
+                                        .color(INFO_COLOR)
+                                        .field("Server ID", &server.id.0.to_string(), true)
+                                        .field("Owner ID", &server.owner_id.0.to_string(), true)
+                                        .field("Member count",
+                                               &server.member_count.to_string(),
+                                               true)
+                                        .field("Roles", &roles, false)
+                                        .field("Channel ID", &channel.id.0.to_string(), true);
+
+                match server.icon {
+                    Some(ref icon) => {
+                        embed.thumbnail(&format!("https://cdn.discordapp.com/icons/{}/{}.png",
+                                                 server.id.0,
+                                                 icon))
                     }
+                    None => embed,
                 }
-
-                buf.push_str(&format!("\n\nChannel ID: {}```", channel.id.0));
-
-                bot.send(message.channel_id, &buf);
             }
 
             Some(ChannelRef::Group(group)) => {
                 bot.send(message.channel_id, &format!("```{:#?}```", group));
+                return;
             }
 
             None => {
@@ -337,14 +294,18 @@ impl<'a> Module<'a> {
                     message.channel_id,
                     "Huh, I couldn't get this channel's info for some reason. Try again I guess?",
                 );
+                return;
             }
-        }
+        };
+
+        // Sending an embed looks at the state, so it has to be unlocked by now.
+        bot.send_embed(message.channel_id, &embed);
     }
 
     fn handle_room(&self, bot: &Bot, message: &Message, _text: &str) {
         match bot.get_state()
                  .read()
-                 .unwrap()
+                 .recover()
                  .find_channel(message.channel_id)
         {
             Some(ChannelRef::Private(_)) | Some(ChannelRef::Group(_)) => {
@@ -466,3 +427,100 @@ fn make_smallcaps(c: char) -> char {
 
     c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{self, Action};
+    use module::Module as ModuleTrait;
+    use modules::admin;
+
+    #[test]
+    fn text_transforms() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!fraktur Hello");
+        fake::run(&fake, &bot, "!aesthetic Hi!");
+        fake::run(&fake, &bot, "!smallcaps Hello");
+        assert_eq!(vec!["𝕳𝔢𝔩𝔩𝔬", "Ｈｉ！", "ʜello"], fake.take_replies());
+    }
+
+    #[test]
+    fn temperature() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!temp 5C");
+        fake::run(&fake, &bot, "!temp -40 f");
+        assert_eq!(vec!["5.00°C is **41.00**°F.", "-40.00°F is **-40.00**°C."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!temp hot");
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].starts_with("`!temperature <number> <C or F>`"));
+    }
+
+    #[test]
+    fn roll_and_pick() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!roll 1");
+        fake::run(&fake, &bot, "!pick one;one");
+        assert_eq!(vec!["<@2> rolled **0**!", "<@2>: I pick one!"], fake.take_replies());
+    }
+
+    #[test]
+    fn info() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!info");
+        let embeds = fake.take_embeds();
+        assert_eq!(1, embeds.len());
+        assert_eq!(Some("Test Server".to_owned()), embeds[0].title);
+        assert!(embeds[0].fields
+                         .iter()
+                         .any(|x| x.name == "Roles" && x.value.contains("30 'Moderators'")));
+
+        fake::run_as(&fake, &bot, &fake::owner(), fake::NO_EMBEDS_CHANNEL_ID, "!info");
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].starts_with("**Test Server**\n**Server ID**: 10\n"));
+    }
+
+    #[test]
+    fn room() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!room <@3>");
+        let actions = fake.take_actions();
+
+        // The room itself, then the permissions for everyone, the author and the mention.
+        assert_eq!(4, actions.len());
+        match actions[0] {
+            Action::CreateChannel { server, .. } => assert_eq!(fake::SERVER_ID, server),
+            ref x => panic!("unexpected action {:?}", x),
+        }
+        assert!(actions[1..].iter().all(|x| match *x {
+                                                Action::CreatePermission { .. } => true,
+                                                _ => false,
+                                            }));
+    }
+
+    #[test]
+    fn room_cooldowns() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap(),
+                                         admin::Module::new(&ModuleConfig::default()).unwrap()]);
+
+        // The owner is an admin, so they neither wait for the cooldowns nor start them.
+        fake::run(&fake, &bot, "!room <@3>");
+        fake::run(&fake, &bot, "!room <@3>");
+        assert_eq!(8, fake.take_actions().len());
+
+        let someone = fake::someone();
+        fake::run_as(&fake, &bot, &someone, fake::CHANNEL_ID, "!room <@2>");
+        assert_eq!(4, fake.take_actions().len());
+        fake::run_as(&fake, &bot, &someone, fake::CHANNEL_ID, "!room <@2>");
+        assert_eq!(vec!["Slow down! You can use this command again in 5m."],
+                   fake.take_replies());
+    }
+}
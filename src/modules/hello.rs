@@ -1,4 +1,5 @@
 use bot::Bot;
+use config::ModuleConfig;
 use discord::model::Message;
 use module;
 use rand::{self, Rng};
@@ -13,7 +14,7 @@ enum Commands {
 }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         static HELLO: [&'static str; 2] = ["hello", "hi"];
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         map.insert(Commands::Hello as u32, &HELLO);
@@ -51,3 +52,21 @@ impl<'a> module::Module for Module<'a> {
                  &format!("Hi, {}! {}", message.author.mention(), emoji));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake;
+    use module::Module as ModuleTrait;
+
+    #[test]
+    fn hello() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        assert!(fake::run(&fake, &bot, "!hi"));
+
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].starts_with("Hi, <@2>! "));
+    }
+}
@@ -1,19 +1,18 @@
 use bot::Bot;
+use config::ModuleConfig;
 use discord::model::Message;
 use module;
-use std::{collections::hash_map::HashMap, env};
+use std::collections::hash_map::HashMap;
 
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
+    invite_link: String,
 }
 
-lazy_static! {
-    static ref INVITE_LINK: Result<String, String> = env::var("YALTER_BOT_CLIENT_ID")
-        .map_err(|_| "Please set the YALTER_BOT_CLIENT_ID environment variable".to_string())
-        .map(|client_id| format!(
-            "https://discordapp.com/oauth2/authorize?client_id={}&scope=bot&permissions=271707152",
-            client_id
-        ));
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    client_id: Option<String>,
 }
 
 enum Commands {
@@ -21,13 +20,23 @@ enum Commands {
 }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         static INVITE: [&'static str; 1] = ["invite"];
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         map.insert(Commands::Invite as u32, &INVITE);
-        INVITE_LINK.as_ref()
-                   .map_err(|s| s.clone())
-                   .and(Ok(Box::new(Module { commands: map })))
+
+        let client_id = config.parse::<Config>()?.client_id.ok_or_else(|| {
+            "Please set client_id in [modules.invite] or the YALTER_BOT_CLIENT_ID environment \
+             variable"
+                .to_string()
+        })?;
+
+        let invite_link = format!("https://discordapp.com/oauth2/authorize?client_id={}\
+                                   &scope=bot&permissions=271707152",
+                                  client_id);
+
+        Ok(Box::new(Module { commands: map,
+                             invite_link, }))
     }
 
     fn name(&self) -> &'static str {
@@ -53,7 +62,32 @@ impl<'a> module::Module for Module<'a> {
     fn handle(&self, bot: &Bot, message: &Message, _id: u32, _text: &str) {
         bot.send_pm(message.author.id,
                     &format!("Follow this link to invite the bot to your server: {}",
-                             INVITE_LINK.as_ref().unwrap()),
+                             self.invite_link),
                     message.channel_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{self, Action};
+    use module::Module as ModuleTrait;
+
+    #[test]
+    fn invite() {
+        assert!(Module::new(&ModuleConfig::default()).is_err());
+
+        let config = ModuleConfig::from_toml("invite", "client_id = \"1234\"");
+        let (fake, bot) = fake::bot(vec![Module::new(&config).unwrap()]);
+        fake::run(&fake, &bot, "!invite");
+
+        match fake.take_actions().as_slice() {
+            &[Action::Send { channel,
+                             ref text, }] => {
+                assert_eq!(fake::PRIVATE_CHANNEL_ID, channel);
+                assert!(text.contains("https://discordapp.com/oauth2/authorize?client_id=1234&"));
+            }
+            x => panic!("unexpected actions {:?}", x),
+        }
+    }
+}
@@ -1,8 +1,12 @@
-use bot::Bot;
+use bot::{Bot, DEFAULT_COMMAND_PREFIX};
+use config::ModuleConfig;
 use discord::model::Message;
 use module;
+use pages::paginate;
 use std::{cmp::Ordering, collections::hash_map::HashMap};
 
+const COMMANDS_PER_PAGE: usize = 10;
+
 struct Command<'a> {
     module: &'a module::Module,
     id: u32,
@@ -33,11 +37,27 @@ enum Commands {
     Command = 2,
 }
 
+// Help messages are written with the default prefix, replace it with the one actually used.
+fn with_prefix(text: &str, prefix: &str) -> String {
+    text.replace(&format!("`{}", DEFAULT_COMMAND_PREFIX), &format!("`{}", prefix))
+}
+
 impl<'a> Module<'a> {
     fn handle_modules(&self, bot: &Bot, message: &Message, text: &str) {
+        let server = bot.get_server_id(message.channel_id);
+        let available = |m: &module::Module, id: u32| {
+            !bot.is_command_turned_off(server, message.channel_id, m, id)
+        };
+
         if text.is_empty() {
             let mut buf = "List of available modules:".to_string();
             for m in bot.get_modules() {
+                // Modules with all of their commands turned off aren't available here.
+                if !m.commands().is_empty() && !m.commands().keys().any(|&id| available(&**m, id))
+                {
+                    continue;
+                }
+
                 buf.push_str(format!("\n- `{}`: {}", m.name(), m.description()).as_str());
             }
 
@@ -45,41 +65,56 @@ impl<'a> Module<'a> {
             return;
         }
 
+        let prefix = bot.get_command_prefix(server);
         let text_lc = text.to_lowercase();
 
         for m in bot.get_modules() {
             if m.name().to_lowercase() == text_lc {
-                let mut buf = format!("`{}`: {}", m.name(), m.description());
+                let header = format!("`{}`: {}", m.name(), m.description());
 
                 let mut commands: Vec<Command> = Vec::new();
                 for (&id, &cmds) in m.commands() {
-                    commands.push(Command { module: &**m,
-                                            id,
-                                            names: &cmds, });
+                    if available(&**m, id) {
+                        commands.push(Command { module: &**m,
+                                                id,
+                                                names: &cmds, });
+                    }
                 }
 
-                if commands.is_empty() {
-                    buf.push_str("\nThere are no commands defined by this module.");
+                if m.commands().is_empty() {
+                    bot.send(message.channel_id,
+                             &format!("{}\nThere are no commands defined by this module.",
+                                      header));
+                } else if commands.is_empty() {
+                    bot.send(message.channel_id,
+                             &format!("{}\nAll of its commands are turned off here.", header));
                 } else {
                     commands.sort_by(|a, b| a.cmp(b));
 
-                    buf.push_str("\nCommand list:");
+                    let mut lines = Vec::new();
                     for c in commands {
+                        let mut buf = String::new();
+
                         let mut first = true;
                         for alias in c.names {
                             if first {
                                 first = false;
-                                buf.push_str(format!("\n- `!{}`", alias).as_str());
+                                buf.push_str(format!("- `{}{}`", prefix, alias).as_str());
                             } else {
-                                buf.push_str(format!(", `!{}`", alias).as_str());
+                                buf.push_str(format!(", `{}{}`", prefix, alias).as_str());
                             }
                         }
 
                         buf.push_str(format!(": {}", c.module.command_description(c.id)).as_str());
+                        lines.push(buf);
                     }
+
+                    bot.send_paginated(message.channel_id,
+                                       paginate(&format!("{}\nCommand list:", header),
+                                                &lines,
+                                                COMMANDS_PER_PAGE));
                 }
 
-                bot.send(message.channel_id, &buf);
                 return;
             }
         }
@@ -89,9 +124,16 @@ impl<'a> Module<'a> {
     }
 
     fn handle_commands(&self, bot: &Bot, message: &Message, _text: &str) {
+        let server = bot.get_server_id(message.channel_id);
+        let prefix = bot.get_command_prefix(server);
+
         let mut commands: Vec<Command> = Vec::new();
         for m in bot.get_modules() {
             for (&id, &cmds) in m.commands() {
+                if bot.is_command_turned_off(server, message.channel_id, &**m, id) {
+                    continue;
+                }
+
                 commands.push(Command { module: &**m,
                                         id,
                                         names: &cmds, });
@@ -100,29 +142,37 @@ impl<'a> Module<'a> {
 
         commands.sort_by(|a, b| a.cmp(b));
 
-        let mut buf = "Available commands:".to_string();
+        let mut lines = Vec::new();
         for c in commands {
+            let mut buf = String::new();
+
             let mut first = true;
             for alias in c.names {
                 if first {
                     first = false;
-                    buf.push_str(format!("\n- `!{}`", alias).as_str());
+                    buf.push_str(format!("- `{}{}`", prefix, alias).as_str());
                 } else {
-                    buf.push_str(format!(", `!{}`", alias).as_str());
+                    buf.push_str(format!(", `{}{}`", prefix, alias).as_str());
                 }
             }
 
             buf.push_str(format!(" (module `{}`): {}",
                                  c.module.name(),
                                  c.module.command_description(c.id)).as_str());
+            lines.push(buf);
         }
 
-        bot.send(message.channel_id, &buf);
+        bot.send_paginated(message.channel_id,
+                           paginate("Available commands:", &lines, COMMANDS_PER_PAGE));
     }
 
     fn handle_command(&self, bot: &Bot, message: &Message, text: &str) {
-        let text = if text.starts_with('!') {
-            text.split_at(1).1
+        let prefix = bot.get_command_prefix_for_channel(message.channel_id);
+
+        let text = if text.starts_with(prefix.as_str()) {
+            text.split_at(prefix.len()).1
+        } else if text.starts_with(DEFAULT_COMMAND_PREFIX) {
+            text.split_at(DEFAULT_COMMAND_PREFIX.len()).1
         } else {
             text
         };
@@ -130,13 +180,14 @@ impl<'a> Module<'a> {
         if text.is_empty() {
             bot.send(message.channel_id,
                      &format!("Bot version {} using **discord-rs**.\n\
-                               `!mods` - list modules!\n\
-                               `!mod <name>` - list commands of a module!\n\
-                               `!help <command>` - help for a command!\n\
+                               `{prefix}mods` - list modules!\n\
+                               `{prefix}mod <name>` - list commands of a module!\n\
+                               `{prefix}help <command>` - help for a command!\n\
                                \n\
                                Or simply:\n\
-                               `!commands` - list all commands!",
-                              env!("CARGO_PKG_VERSION")));
+                               `{prefix}commands` - list all commands!",
+                              env!("CARGO_PKG_VERSION"),
+                              prefix = prefix));
             return;
         }
 
@@ -151,17 +202,28 @@ impl<'a> Module<'a> {
                             buf.push_str("\n\n");
                         }
 
-                        buf.push_str(format!("`!{}`", text).as_str());
+                        buf.push_str(format!("`{}{}`", prefix, text).as_str());
 
                         for alias in cmds {
                             if *alias != text {
-                                buf.push_str(format!(", `!{}`", alias).as_str());
+                                buf.push_str(format!(", `{}{}`", prefix, alias).as_str());
                             }
                         }
 
                         buf.push_str(format!(": {}\n{}",
                                              m.command_description(id),
-                                             m.command_help_message(id)).as_str());
+                                             with_prefix(m.command_help_message(id),
+                                                         &prefix)).as_str());
+
+                        let cooldowns = m.command_cooldowns(id);
+                        if !cooldowns.is_empty() {
+                            buf.push_str("\nCooldown: ");
+                            buf.push_str(&cooldowns.iter()
+                                                   .map(|x| x.to_string())
+                                                   .collect::<Vec<_>>()
+                                                   .join(", "));
+                            buf.push('.');
+                        }
                         break;
                     }
                 }
@@ -170,8 +232,8 @@ impl<'a> Module<'a> {
 
         if buf.is_empty() {
             bot.send(message.channel_id,
-                     format!("Could not find the `!{}` command in any of the modules!",
-                             text).as_str());
+                     format!("Could not find the `{}{}` command in any of the modules!",
+                             prefix, text).as_str());
         } else {
             bot.send(message.channel_id, buf.as_str());
         }
@@ -179,7 +241,7 @@ impl<'a> Module<'a> {
 }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         static MODULES: [&'static str; 4] = ["modules", "module", "mods", "mod"];
         map.insert(Commands::Modules as u32, &MODULES);
@@ -234,3 +296,94 @@ impl<'a> module::Module for Module<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::{self, Action};
+    use module::Module as ModuleTrait;
+    use modules::{admin, fun, hello};
+
+    fn bot() -> (::std::sync::Arc<fake::Fake>, ::std::sync::Arc<Bot>) {
+        fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap(),
+                       hello::Module::new(&ModuleConfig::default()).unwrap(),
+                       fun::Module::new(&ModuleConfig::default()).unwrap()])
+    }
+
+    #[test]
+    fn modules() {
+        let (fake, bot) = bot();
+
+        fake::run(&fake, &bot, "!modules");
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].contains("- `Hello`: Provides the !hello command."));
+
+        fake::run(&fake, &bot, "!mod hello");
+        assert_eq!(vec!["`Hello`: Provides the !hello command.\n\
+                         Command list:\n\
+                         - `!hello`, `!hi`: Prints a greeting message."],
+                   fake.take_replies());
+    }
+
+    #[test]
+    fn commands_are_paginated() {
+        let (fake, bot) = bot();
+
+        fake::run(&fake, &bot, "!commands");
+        let actions = fake.take_actions();
+
+        // One page of commands and the two arrows.
+        assert_eq!(3, actions.len());
+        match actions[0] {
+            Action::Send { ref text, .. } => {
+                assert!(text.starts_with("Available commands:\n- `!aesthetic`"));
+                assert!(text.ends_with("`Page 1/2`"));
+            }
+            ref x => panic!("unexpected action {:?}", x),
+        }
+        match actions[1] {
+            Action::AddReaction { .. } => {}
+            ref x => panic!("unexpected action {:?}", x),
+        }
+    }
+
+    #[test]
+    fn help() {
+        let (fake, bot) = bot();
+
+        fake::run(&fake, &bot, "!help hi");
+        assert_eq!(vec!["`!hi`, `!hello`: Prints a greeting message.\n\
+                         `!hello` - Prints a greeting message."],
+                   fake.take_replies());
+
+        fake::run(&fake, &bot, "!help nothing");
+        assert_eq!(vec!["Could not find the `!nothing` command in any of the modules!"],
+                   fake.take_replies());
+    }
+
+    #[test]
+    fn turned_off() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap(),
+                                         hello::Module::new(&ModuleConfig::default()).unwrap(),
+                                         admin::Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!disable hello <#20>");
+        fake.take_replies();
+
+        fake::run(&fake, &bot, "!modules");
+        fake::run(&fake, &bot, "!mod hello");
+        let replies = fake.take_replies();
+        assert!(!replies[0].contains("Hello"));
+        assert_eq!("`Hello`: Provides the !hello command.\n\
+                    All of its commands are turned off here.",
+                   replies[1]);
+
+        fake::run(&fake, &bot, "!commands");
+        assert!(!fake.take_replies()[0].contains("`!hello`"));
+
+        // Only in that channel.
+        fake::run_as(&fake, &bot, &fake::owner(), fake::NO_EMBEDS_CHANNEL_ID, "!commands");
+        assert!(fake.take_replies()[0].contains("`!hello`"));
+    }
+}
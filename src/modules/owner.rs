@@ -0,0 +1,123 @@
+use bot::{Bot, Shutdown};
+use config::ModuleConfig;
+use discord::model::Message;
+use module;
+use std::collections::hash_map::HashMap;
+
+pub struct Module<'a> {
+    commands: HashMap<u32, &'a [&'a str]>,
+}
+
+enum Commands {
+    Shutdown = 0,
+    Restart = 1,
+    Status = 2,
+}
+
+impl<'a> module::Module for Module<'a> {
+    fn new(_config: &ModuleConfig) -> Result<Box<module::Module>, String> {
+        let mut map: HashMap<u32, &[&str]> = HashMap::new();
+        static SHUTDOWN: [&'static str; 1] = ["shutdown"];
+        map.insert(Commands::Shutdown as u32, &SHUTDOWN);
+        static RESTART: [&'static str; 1] = ["restart"];
+        map.insert(Commands::Restart as u32, &RESTART);
+        static STATUS: [&'static str; 1] = ["status"];
+        map.insert(Commands::Status as u32, &STATUS);
+
+        Ok(Box::new(Module { commands: map }))
+    }
+
+    fn name(&self) -> &'static str {
+        "Owner"
+    }
+
+    fn description(&self) -> &'static str {
+        "Commands for the owners of the bot."
+    }
+
+    fn commands(&self) -> &HashMap<u32, &[&str]> {
+        &self.commands
+    }
+
+    fn command_description(&self, id: u32) -> &'static str {
+        match id {
+            x if x == Commands::Shutdown as u32 => "Stops the bot.",
+            x if x == Commands::Restart as u32 => "Restarts the bot.",
+            x if x == Commands::Status as u32 => "Shows what the bot is busy with.",
+            _ => panic!("Owner::command_description - invalid id."),
+        }
+    }
+
+    fn command_help_message(&self, id: u32) -> &'static str {
+        match id {
+            x if x == Commands::Shutdown as u32 => {
+                "`!shutdown` - Finishes the running commands and stops the bot. Only for the \
+                 owners of the bot."
+            }
+            x if x == Commands::Restart as u32 => {
+                "`!restart` - Finishes the running commands and restarts the bot. Only for the \
+                 owners of the bot."
+            }
+            x if x == Commands::Status as u32 => {
+                "`!status` - Shows how many requests to Discord are waiting to be sent. Only for \
+                 the owners of the bot."
+            }
+            _ => panic!("Owner::command_help_message - invalid id."),
+        }
+    }
+
+    fn handle(&self, bot: &Bot, message: &Message, id: u32, _text: &str) {
+        if !bot.is_owner(message.author.id) {
+            bot.send(message.channel_id,
+                     "Sorry, only the owners of the bot can do that.");
+            return;
+        }
+
+        // The reply also wakes up the main loop, which notices the request on the next event.
+        match id {
+            x if x == Commands::Shutdown as u32 => {
+                bot.send(message.channel_id, "Shutting down. 👋");
+                bot.request_shutdown(Shutdown::Exit);
+            }
+            x if x == Commands::Restart as u32 => {
+                bot.send(message.channel_id, "Restarting, be right back.");
+                bot.request_shutdown(Shutdown::Restart);
+            }
+            x if x == Commands::Status as u32 => {
+                bot.send(message.channel_id,
+                         &format!("Requests to Discord in the queue: {}.",
+                                  bot.get_queue_depth()));
+            }
+            _ => panic!("Owner::handle - invalid id."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake;
+    use module::Module as ModuleTrait;
+
+    #[test]
+    fn owner_only() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run_as(&fake, &bot, &fake::someone(), fake::CHANNEL_ID, "!shutdown");
+        assert_eq!(vec!["Sorry, only the owners of the bot can do that."],
+                   fake.take_replies());
+        assert_eq!(None, bot.get_shutdown());
+
+        fake::run(&fake, &bot, "!status");
+        assert_eq!(vec!["Requests to Discord in the queue: 0."], fake.take_replies());
+
+        fake::run(&fake, &bot, "!restart");
+        fake::run(&fake, &bot, "!shutdown");
+        assert_eq!(vec!["Restarting, be right back.", "I'm shutting down, try again in a bit."],
+                   fake.take_replies());
+
+        // Only the first request counts.
+        bot.request_shutdown(Shutdown::Exit);
+        assert_eq!(Some(Shutdown::Restart), bot.get_shutdown());
+    }
+}
@@ -1,8 +1,11 @@
+use args::{Arg, Arguments, Arity, Kind, Schema};
 use bot::Bot;
+use config::ModuleConfig;
+use cooldowns::{Cooldown, Scope};
 use discord::model::Message;
+use embed::Embed;
 use hyper::{self, header::UserAgent, Client};
 use module;
-use regex::Regex;
 use serde_json;
 use std::{
     collections::{hash_map::HashMap, BTreeMap},
@@ -13,17 +16,52 @@ use url::Url;
 
 include!("speedruncom_types.rs");
 
+const WRS_PER_PAGE: usize = 10;
+const PBS_PER_PAGE: usize = 10;
+
+const SPEEDRUN_COLOR: u64 = 0xf0c419;
+
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
+    api_base: Url,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    // Relative paths are joined onto this, so it needs the trailing slash.
+    api_base: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { api_base: "https://www.speedrun.com/api/v1/".to_owned() }
+    }
 }
 
 lazy_static! {
-    static ref SPEEDRUNCOM_API_BASE: Url = Url::parse("https://www.speedrun.com/api/v1/").unwrap();
     static ref USERAGENT: UserAgent =
         UserAgent(concat!("yalter-bot/", env!("CARGO_PKG_VERSION")).to_string());
-    static ref PB_REGEX: Regex = Regex::new(r"\s*(\S+)\s+(.*)").unwrap();
 }
 
+static WR_ARGUMENTS: Schema = Schema { args: &[Arg { name: "game",
+                                                     kind: Kind::Rest,
+                                                     arity: Arity::Required, }],
+                                       flags: &[], };
+
+static PB_ARGUMENTS: Schema = Schema { args: &[Arg { name: "player",
+                                                     kind: Kind::Text,
+                                                     arity: Arity::Required, },
+                                               Arg { name: "game",
+                                                     kind: Kind::Rest,
+                                                     arity: Arity::Required, }],
+                                       flags: &[], };
+
+static COOLDOWNS: [Cooldown; 2] = [Cooldown { scope: Scope::User,
+                                              seconds: 15, },
+                                   Cooldown { scope: Scope::Channel,
+                                              seconds: 5, }];
+
 #[derive(Debug)]
 enum MyError {
     Network(hyper::error::Error),
@@ -97,13 +135,19 @@ enum Commands {
 }
 
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         static WR: [&'static str; 2] = ["worldrecord", "wr"];
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         map.insert(Commands::WR as u32, &WR);
         static PB: [&'static str; 2] = ["personalbest", "pb"];
         map.insert(Commands::PB as u32, &PB);
-        Ok(Box::new(Module { commands: map }))
+
+        let config = config.parse::<Config>()?;
+        let api_base =
+            Url::parse(&config.api_base).map_err(|err| format!("Invalid api_base: {}", err))?;
+
+        Ok(Box::new(Module { commands: map,
+                             api_base, }))
     }
 
     fn name(&self) -> &'static str {
@@ -118,6 +162,11 @@ impl<'a> module::Module for Module<'a> {
         &self.commands
     }
 
+    // !wr makes a request per category, keep speedrun.com happy.
+    fn max_concurrent_handlers(&self) -> usize {
+        2
+    }
+
     fn command_description(&self, id: u32) -> &'static str {
         match id {
             x if x == Commands::WR as u32 => "Shows the world record times.",
@@ -132,119 +181,151 @@ impl<'a> module::Module for Module<'a> {
                 "`!wr <game>` - Shows the world record times for all categories for the given game. For example, `!wr Half-Life`."
             }
             x if x == Commands::PB as u32 => {
-                "`!pb <player> <game>` - Shows player's personal bests in the given game. For example, `!pb YaLTeR Half-Life`."
+                "`!pb <player> <game>` - Shows player's personal bests in the given game. For example, `!pb YaLTeR Half-Life`. Put the player name in quotes if it has spaces in it."
             }
             _ => panic!("Speedrun::command_help_message - invalid id."),
         }
     }
 
-    fn handle(&self, bot: &Bot, message: &Message, id: u32, text: &str) {
+    fn command_cooldowns(&self, _: u32) -> &'static [Cooldown] {
+        &COOLDOWNS
+    }
+
+    fn command_arguments(&self, id: u32) -> Option<&'static Schema> {
+        match id {
+            x if x == Commands::WR as u32 => Some(&WR_ARGUMENTS),
+            x if x == Commands::PB as u32 => Some(&PB_ARGUMENTS),
+            _ => None,
+        }
+    }
+
+    fn handle(&self, _bot: &Bot, _message: &Message, _id: u32, _text: &str) {
+        panic!("Speedrun::handle - invalid id.");
+    }
+
+    fn handle_with_arguments(&self, bot: &Bot, message: &Message, id: u32, args: &Arguments) {
         match id {
-            x if x == Commands::WR as u32 => self.handle_wr(&bot, &message, &text),
-            x if x == Commands::PB as u32 => self.handle_pb(&bot, &message, &text),
-            _ => panic!("Speedrun::handle - invalid id."),
+            x if x == Commands::WR as u32 => self.handle_wr(&bot, &message, args),
+            x if x == Commands::PB as u32 => self.handle_pb(&bot, &message, args),
+            _ => panic!("Speedrun::handle_with_arguments - invalid id."),
         }
     }
 }
 
 impl<'a> Module<'a> {
-    fn handle_wr(&self, bot: &Bot, message: &Message, text: &str) {
-        bot.send(message.channel_id,
-                 match get_wrs(&text) {
-                     Ok((game, wrs)) => {
-                         if wrs.is_empty() {
-                             format!("**{}** has no world records. :|", game)
-                         } else {
-                             let mut buf = format!("World records for **{}**:", game);
-                             for mut wr in wrs {
-                                 buf.push_str(&format!("\n{}", wr.category));
-
-                                 if let Some(subcategory) = wr.subcategory {
-                                     buf.push_str(&format!(" ({})", subcategory));
-                                 }
-
-                                 buf.push_str(&format!(": **{}** by {}",
-                                                       format_time(&wr.time),
-                                                       wr.players[0]));
-
-                                 wr.players.remove(0);
-                                 if let Some(last_player) = wr.players.pop() {
-                                     for player in wr.players {
-                                         buf.push_str(&format!(", {}", player));
-                                     }
-                                     buf.push_str(&format!(" and {}", last_player));
-                                 }
-
-                                 buf.push('!');
-                             }
-                             buf
-                         }
-                     }
-                     Err(MyError::Network(err)) => {
-                         format!("Couldn't communicate with https://www.speedrun.com. :( ({})",
-                                 err)
-                     }
-                     Err(MyError::NoSuchGame) => {
-                         "There's no such game on speedrun.com! :O".to_string()
-                     }
-                     Err(err) => format!("Something's broken. :/ ({})", err),
-                 }.as_str());
-    }
-
-    fn handle_pb(&self, bot: &Bot, message: &Message, text: &str) {
-        if let Some(caps) = PB_REGEX.captures(text) {
+    fn handle_wr(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        let (game, wrs) = match get_wrs(&self.api_base, args.text("game").unwrap()) {
+            Ok(x) => x,
+            Err(MyError::Network(err)) => {
+                bot.send(message.channel_id,
+                         &format!("Couldn't communicate with https://www.speedrun.com. :( ({})",
+                                  err));
+                return;
+            }
+            Err(MyError::NoSuchGame) => {
+                bot.send(message.channel_id, "There's no such game on speedrun.com! :O");
+                return;
+            }
+            Err(err) => {
+                bot.send(message.channel_id, &format!("Something's broken. :/ ({})", err));
+                return;
+            }
+        };
+
+        if wrs.is_empty() {
             bot.send(message.channel_id,
-                     match get_pbs(caps.get(1).unwrap().as_str(), caps.get(2).unwrap().as_str()) {
-                         Ok((player, game, mut pbs)) => {
-                             if pbs.is_empty() {
-                                 format!("**{}** has no personal bests in **{}**. :|", player, game)
-                             } else {
-                                 let mut buf =
-                                     format!("**{}**'s personal bests in **{}**:", player, game);
-
-                                 pbs.sort_by_key(|x| x.category.clone());
-
-                                 for pb in pbs {
-                                     buf.push_str(&format!("\n{}", pb.category));
-
-                                     if !pb.subcategories.is_empty() {
-                                         buf.push_str(&format!(" ({}", pb.subcategories[0]));
-
-                                         for subcategory in pb.subcategories.into_iter().skip(1) {
-                                             buf.push_str(&format!(", {}", subcategory));
-                                         }
-
-                                         buf.push_str(")");
-                                     }
-
-                                     buf.push_str(&format!(": **{}** - {}",
-                                                           format_time(&pb.time),
-                                                           pb.place));
-                                     buf.push_str(&number_suffix(pb.place));
-
-                                     if pb.place == 1 {
-                                         buf.push_str(" 🏆");
-                                     }
-                                 }
-                                 buf
+                     &format!("**{}** has no world records. :|", game.name));
+            return;
+        }
+
+        let title = format!("World records for {}", game.name);
+        let pages = wrs.chunks(WRS_PER_PAGE)
+                       .map(|wrs| {
+                                wrs.iter().fold(game.embed(&title), |embed, wr| {
+                                    let mut name = wr.category.clone();
+                                    if let Some(ref subcategory) = wr.subcategory {
+                                        name.push_str(&format!(" ({})", subcategory));
+                                    }
+
+                                    embed.field(&name,
+                                                &format!("**{}** by {}",
+                                                         format_time(&wr.time),
+                                                         join_players(&wr.players)),
+                                                false)
+                                })
+                            })
+                       .collect();
+
+        bot.send_paginated_embeds(message.channel_id, pages);
+    }
+
+    fn handle_pb(&self, bot: &Bot, message: &Message, args: &Arguments) {
+        let player = args.text("player").unwrap();
+        let game = args.text("game").unwrap();
+
+        let (player, game, mut pbs) = match get_pbs(&self.api_base, player, game) {
+            Ok(x) => x,
+            Err(err) => {
+                bot.send(message.channel_id,
+                         &match err {
+                             MyError::Network(err) => {
+                                 format!("Couldn't communicate with https://www.speedrun.com. \
+                                          :( ({})",
+                                         err)
                              }
-                         }
-                         Err(MyError::Network(err)) => {
-                             format!("Couldn't communicate with https://www.speedrun.com. :( ({})",
-                                     err)
-                         }
-                         Err(MyError::NoSuchGame) => {
-                             "There's no such game on speedrun.com! :O".to_string()
-                         }
-                         Err(MyError::NoSuchPlayer) => {
-                             "There's no such player on speedrun.com! :O".to_string()
-                         }
-                         Err(err) => format!("Something's broken. :/ ({})", err),
-                     }.as_str());
-        } else {
+                             MyError::NoSuchGame => {
+                                 "There's no such game on speedrun.com! :O".to_string()
+                             }
+                             MyError::NoSuchPlayer => {
+                                 "There's no such player on speedrun.com! :O".to_string()
+                             }
+                             err => format!("Something's broken. :/ ({})", err),
+                         });
+                return;
+            }
+        };
+
+        if pbs.is_empty() {
             bot.send(message.channel_id,
-                     <Module as module::Module>::command_help_message(&self, Commands::PB as u32));
+                     &format!("**{}** has no personal bests in **{}**. :|", player, game.name));
+            return;
         }
+
+        pbs.sort_by_key(|x| x.category.clone());
+
+        let title = format!("{}'s personal bests in {}", player, game.name);
+        let pages = pbs.chunks(PBS_PER_PAGE)
+                       .map(|pbs| {
+                                pbs.iter().fold(game.embed(&title), |embed, pb| {
+                                    let mut name = pb.category.clone();
+                                    if !pb.subcategories.is_empty() {
+                                        name.push_str(&format!(" ({})",
+                                                               pb.subcategories.join(", ")));
+                                    }
+
+                                    let mut value = format!("**{}** - {}{}",
+                                                            format_time(&pb.time),
+                                                            pb.place,
+                                                            number_suffix(pb.place));
+                                    if pb.place == 1 {
+                                        value.push_str(" 🏆");
+                                    }
+
+                                    embed.field(&name, &value, false)
+                                })
+                            })
+                       .collect();
+
+        bot.send_paginated_embeds(message.channel_id, pages);
+    }
+}
+
+// "A", "A and B", "A, B and C".
+fn join_players(players: &[String]) -> String {
+    match players.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        Some((last, _)) => last.clone(),
+        None => String::new(),
     }
 }
 
@@ -271,6 +352,37 @@ fn format_time(time: &Duration) -> String {
     buf
 }
 
+struct Game {
+    name: String,
+    link: Option<String>,
+    cover: Option<String>,
+}
+
+impl Game {
+    fn new(data: &APIGamesData) -> Self {
+        Game { name: data.names.international.clone(),
+               link: data.weblink.clone(),
+               cover: data.assets
+                          .as_ref()
+                          .and_then(|x| x.cover_medium.as_ref())
+                          .and_then(|x| x.uri.clone()), }
+    }
+
+    // An embed with the title linking to the game, with its cover.
+    fn embed(&self, title: &str) -> Embed {
+        let mut embed = Embed::new().title(title).color(SPEEDRUN_COLOR);
+
+        if let Some(ref link) = self.link {
+            embed = embed.url(link);
+        }
+        if let Some(ref cover) = self.cover {
+            embed = embed.thumbnail(cover);
+        }
+
+        embed
+    }
+}
+
 struct WR {
     category: String,
     subcategory: Option<String>,
@@ -278,8 +390,8 @@ struct WR {
     time: Duration,
 }
 
-fn get_wrs(text: &str) -> Result<(String, Vec<WR>), MyError> {
-    let mut games = SPEEDRUNCOM_API_BASE.join("games").unwrap();
+fn get_wrs(api_base: &Url, text: &str) -> Result<(Game, Vec<WR>), MyError> {
+    let mut games = api_base.join("games").unwrap();
     games.query_pairs_mut()
          .append_pair("name", text)
          .append_pair("embed", "categories.variables")
@@ -294,6 +406,7 @@ fn get_wrs(text: &str) -> Result<(String, Vec<WR>), MyError> {
     }
 
     let game = games.data.into_iter().next().unwrap();
+    let info = Game::new(&game);
 
     let game_categories = game.categories.ok_or_else(|| {
         MyError::Custom("The `categories` object is absent from the JSON.".to_owned())
@@ -321,7 +434,7 @@ fn get_wrs(text: &str) -> Result<(String, Vec<WR>), MyError> {
 
             for (value_id, value) in subcategory_variable.values.values {
                 let mut leaderboard =
-                    SPEEDRUNCOM_API_BASE.join(&format!("leaderboards/{}/category/{}",
+                    api_base.join(&format!("leaderboards/{}/category/{}",
                                                        game.id, category.id))
                                         .map_err(|x| x.to_string())?;
 
@@ -365,7 +478,7 @@ fn get_wrs(text: &str) -> Result<(String, Vec<WR>), MyError> {
             // No subcategories, just get runs.
 
             let mut leaderboard =
-                SPEEDRUNCOM_API_BASE.join(&format!("leaderboards/{}/category/{}",
+                api_base.join(&format!("leaderboards/{}/category/{}",
                                                    game.id, category.id))
                                     .map_err(|x| x.to_string())?;
 
@@ -405,7 +518,7 @@ fn get_wrs(text: &str) -> Result<(String, Vec<WR>), MyError> {
         }
     }
 
-    Ok((game.names.international, wrs))
+    Ok((info, wrs))
 }
 
 struct PB {
@@ -415,8 +528,11 @@ struct PB {
     place: u64,
 }
 
-fn get_pbs(player_name: &str, game_name: &str) -> Result<(String, String, Vec<PB>), MyError> {
-    let mut games = SPEEDRUNCOM_API_BASE.join("games").unwrap();
+fn get_pbs(api_base: &Url,
+           player_name: &str,
+           game_name: &str)
+           -> Result<(String, Game, Vec<PB>), MyError> {
+    let mut games = api_base.join("games").unwrap();
     games.query_pairs_mut()
          .append_pair("name", game_name)
          .append_pair("max", "1");
@@ -433,7 +549,7 @@ fn get_pbs(player_name: &str, game_name: &str) -> Result<(String, String, Vec<PB
 
     let game = games.data.into_iter().next().unwrap();
 
-    let mut users = SPEEDRUNCOM_API_BASE.join(&format!("users/{}/personal-bests", player_name))
+    let mut users = api_base.join(&format!("users/{}/personal-bests", player_name))
                                         .map_err(|x| x.to_string())?;
 
     users.query_pairs_mut()
@@ -477,7 +593,7 @@ fn get_pbs(player_name: &str, game_name: &str) -> Result<(String, String, Vec<PB
                       place: run.place, });
     }
 
-    Ok((player_name.to_owned(), game.names.international, pbs))
+    Ok((player_name.to_owned(), Game::new(&game), pbs))
 }
 
 #[derive(Debug)]
@@ -534,3 +650,33 @@ fn number_suffix(n: u64) -> String {
         _ => "th".to_owned(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake;
+    use module::Module as ModuleTrait;
+
+    // Anything past the argument checks talks to speedrun.com.
+    #[test]
+    fn missing_arguments() {
+        let (fake, bot) = fake::bot(vec![Module::new(&ModuleConfig::default()).unwrap()]);
+
+        fake::run(&fake, &bot, "!wr");
+        fake::run(&fake, &bot, "!pb YaLTeR");
+
+        let replies = fake.take_replies();
+        assert_eq!(2, replies.len());
+        assert!(replies[0].starts_with("You forgot the `<game>` argument.\n`!wr <game>`"));
+        assert!(replies[1].starts_with("You forgot the `<game>` argument.\n`!pb <player> <game>`"));
+    }
+
+    #[test]
+    fn players() {
+        let players = ["A", "B", "C"].iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert_eq!("A", join_players(&players[..1]));
+        assert_eq!("A and B", join_players(&players[..2]));
+        assert_eq!("A, B and C", join_players(&players));
+    }
+}
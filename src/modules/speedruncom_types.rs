@@ -9,6 +9,8 @@ struct APIGames {
 struct APIGamesData {
 	id: String,
 	names: APIGamesNames,
+	weblink: Option<String>,
+	assets: Option<APIGamesAssets>,
 	categories: Option<APICategories>
 }
 
@@ -17,6 +19,17 @@ struct APIGamesNames {
 	international: String
 }
 
+#[derive(Deserialize, Debug)]
+struct APIGamesAssets {
+	#[serde(rename="cover-medium")]
+	cover_medium: Option<APIGamesAsset>
+}
+
+#[derive(Deserialize, Debug)]
+struct APIGamesAsset {
+	uri: Option<String>
+}
+
 #[derive(Deserialize, Debug)]
 struct APICategories {
 	data: Vec<APICategoryData>
@@ -1,26 +1,47 @@
 use bot::Bot;
+use config::ModuleConfig;
+use cooldowns::{Cooldown, Scope};
 use discord::model::Message;
+use embed::Embed;
 use failure::{self, ResultExt};
 use hyper::client::Client;
 use module;
 use serde::{Deserialize, Deserializer};
 use serde_xml_rs::deserialize;
-use std::{collections::hash_map::HashMap, env, error::Error};
+use std::collections::hash_map::HashMap;
 use url::Url;
 
+// Where people can look at the full results.
+const WEBSITE_URL: &str = "https://www.wolframalpha.com/input/";
+
+const WOLFRAM_ALPHA_COLOR: u64 = 0xdd1100;
+
 pub struct Module<'a> {
     commands: HashMap<u32, &'a [&'a str]>,
+    app_id: String,
+    api_base: Url,
 }
 
-lazy_static! {
-    static ref WOLFRAMALPHA_API_BASE: Url =
-        Url::parse("http://api.wolframalpha.com/v2/query").unwrap();
-    static ref WOLFRAMALPHA_CLIENT_ID: Result<String, String> =
-        env::var("YALTER_BOT_WOLFRAMALPHA_APPID").map_err(|_| {
-                     "Please set the YALTER_BOT_WOLFRAMALPHA_APPID environment variable".to_string()
-                 });
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    app_id: Option<String>,
+    api_base: String,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Config { app_id: None,
+                 api_base: "http://api.wolframalpha.com/v2/query".to_owned(), }
+    }
+}
+
+// Every query counts towards the app ID quota.
+static WA_COOLDOWNS: [Cooldown; 2] = [Cooldown { scope: Scope::User,
+                                                 seconds: 30, },
+                                      Cooldown { scope: Scope::Server,
+                                                 seconds: 10, }];
+
 enum Commands {
     WA = 0,
 }
@@ -93,14 +114,42 @@ struct DidYouMean {
     contents: String,
 }
 
+impl Pod {
+    fn plaintext(&self) -> Option<&str> {
+        self.subpods
+            .iter()
+            .filter_map(|s| s.plaintext.as_ref())
+            .find(|x| !x.is_empty())
+            .map(String::as_str)
+    }
+
+    fn image(&self) -> Option<&str> {
+        self.subpods
+            .iter()
+            .filter_map(|s| s.image.as_ref())
+            .map(|x| x.src.as_str())
+            .next()
+    }
+}
+
 impl<'a> module::Module for Module<'a> {
-    fn new() -> Result<Box<module::Module>, String> {
+    fn new(config: &ModuleConfig) -> Result<Box<module::Module>, String> {
         static WA: [&'static str; 2] = ["wolphramalpha", "wa"];
         let mut map: HashMap<u32, &[&str]> = HashMap::new();
         map.insert(Commands::WA as u32, &WA);
-        WOLFRAMALPHA_CLIENT_ID.as_ref()
-                              .map_err(|s| s.clone())
-                              .and(Ok(Box::new(Module { commands: map })))
+
+        let config = config.parse::<Config>()?;
+        let app_id = config.app_id.ok_or_else(|| {
+            "Please set app_id in [modules.wolframalpha] or the YALTER_BOT_WOLFRAMALPHA_APPID \
+             environment variable"
+                .to_string()
+        })?;
+        let api_base =
+            Url::parse(&config.api_base).map_err(|err| format!("Invalid api_base: {}", err))?;
+
+        Ok(Box::new(Module { commands: map,
+                             app_id,
+                             api_base, }))
     }
 
     fn name(&self) -> &'static str {
@@ -115,6 +164,11 @@ impl<'a> module::Module for Module<'a> {
         &self.commands
     }
 
+    // Queries take a few seconds each and count towards the app ID quota.
+    fn max_concurrent_handlers(&self) -> usize {
+        2
+    }
+
     fn command_description(&self, _: u32) -> &'static str {
         "Queries the Wolfram!Alpha service."
     }
@@ -123,6 +177,10 @@ impl<'a> module::Module for Module<'a> {
         "`!wa <input>` - Queries Wolfram!Alpha with the given input and returns the result. For example, `!wa int sin x / x dx, 0 < x < +inf`."
     }
 
+    fn command_cooldowns(&self, _: u32) -> &'static [Cooldown] {
+        &WA_COOLDOWNS
+    }
+
     fn handle(&self, bot: &Bot, message: &Message, _id: u32, text: &str) {
         if text.is_empty() {
             bot.send(message.channel_id,
@@ -146,12 +204,13 @@ impl<'a> module::Module for Module<'a> {
 
 impl<'a> Module<'a> {
     fn handle_wa(&self, bot: &Bot, message: &Message, text: &str) -> Result<(), failure::Error> {
-        let mut url = WOLFRAMALPHA_API_BASE.clone();
+        let mut url = self.api_base.clone();
         url.query_pairs_mut()
-           .append_pair("appid", WOLFRAMALPHA_CLIENT_ID.as_ref().unwrap())
+           .append_pair("appid", &self.app_id)
            .append_pair("input", text);
 
-        println!("URL: {}", url.as_str());
+        // The app ID is redacted by the logger.
+        debug!("URL: {}", url.as_str());
 
         let client = Client::new();
         let response = client.get(url.as_str())
@@ -183,60 +242,71 @@ impl<'a> Module<'a> {
             bail!(text);
         }
 
-        let send_pod_contents = |pod: &Pod, text: &str, image_filename| {
-            let mut text = text.to_owned();
-
-            if let Some(subpod) = pod.subpods.iter().find(|s| {
-                                                              !s.plaintext
-                                                                .as_ref()
-                                                                .map(String::as_ref)
-                                                                .unwrap_or("")
-                                                                .is_empty()
-                                                          }) {
-                text.push_str(&format!("\n```\n{}\n```", subpod.plaintext.as_ref().unwrap()));
-            }
+        let is_input_interpretation =
+            |pod: &Pod| pod.title == "Input" || pod.title == "Input interpretation";
 
-            if let Some(subpod) = pod.subpods.iter().find(|s| s.image.is_some()) {
-                match client.get(&subpod.image.as_ref().unwrap().src).send() {
-                    Ok(result) => {
-                        bot.send_file(message.channel_id, &text, result, image_filename);
-                    }
-                    Err(err) => {
-                        if !text.is_empty() {
-                            text.push_str("\n\n");
-                        }
-
-                        text.push_str(&format!(
-                            "Something's broken. :/ \
-                             (Couldn't get the resulting image returned by the API: {})",
-                            err.description()
-                        ));
-
-                        bot.send(message.channel_id, &text);
-                    }
-                }
+        let input_interpretation = result.pods.iter().find(|pod| is_input_interpretation(pod));
+        let results = match result.pods.iter().find(|pod| !is_input_interpretation(pod)) {
+            Some(x) => x,
+            None => {
+                bail!("Wolfram!Alpha didn't return a result pod. \
+                       This probably means that the standard computation time exceeded.")
             }
         };
 
-        let is_input_interpretation =
-            |pod: &Pod| pod.title == "Input" || pod.title == "Input interpretation";
+        let link = Url::parse_with_params(WEBSITE_URL, &[("i", text)]).unwrap();
+        let mut embed = Embed::new().title(text)
+                                    .url(link.as_str())
+                                    .color(WOLFRAM_ALPHA_COLOR);
 
-        if let Some(input_interpretation) =
-            result.pods.iter().find(|pod| is_input_interpretation(pod))
-        {
-            send_pod_contents(input_interpretation,
-                              "Input interpretation:",
-                              "input_interpretation.gif");
+        for pod in input_interpretation.into_iter().chain(Some(results)) {
+            if let Some(plaintext) = pod.plaintext() {
+                embed = embed.code_field(&pod.title, plaintext, false);
+            }
         }
 
-        if let Some(results) = result.pods.iter().find(|pod| !is_input_interpretation(pod)) {
-            send_pod_contents(results, "Result:", "result.gif");
-        } else {
-            bot.send(message.channel_id,
-                     "Wolfram!Alpha didn't return a result pod. \
-                      This probably means that the standard computation time exceeded.");
+        // The result gets the big picture and the interpretation the small one.
+        if let Some(image) = input_interpretation.and_then(Pod::image) {
+            embed = embed.thumbnail(image);
         }
 
+        if let Some(image) = results.image() {
+            embed = embed.image(image);
+        }
+
+        bot.send_embed(message.channel_id, &embed);
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discord::model::MessageId;
+    use fake;
+    use module::Module as ModuleTrait;
+
+    #[test]
+    fn needs_app_id() {
+        assert!(Module::new(&ModuleConfig::default()).is_err());
+        assert!(Module::new(&ModuleConfig::from_toml("wolframalpha", "app_id = \"abc\"")).is_ok());
+    }
+
+    // Anything but the help message makes a query.
+    #[test]
+    fn empty_query() {
+        let config = ModuleConfig::from_toml("wolframalpha", "app_id = \"abc\"");
+        let (fake, bot) = fake::bot(vec![Module::new(&config).unwrap()]);
+        let message = fake::message(MessageId(fake.generate_id()),
+                                    fake::CHANNEL_ID,
+                                    &fake::owner(),
+                                    "!wa");
+
+        bot.get_modules()[0].handle(&bot, &message, Commands::WA as u32, "");
+
+        let replies = fake.take_replies();
+        assert_eq!(1, replies.len());
+        assert!(replies[0].starts_with("`!wa <input>`"));
+    }
+}
@@ -0,0 +1,93 @@
+use embed::Embed;
+use std::time::Instant;
+
+// How long a page can get, leaving room for the page number.
+const MAX_PAGE_LENGTH: usize = 1900;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Page {
+    Text(String),
+    Embed(Embed),
+}
+
+// A message whose contents can be flipped through with reactions.
+pub struct Paginated {
+    pub pages: Vec<Page>,
+    pub current: usize,
+    pub expires: Instant,
+}
+
+impl Paginated {
+    // The current page with the page number, which goes into the footer of embeds.
+    pub fn page(&self) -> Page {
+        let number = format!("Page {}/{}", self.current + 1, self.pages.len());
+
+        match self.pages[self.current] {
+            Page::Text(ref text) => Page::Text(format!("{}\n\n`{}`", text, number)),
+            Page::Embed(ref embed) => Page::Embed(embed.clone().footer(&number)),
+        }
+    }
+}
+
+// Splits lines into pages of at most `lines_per_page` lines, each starting with the header.
+// Pages are also kept short enough to fit into a single message.
+pub fn paginate(header: &str, lines: &[String], lines_per_page: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut current = header.to_owned();
+    let mut count = 0;
+
+    for line in lines {
+        if count > 0
+           && (count == lines_per_page
+               || current.chars().count() + 1 + line.chars().count() > MAX_PAGE_LENGTH)
+        {
+            pages.push(current);
+            current = header.to_owned();
+            count = 0;
+        }
+
+        current.push('\n');
+        current.push_str(line);
+        count += 1;
+    }
+
+    pages.push(current);
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginate_by_count() {
+        let lines = (1..6).map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["Header:\n1\n2", "Header:\n3\n4", "Header:\n5"],
+                   paginate("Header:", &lines, 2));
+    }
+
+    #[test]
+    fn paginate_by_length() {
+        let lines = vec!["a".repeat(1000), "b".repeat(1000), "c".to_owned()];
+        let pages = paginate("Header:", &lines, 10);
+
+        assert_eq!(2, pages.len());
+        assert!(pages[1].ends_with("\nc"));
+    }
+
+    #[test]
+    fn paginate_empty() {
+        assert_eq!(vec!["Header:"], paginate("Header:", &[], 10));
+    }
+
+    #[test]
+    fn page_numbers() {
+        let paginated = Paginated { pages: vec![Page::Text("a".to_owned()),
+                                                Page::Embed(Embed::new().title("b"))],
+                                    current: 1,
+                                    expires: Instant::now(), };
+
+        assert_eq!(Page::Embed(Embed::new().title("b").footer("Page 2/2")),
+                   paginated.page());
+    }
+}
@@ -0,0 +1,318 @@
+use crashes::Recover;
+use std::{
+    collections::hash_map::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const WATCHDOG_INTERVAL_MS: u64 = 500;
+const IDLE_POLL_INTERVAL_MS: u64 = 20;
+
+// Why a job couldn't be queued.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Busy {
+    // The queue is full.
+    Queue,
+    // There are already as many jobs with this key as allowed.
+    Limit,
+}
+
+type Task = Box<FnOnce() + Send>;
+
+struct Job {
+    key: &'static str,
+    task: Task,
+    on_timeout: Task,
+}
+
+struct Running {
+    key: &'static str,
+    started: Instant,
+    // Taken out once the job times out, which also releases the job's slot.
+    on_timeout: Option<Task>,
+    // Set when the worker got replaced and should exit after this job.
+    retired: Arc<AtomicBool>,
+}
+
+struct Shared {
+    receiver: Mutex<Receiver<Job>>,
+    // Map from worker id into the job it is running.
+    running: Mutex<HashMap<usize, Running>>,
+    // Number of queued and running jobs per key. Jobs which timed out don't count anymore.
+    in_flight: Mutex<HashMap<&'static str, usize>>,
+    next_worker_id: AtomicUsize,
+    timeout: Duration,
+}
+
+// A fixed set of worker threads running jobs from a bounded queue.
+pub struct Pool {
+    sender: Mutex<SyncSender<Job>>,
+    shared: Arc<Shared>,
+}
+
+impl Shared {
+    fn release(&self, key: &'static str) {
+        let mut in_flight = self.in_flight.lock().recover();
+
+        let remove = match in_flight.get_mut(key) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+
+        if remove {
+            in_flight.remove(key);
+        }
+    }
+}
+
+fn spawn_worker(shared: Arc<Shared>) {
+    let id = shared.next_worker_id.fetch_add(1, Ordering::SeqCst);
+
+    thread::spawn(move || {
+        let retired = Arc::new(AtomicBool::new(false));
+
+        loop {
+            let job = match shared.receiver.lock().recover().recv() {
+                Ok(job) => job,
+                Err(_) => return, // The pool is gone.
+            };
+
+            shared.running.lock().recover().insert(id,
+                                                   Running { key: job.key,
+                                                             started: Instant::now(),
+                                                             on_timeout: Some(job.on_timeout),
+                                                             retired: retired.clone(), });
+
+            // Module code is isolated on its own, this only keeps the worker alive and the
+            // bookkeeping right if something else panics.
+            if panic::catch_unwind(AssertUnwindSafe(job.task)).is_err() {
+                error!("A {} job panicked.", job.key);
+            }
+
+            let timed_out = shared.running
+                                  .lock()
+                                  .recover()
+                                  .remove(&id)
+                                  .map_or(false, |x| x.on_timeout.is_none());
+            if !timed_out {
+                shared.release(job.key);
+            }
+
+            if retired.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    });
+}
+
+fn watchdog(shared: Arc<Shared>) {
+    loop {
+        thread::sleep(Duration::from_millis(WATCHDOG_INTERVAL_MS));
+
+        let mut timed_out = Vec::new();
+
+        for running in shared.running.lock().recover().values_mut() {
+            if running.on_timeout.is_some() && running.started.elapsed() >= shared.timeout {
+                running.retired.store(true, Ordering::SeqCst);
+                timed_out.push((running.key, running.on_timeout.take().unwrap()));
+            }
+        }
+
+        for (key, on_timeout) in timed_out {
+            // Threads can't be killed, so the stuck worker is left to finish on its own
+            // and a fresh one takes its place. The job is given up on, so that a few stuck
+            // ones don't use up the limit for good.
+            shared.release(key);
+            spawn_worker(shared.clone());
+            on_timeout();
+        }
+    }
+}
+
+impl Pool {
+    pub fn new(workers: usize, queue_size: usize, timeout: Duration) -> Self {
+        let (sender, receiver) = sync_channel(queue_size);
+
+        let shared = Arc::new(Shared { receiver: Mutex::new(receiver),
+                                       running: Mutex::new(HashMap::new()),
+                                       in_flight: Mutex::new(HashMap::new()),
+                                       next_worker_id: AtomicUsize::new(0),
+                                       timeout, });
+
+        for _ in 0..workers {
+            spawn_worker(shared.clone());
+        }
+
+        let watchdog_shared = shared.clone();
+        thread::spawn(move || watchdog(watchdog_shared));
+
+        Pool { sender: Mutex::new(sender),
+               shared, }
+    }
+
+    // Queues a job. At most `limit` jobs with the same key can be queued or running at once.
+    // If the job runs for longer than the timeout, on_timeout gets called and the job stops
+    // counting towards the limit.
+    pub fn execute<F, T>(&self,
+                         key: &'static str,
+                         limit: usize,
+                         task: F,
+                         on_timeout: T)
+                         -> Result<(), Busy>
+        where F: FnOnce() + Send + 'static,
+              T: FnOnce() + Send + 'static
+    {
+        {
+            let mut in_flight = self.shared.in_flight.lock().recover();
+            let count = in_flight.entry(key).or_insert(0);
+
+            if *count >= limit {
+                return Err(Busy::Limit);
+            }
+
+            *count += 1;
+        }
+
+        let job = Job { key,
+                        task: Box::new(task),
+                        on_timeout: Box::new(on_timeout), };
+
+        match self.sender.lock().recover().try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.shared.release(key);
+                Err(Busy::Queue)
+            }
+        }
+    }
+
+    // Blocks until there are no queued or running jobs.
+    pub fn wait_idle(&self) {
+        while !self.is_idle() {
+            thread::sleep(Duration::from_millis(IDLE_POLL_INTERVAL_MS));
+        }
+    }
+
+    // Like wait_idle(), but gives up after the timeout. Returns false if it did.
+    pub fn wait_idle_for(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        while !self.is_idle() {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+
+            thread::sleep(Duration::from_millis(IDLE_POLL_INTERVAL_MS));
+        }
+
+        true
+    }
+
+    fn is_idle(&self) -> bool {
+        self.shared.in_flight.lock().recover().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    // One worker, so that the jobs run in order.
+    fn pool(queue_size: usize) -> Pool {
+        Pool::new(1, queue_size, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn limit() {
+        let pool = pool(10);
+        let (unblock, blocked) = channel::<()>();
+        let (done, finished) = channel();
+
+        pool.execute("a", 1, move || blocked.recv().unwrap(), || {}).unwrap();
+        assert_eq!(Err(Busy::Limit), pool.execute("a", 1, || {}, || {}));
+        assert_eq!(Ok(()), pool.execute("b", 1, move || done.send(()).unwrap(), || {}));
+
+        // "b" runs once "a" is done and released.
+        unblock.send(()).unwrap();
+        finished.recv().unwrap();
+        assert_eq!(Ok(()), pool.execute("a", 1, || {}, || {}));
+    }
+
+    #[test]
+    fn panicking_job() {
+        let pool = pool(10);
+        let (done, finished) = channel();
+
+        pool.execute("a", 1, || panic!("oops"), || {}).unwrap();
+        pool.execute("b", 1, move || done.send(()).unwrap(), || {}).unwrap();
+
+        // The worker survived and "a" got released.
+        finished.recv().unwrap();
+        assert_eq!(Ok(()), pool.execute("a", 1, || {}, || {}));
+    }
+
+    #[test]
+    fn full_queue() {
+        let pool = pool(1);
+        let (unblock, blocked) = channel::<()>();
+        let (started, running) = channel();
+
+        pool.execute("a",
+                     1,
+                     move || {
+                         started.send(()).unwrap();
+                         blocked.recv().unwrap();
+                     },
+                     || {})
+            .unwrap();
+
+        // Wait for the worker to take "a" off the queue, then fill it up.
+        running.recv().unwrap();
+        pool.execute("b", 1, || {}, || {}).unwrap();
+        assert_eq!(Err(Busy::Queue), pool.execute("c", 1, || {}, || {}));
+
+        unblock.send(()).unwrap();
+    }
+
+    #[test]
+    fn timed_out_job() {
+        let pool = Pool::new(1, 10, Duration::from_millis(100));
+        let (unblock, blocked) = channel::<()>();
+        let (stuck_done, stuck_finished) = channel();
+        let (timeout, timed_out) = channel();
+        let (done, finished) = channel();
+
+        pool.execute("a",
+                     1,
+                     move || {
+                         blocked.recv().unwrap();
+                         stuck_done.send(()).unwrap();
+                     },
+                     move || timeout.send(()).unwrap())
+            .unwrap();
+
+        // The stuck job doesn't hold on to its slot, and a new worker runs the next one.
+        timed_out.recv().unwrap();
+        assert!(pool.wait_idle_for(Duration::from_secs(0)));
+        pool.execute("a", 1, move || done.send(()).unwrap(), || {}).unwrap();
+        finished.recv().unwrap();
+
+        // Nor does it release someone else's slot once it's done.
+        let (unblock_next, blocked_next) = channel::<()>();
+        pool.execute("a", 1, move || blocked_next.recv().unwrap(), || {}).unwrap();
+        unblock.send(()).unwrap();
+        stuck_finished.recv().unwrap();
+        assert_eq!(Err(Busy::Limit), pool.execute("a", 1, || {}, || {}));
+        unblock_next.send(()).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+use crashes::Recover;
+use discord::{
+    model::{ChannelId, ServerId},
+    Error, Result,
+};
+use hyper::status::StatusCode;
+use std::{
+    cmp,
+    collections::hash_map::HashMap,
+    sync::{Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+// How many times a request is tried before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+// The delay before retrying after a Discord server error, doubled after every attempt.
+const SERVER_ERROR_BASE_DELAY_MS: u64 = 1000;
+// Used when Discord says to slow down but not for how long.
+const DEFAULT_RETRY_AFTER_MS: u64 = 1000;
+
+// What a request does. Discord keeps a separate rate limit for every kind of request in every
+// channel or server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    SendMessage(ChannelId),
+    EditMessage(ChannelId),
+    GetMessages(ChannelId),
+    DeleteMessages(ChannelId),
+    DeleteMessage(ChannelId),
+    Reactions(ChannelId),
+    Permissions(ChannelId),
+    Typing(ChannelId),
+    CreateChannel(ServerId),
+    GetMember(ServerId),
+    Members(ServerId),
+    Bans(ServerId),
+    CreatePrivateChannel,
+}
+
+// Outgoing REST requests. A request waits until the requests made before it in the same channel
+// are done and until its route is out of the rate limit, and gets retried if Discord turned it
+// away because of the rate limit or trouble on its side.
+pub struct Queue {
+    state: Mutex<State>,
+    changed: Condvar,
+    server_error_delay: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    // Number of requests waiting or running.
+    depth: usize,
+    // Routes which are rate limited until the given time.
+    limited: HashMap<Route, Instant>,
+    // Channels which have requests waiting or running.
+    turns: HashMap<ChannelId, Turns>,
+}
+
+// Requests in a channel take numbered tickets and go one at a time in the order of the tickets.
+#[derive(Default)]
+struct Turns {
+    serving: u64,
+    next: u64,
+    // The thread which holds the turn for several requests. Its requests in the channel don't
+    // take tickets of their own.
+    holder: Option<ThreadId>,
+}
+
+// Lets the next request in the channel go even if this one panicked.
+struct Ticket<'a> {
+    queue: &'a Queue,
+    // None if there's no turn to pass on.
+    channel: Option<ChannelId>,
+    // Whether this is a request, rather than a held turn.
+    request: bool,
+}
+
+impl Route {
+    fn channel(&self) -> Option<ChannelId> {
+        match *self {
+            Route::SendMessage(x)
+            | Route::EditMessage(x)
+            | Route::GetMessages(x)
+            | Route::DeleteMessages(x)
+            | Route::DeleteMessage(x)
+            | Route::Reactions(x)
+            | Route::Permissions(x)
+            | Route::Typing(x) => Some(x),
+            Route::CreateChannel(_)
+            | Route::GetMember(_)
+            | Route::Members(_)
+            | Route::Bans(_)
+            | Route::CreatePrivateChannel => None,
+        }
+    }
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Queue::with_server_error_delay(Duration::from_millis(SERVER_ERROR_BASE_DELAY_MS))
+    }
+
+    fn with_server_error_delay(server_error_delay: Duration) -> Self {
+        Queue { state: Mutex::new(State::default()),
+                changed: Condvar::new(),
+                server_error_delay, }
+    }
+
+    // The number of requests which are waiting or running right now.
+    pub fn depth(&self) -> usize {
+        self.state.lock().recover().depth
+    }
+
+    // Runs f holding the channel's turn, so that the requests it makes in the channel go out one
+    // after another with nothing else in between, like the parts of a long message.
+    pub fn hold<R, F: FnOnce() -> R>(&self, channel: ChannelId, f: F) -> R {
+        let _ticket = self.wait_for_turn(Some(channel), false);
+        f()
+    }
+
+    // Makes the request with f, which is called again for every retry.
+    pub fn run<T, F: FnMut() -> Result<T>>(&self, route: Route, mut f: F) -> Result<T> {
+        let _ticket = self.wait_for_turn(route.channel(), true);
+
+        let mut attempt = 1;
+        loop {
+            self.wait_for_rate_limit(route);
+
+            let err = match f() {
+                Ok(x) => return Ok(x),
+                Err(err) => err,
+            };
+
+            let delay = match err {
+                Error::RateLimited(ms) => Duration::from_millis(ms),
+                Error::Status(StatusCode::TooManyRequests, ref value) => {
+                    let ms = value.as_ref()
+                                  .and_then(|x| x["retry_after"].as_u64())
+                                  .unwrap_or(DEFAULT_RETRY_AFTER_MS);
+                    Duration::from_millis(ms)
+                }
+                Error::Status(status, _) if status.is_server_error() => {
+                    self.server_error_delay * 2u32.pow(attempt - 1)
+                }
+                _ => return Err(err),
+            };
+
+            if attempt == MAX_ATTEMPTS {
+                warn!("Giving up on a {:?} request after {} attempts: {:?}.", route, attempt, err);
+                return Err(err);
+            }
+
+            warn!("A {:?} request failed with {:?}, retrying in {}ms ({} requests queued).",
+                  route,
+                  err,
+                  delay.as_secs() * 1000 + u64::from(delay.subsec_millis()),
+                  self.depth());
+
+            // Everything else on this route has to wait too.
+            self.state
+                .lock()
+                .recover()
+                .limited
+                .insert(route, Instant::now() + delay);
+
+            attempt += 1;
+        }
+    }
+
+    fn wait_for_turn<'a>(&'a self, channel: Option<ChannelId>, request: bool) -> Ticket<'a> {
+        let mut state = self.state.lock().recover();
+        if request {
+            state.depth += 1;
+            self.changed.notify_all();
+        }
+
+        let channel = channel.filter(|channel| {
+                                         let me = Some(thread::current().id());
+                                         state.turns.get(channel).map_or(true, |x| x.holder != me)
+                                     });
+
+        if let Some(channel) = channel {
+            let ticket = {
+                let turns = state.turns.entry(channel).or_insert_with(Turns::default);
+                turns.next += 1;
+                turns.next - 1
+            };
+
+            while state.turns[&channel].serving != ticket {
+                state = self.changed.wait(state).recover();
+            }
+
+            if !request {
+                state.turns.get_mut(&channel).unwrap().holder = Some(thread::current().id());
+            }
+        }
+
+        Ticket { queue: self,
+                 channel,
+                 request, }
+    }
+
+    fn wait_for_rate_limit(&self, route: Route) {
+        loop {
+            let until = {
+                let mut state = self.state.lock().recover();
+                match state.limited.get(&route).cloned() {
+                    Some(until) if until > Instant::now() => until,
+                    Some(_) => {
+                        state.limited.remove(&route);
+                        return;
+                    }
+                    None => return,
+                }
+            };
+
+            let now = Instant::now();
+            thread::sleep(cmp::max(until, now) - now);
+        }
+    }
+}
+
+impl<'a> Drop for Ticket<'a> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().recover();
+        if self.request {
+            state.depth -= 1;
+        }
+
+        if let Some(channel) = self.channel {
+            let done = {
+                let turns = state.turns.get_mut(&channel).unwrap();
+                turns.serving += 1;
+                turns.holder = None;
+                turns.serving == turns.next
+            };
+
+            if done {
+                state.turns.remove(&channel);
+            }
+        }
+
+        self.queue.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+
+    fn wait_for_depth(queue: &Queue, depth: usize) {
+        let mut state = queue.state.lock().unwrap();
+        while state.depth != depth {
+            state = queue.changed.wait(state).unwrap();
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let queue = Queue::with_server_error_delay(Duration::from_millis(1));
+        let route = Route::SendMessage(ChannelId(1));
+
+        let mut attempts = 0;
+        let result = queue.run(route, || {
+                                   attempts += 1;
+                                   match attempts {
+                                       1 => Err(Error::RateLimited(5)),
+                                       2 => Err(Error::Status(StatusCode::BadGateway, None)),
+                                       3 => Err(Error::Status(StatusCode::TooManyRequests,
+                                                              Some(json!({ "retry_after": 5 })))),
+                                       _ => Ok(attempts),
+                                   }
+                               });
+        assert_eq!(4, result.unwrap());
+
+        // Other errors aren't going to go away by retrying.
+        let mut attempts = 0;
+        assert!(queue.run(route, || {
+                              attempts += 1;
+                              Err::<(), _>(Error::Other("Unknown Message"))
+                          }).is_err());
+        assert_eq!(1, attempts);
+
+        let mut attempts = 0;
+        assert!(queue.run(route, || {
+                              attempts += 1;
+                              Err::<(), _>(Error::Status(StatusCode::InternalServerError, None))
+                          }).is_err());
+        assert_eq!(MAX_ATTEMPTS, attempts);
+
+        assert_eq!(0, queue.depth());
+    }
+
+    #[test]
+    fn waits_for_the_rate_limit() {
+        let queue = Queue::new();
+        let route = Route::DeleteMessages(ChannelId(1));
+        let start = Instant::now();
+
+        let mut first = true;
+        queue.run(route, || {
+                      if first {
+                          first = false;
+                          Err(Error::RateLimited(50))
+                      } else {
+                          Ok(())
+                      }
+                  })
+             .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn channel_order() {
+        let queue = Arc::new(Queue::new());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let first = {
+            let queue = queue.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                queue.run(Route::SendMessage(ChannelId(1)), || {
+                              started_tx.send(()).unwrap();
+                              release_rx.recv().unwrap();
+                              log.lock().unwrap().push("first");
+                              Ok(())
+                          })
+                     .unwrap();
+            })
+        };
+        started_rx.recv().unwrap();
+
+        let second = {
+            let queue = queue.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                queue.run(Route::EditMessage(ChannelId(1)), || {
+                              log.lock().unwrap().push("second");
+                              Ok(())
+                          })
+                     .unwrap();
+            })
+        };
+
+        // Other channels don't wait.
+        queue.run(Route::SendMessage(ChannelId(2)), || {
+                      log.lock().unwrap().push("other");
+                      Ok(())
+                  })
+             .unwrap();
+
+        wait_for_depth(&queue, 2);
+        assert_eq!(vec!["other"], *log.lock().unwrap());
+
+        release_tx.send(()).unwrap();
+        first.join().unwrap();
+        second.join().unwrap();
+
+        assert_eq!(vec!["other", "first", "second"], *log.lock().unwrap());
+        assert_eq!(0, queue.depth());
+        assert!(queue.state.lock().unwrap().turns.is_empty());
+    }
+
+    #[test]
+    fn hold() {
+        let queue = Arc::new(Queue::new());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let first = {
+            let queue = queue.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                queue.hold(ChannelId(1), || {
+                         for part in &["part 1", "part 2"] {
+                             queue.run(Route::SendMessage(ChannelId(1)), || {
+                                           log.lock().unwrap().push(*part);
+                                           Ok(())
+                                       })
+                                  .unwrap();
+
+                             if *part == "part 1" {
+                                 started_tx.send(()).unwrap();
+                                 release_rx.recv().unwrap();
+                             }
+                         }
+                     });
+            })
+        };
+        started_rx.recv().unwrap();
+
+        // Can't get in between the parts.
+        let second = {
+            let queue = queue.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                queue.run(Route::SendMessage(ChannelId(1)), || {
+                              log.lock().unwrap().push("second");
+                              Ok(())
+                          })
+                     .unwrap();
+            })
+        };
+        wait_for_depth(&queue, 1);
+
+        release_tx.send(()).unwrap();
+        first.join().unwrap();
+        second.join().unwrap();
+
+        assert_eq!(vec!["part 1", "part 2", "second"], *log.lock().unwrap());
+        assert_eq!(0, queue.depth());
+        assert!(queue.state.lock().unwrap().turns.is_empty());
+    }
+}
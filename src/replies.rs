@@ -0,0 +1,226 @@
+use crashes::Recover;
+use discord::model::{ChannelId, Message, MessageId};
+use std::{
+    cell::RefCell,
+    collections::{hash_map::HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+// How many of the recent commands in every channel are remembered.
+const COMMANDS_PER_CHANNEL: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Text,
+    Embed,
+    // Can't be edited, so never reused.
+    File,
+}
+
+// A message the bot sent in reply to a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reply {
+    pub id: MessageId,
+    pub kind: Kind,
+}
+
+struct Command {
+    message: Message,
+    replies: Vec<Reply>,
+}
+
+// The command being run on this thread.
+struct Running {
+    channel: ChannelId,
+    // Replies from an earlier run of the command, to be edited instead of sending new messages.
+    earlier: VecDeque<Reply>,
+    // Earlier replies which couldn't be reused.
+    unused: Vec<Reply>,
+    replies: Vec<Reply>,
+}
+
+thread_local! {
+    static RUNNING: RefCell<Option<Running>> = RefCell::new(None);
+}
+
+// The bot's replies to recent commands, so that they can be updated when the command is edited
+// and removed when it is deleted.
+pub struct Replies {
+    // Oldest commands first.
+    channels: Mutex<HashMap<ChannelId, VecDeque<Command>>>,
+}
+
+impl Replies {
+    pub fn new() -> Self {
+        Replies { channels: Mutex::new(HashMap::new()) }
+    }
+
+    // Runs f, which is the command in the message, and remembers the replies it sends to the
+    // channel. The earlier replies get reused in order, the ones left over are returned.
+    pub fn track<F: FnOnce()>(&self, message: &Message, earlier: Vec<Reply>, f: F) -> Vec<Reply> {
+        let running = Running { channel: message.channel_id,
+                                earlier: earlier.into_iter().collect(),
+                                unused: Vec::new(),
+                                replies: Vec::new(), };
+
+        let outer = RUNNING.with(|x| x.replace(Some(running)));
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let running = RUNNING.with(|x| x.replace(outer)).unwrap();
+
+        self.remember(message.clone(), running.replies);
+
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+
+        let mut left_over = running.unused;
+        left_over.extend(running.earlier);
+        left_over
+    }
+
+    // The earlier reply to edit instead of sending a new message, if a command is being run
+    // again and the reply is of the same kind.
+    pub fn take_earlier(&self, channel: ChannelId, kind: Kind) -> Option<MessageId> {
+        RUNNING.with(|x| {
+            let mut running = x.borrow_mut();
+            let running = match *running {
+                Some(ref mut running) if running.channel == channel => running,
+                _ => return None,
+            };
+
+            let reply = running.earlier.pop_front()?;
+            if reply.kind == kind && kind != Kind::File {
+                Some(reply.id)
+            } else {
+                running.unused.push(reply);
+                None
+            }
+        })
+    }
+
+    // Ties the message to the command being run, if it was sent to the command's channel.
+    pub fn record(&self, channel: ChannelId, reply: Reply) {
+        RUNNING.with(|x| {
+            if let Some(ref mut running) = *x.borrow_mut() {
+                if running.channel == channel {
+                    running.replies.push(reply);
+                }
+            }
+        });
+    }
+
+    // Forgets the edited command and returns it with the new content, along with its replies.
+    pub fn take_edited(&self,
+                       channel: ChannelId,
+                       id: MessageId,
+                       content: &str)
+                       -> Option<(Message, Vec<Reply>)> {
+        let mut channels = self.channels.lock().recover();
+        let commands = channels.get_mut(&channel)?;
+
+        // Discord also sends updates when it adds link previews.
+        let index = commands.iter()
+                            .position(|x| x.message.id == id && x.message.content != content)?;
+        let mut command = commands.remove(index).unwrap();
+
+        command.message.content = content.to_owned();
+        Some((command.message, command.replies))
+    }
+
+    // Forgets the deleted command and returns its replies.
+    pub fn take_deleted(&self, channel: ChannelId, id: MessageId) -> Vec<Reply> {
+        let mut channels = self.channels.lock().recover();
+
+        channels.get_mut(&channel)
+                .and_then(|commands| {
+                              let index = commands.iter().position(|x| x.message.id == id)?;
+                              commands.remove(index)
+                          })
+                .map_or_else(Vec::new, |x| x.replies)
+    }
+
+    fn remember(&self, message: Message, replies: Vec<Reply>) {
+        let mut channels = self.channels.lock().recover();
+        let commands = channels.entry(message.channel_id)
+                               .or_insert_with(VecDeque::new);
+
+        if commands.len() == COMMANDS_PER_CHANNEL {
+            commands.pop_front();
+        }
+
+        commands.push_back(Command { message, replies });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake;
+
+    fn reply(id: u64, kind: Kind) -> Reply {
+        Reply { id: MessageId(id),
+                kind }
+    }
+
+    #[test]
+    fn reuses_earlier_replies() {
+        let replies = Replies::new();
+        let message = fake::message(MessageId(1), fake::CHANNEL_ID, &fake::owner(), "!cmd");
+
+        let left_over = replies.track(&message, Vec::new(), || {
+                                          assert_eq!(None,
+                                                     replies.take_earlier(fake::CHANNEL_ID,
+                                                                          Kind::Text));
+                                          replies.record(fake::CHANNEL_ID, reply(2, Kind::Text));
+                                          replies.record(fake::CHANNEL_ID, reply(3, Kind::Embed));
+                                          replies.record(fake::CHANNEL_ID, reply(4, Kind::Text));
+                                          // Other channels don't count.
+                                          replies.record(fake::PRIVATE_CHANNEL_ID,
+                                                         reply(5, Kind::Text));
+                                      });
+        assert!(left_over.is_empty());
+
+        // Not changed, so not a real edit.
+        assert!(replies.take_edited(fake::CHANNEL_ID, MessageId(1), "!cmd").is_none());
+
+        let (message, earlier) = replies.take_edited(fake::CHANNEL_ID, MessageId(1), "!cmd 2")
+                                        .unwrap();
+        assert_eq!("!cmd 2", message.content);
+        assert_eq!(vec![reply(2, Kind::Text), reply(3, Kind::Embed), reply(4, Kind::Text)],
+                   earlier);
+
+        let left_over = replies.track(&message, earlier, || {
+                                          assert_eq!(Some(MessageId(2)),
+                                                     replies.take_earlier(fake::CHANNEL_ID,
+                                                                          Kind::Text));
+                                          replies.record(fake::CHANNEL_ID, reply(2, Kind::Text));
+                                          // The embed can't become text.
+                                          assert_eq!(None,
+                                                     replies.take_earlier(fake::CHANNEL_ID,
+                                                                          Kind::Text));
+                                          replies.record(fake::CHANNEL_ID, reply(6, Kind::Text));
+                                      });
+        assert_eq!(vec![reply(3, Kind::Embed), reply(4, Kind::Text)], left_over);
+
+        assert_eq!(vec![reply(2, Kind::Text), reply(6, Kind::Text)],
+                   replies.take_deleted(fake::CHANNEL_ID, MessageId(1)));
+        assert!(replies.take_deleted(fake::CHANNEL_ID, MessageId(1)).is_empty());
+    }
+
+    #[test]
+    fn forgets_old_commands() {
+        let replies = Replies::new();
+
+        for i in 0..COMMANDS_PER_CHANNEL as u64 + 1 {
+            let message = fake::message(MessageId(i), fake::CHANNEL_ID, &fake::owner(), "!cmd");
+            replies.track(&message, Vec::new(), || {
+                              replies.record(fake::CHANNEL_ID, reply(i + 1000, Kind::Text))
+                          });
+        }
+
+        assert!(replies.take_deleted(fake::CHANNEL_ID, MessageId(0)).is_empty());
+        assert_eq!(vec![reply(1001, Kind::Text)],
+                   replies.take_deleted(fake::CHANNEL_ID, MessageId(1)));
+    }
+}
@@ -0,0 +1,424 @@
+use crashes::Recover;
+use discord::model::ServerId;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{self, Map, Value};
+use std::{
+    collections::hash_map::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const STORAGE_DIRNAME: &str = "storage";
+const VERSION_FILENAME: &str = "version";
+
+// What the admin module kept its settings in before there was storage.
+const LEGACY_MEMORY_FILENAME: &str = "memory.json";
+
+type Migration = fn(&Storage) -> io::Result<()>;
+
+// Migration N brings the storage from version N to version N + 1.
+static MIGRATIONS: [Migration; 1] = [import_legacy_memory];
+
+type Document = Map<String, Value>;
+
+// Module data saved as one JSON document per module per server:
+// <data dir>/storage/<namespace>/<server id>.json
+pub struct Storage {
+    data_dir: PathBuf,
+    // Documents which have been read from disk, by namespace and server.
+    documents: Mutex<HashMap<(String, ServerId), Document>>,
+}
+
+// The part of the storage belonging to a single module.
+pub struct Store<'a> {
+    storage: &'a Storage,
+    namespace: &'a str,
+}
+
+impl Storage {
+    // Opens the storage in the data directory, bringing it up to date if it was made by an older
+    // version of the bot.
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        let storage = Storage { data_dir: data_dir.to_owned(),
+                                documents: Mutex::new(HashMap::new()), };
+
+        fs::create_dir_all(storage.dir())?;
+
+        let version = storage.read_version()?;
+        if version > MIGRATIONS.len() {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("The storage is version {}, but the newest one \
+                                               I know about is {}",
+                                              version,
+                                              MIGRATIONS.len())));
+        }
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            migration(&storage)?;
+            storage.write_version(i + 1)?;
+        }
+
+        Ok(storage)
+    }
+
+    pub fn store<'a>(&'a self, namespace: &'a str) -> Store<'a> {
+        Store { storage: self,
+                namespace, }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.data_dir.join(STORAGE_DIRNAME)
+    }
+
+    fn path(&self, namespace: &str, server: ServerId) -> PathBuf {
+        self.dir().join(namespace).join(format!("{}.json", server.0))
+    }
+
+    fn read_version(&self) -> io::Result<usize> {
+        match fs::read_to_string(self.dir().join(VERSION_FILENAME)) {
+            Ok(text) => text.trim().parse().map_err(|_| {
+                                                 io::Error::new(io::ErrorKind::InvalidData,
+                                                                "Invalid storage version file")
+                                             }),
+            // A fresh storage.
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write_version(&self, version: usize) -> io::Result<()> {
+        write_atomically(&self.dir().join(VERSION_FILENAME),
+                         version.to_string().as_bytes())
+    }
+
+    // Runs f on the document, reading it from disk first if needed.
+    fn with_document<F, T>(&self, namespace: &str, server: ServerId, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Document) -> T
+    {
+        let mut documents = self.documents.lock().recover();
+        let key = (namespace.to_owned(), server);
+
+        if !documents.contains_key(&key) {
+            let document = match File::open(self.path(namespace, server)) {
+                Ok(file) => serde_json::from_reader(file)?,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => Document::new(),
+                Err(err) => return Err(err),
+            };
+
+            documents.insert(key.clone(), document);
+        }
+
+        Ok(f(documents.get_mut(&key).unwrap()))
+    }
+
+    // Changes the document with f and saves it.
+    fn update<F>(&self, namespace: &str, server: ServerId, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Document)
+    {
+        let path = self.path(namespace, server);
+
+        self.with_document(namespace, server, f)?;
+
+        // Keep the lock so that the writes of the same document can't get reordered.
+        let documents = self.documents.lock().recover();
+        let document = &documents[&(namespace.to_owned(), server)];
+
+        if document.is_empty() {
+            match fs::remove_file(&path) {
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                x => x,
+            }
+        } else {
+            fs::create_dir_all(path.parent().unwrap())?;
+            write_atomically(&path, &serde_json::to_vec_pretty(document)?)
+        }
+    }
+
+    fn servers(&self, namespace: &str) -> io::Result<Vec<ServerId>> {
+        let entries = match fs::read_dir(self.dir().join(namespace)) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut servers = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.extension() == Some("json".as_ref()) {
+                if let Some(id) = path.file_stem()
+                                      .and_then(|x| x.to_str())
+                                      .and_then(|x| x.parse().ok())
+                {
+                    servers.push(ServerId(id));
+                }
+            }
+        }
+
+        Ok(servers)
+    }
+}
+
+impl<'a> Store<'a> {
+    // Returns None if there's no such value or it couldn't be read.
+    pub fn get<T: DeserializeOwned>(&self, server: ServerId, key: &str) -> Option<T> {
+        let value = match self.storage
+                              .with_document(self.namespace, server, |x| x.get(key).cloned())
+        {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Could not read {} storage: {}", self.namespace, err);
+                None
+            }
+        };
+
+        value.and_then(|value| match serde_json::from_value(value) {
+                           Ok(x) => Some(x),
+                           Err(err) => {
+                               warn!("Invalid `{}` in {} storage: {}", key, self.namespace, err);
+                               None
+                           }
+                       })
+    }
+
+    pub fn set<T: Serialize>(&self, server: ServerId, key: &str, value: &T) {
+        let value = serde_json::to_value(value).unwrap();
+
+        self.save(server, |document| {
+                      document.insert(key.to_owned(), value);
+                  });
+    }
+
+    // Changes the value with f in one go, so that concurrent updates of the same server don't
+    // overwrite each other. f starts from the default if there's no valid value, and the key is
+    // removed if f leaves it empty. Returns None if the storage couldn't be read or saved.
+    pub fn update<T, F, R>(&self, server: ServerId, key: &str, f: F) -> Option<R>
+        where T: DeserializeOwned + Serialize + Default,
+              F: FnOnce(&mut T) -> R
+    {
+        let namespace = self.namespace;
+        let mut result = None;
+
+        let saved = self.storage.update(namespace, server, |document| {
+            let mut value = match document.get(key).cloned().map(serde_json::from_value) {
+                Some(Ok(value)) => value,
+                Some(Err(err)) => {
+                    warn!("Invalid `{}` in {} storage: {}", key, namespace, err);
+                    T::default()
+                }
+                None => T::default(),
+            };
+
+            result = Some(f(&mut value));
+
+            let value = serde_json::to_value(&value).unwrap();
+            if is_empty(&value) {
+                document.remove(key);
+            } else {
+                document.insert(key.to_owned(), value);
+            }
+        });
+
+        match saved {
+            Ok(()) => result,
+            Err(err) => {
+                error!("Could not save {} storage: {}", namespace, err);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&self, server: ServerId, key: &str) {
+        self.save(server, |document| {
+                      document.remove(key);
+                  });
+    }
+
+    // Removes everything this module has saved for the server.
+    pub fn clear(&self, server: ServerId) {
+        self.save(server, Document::clear);
+    }
+
+    // The servers this module has something saved for.
+    pub fn servers(&self) -> Vec<ServerId> {
+        self.storage.servers(self.namespace).unwrap_or_else(|err| {
+            error!("Could not read {} storage: {}", self.namespace, err);
+            Vec::new()
+        })
+    }
+
+    fn save<F: FnOnce(&mut Document)>(&self, server: ServerId, f: F) {
+        if let Err(err) = self.storage.update(self.namespace, server, f) {
+            error!("Could not save {} storage: {}", self.namespace, err);
+        }
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match *value {
+        Value::Null => true,
+        Value::Array(ref x) => x.is_empty(),
+        Value::Object(ref x) => x.is_empty(),
+        _ => false,
+    }
+}
+
+// Writes into a temporary file first so that a crash can't leave a half-written file behind.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&temp_path, path)
+}
+
+// Version 0 -> 1: move the admin roles and prefixes out of memory.json.
+fn import_legacy_memory(storage: &Storage) -> io::Result<()> {
+    #[derive(Deserialize)]
+    struct Memory {
+        // The map is from ServerId into an array of RoleIds.
+        #[serde(default)]
+        admin_roles: HashMap<String, Vec<u64>>,
+        // The map is from ServerId into the command prefix.
+        #[serde(default)]
+        prefixes: HashMap<String, String>,
+    }
+
+    let memory: Memory = match File::open(storage.data_dir.join(LEGACY_MEMORY_FILENAME)) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    for (server, mut roles) in memory.admin_roles {
+        roles.sort();
+        roles.dedup();
+
+        if let (Ok(server), false) = (server.parse(), roles.is_empty()) {
+            storage.update("admin", ServerId(server), |document| {
+                              document.insert("admin_roles".to_owned(), json!(roles));
+                          })?;
+        }
+    }
+
+    for (server, prefix) in memory.prefixes {
+        if let Ok(server) = server.parse() {
+            storage.update("admin", ServerId(server), |document| {
+                              document.insert("prefix".to_owned(), json!(prefix));
+                          })?;
+        }
+    }
+
+    info!("Imported {} into the storage.", LEGACY_MEMORY_FILENAME);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake::temp_dir;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn set_get_remove() {
+        let dir = temp_dir();
+        let storage = Storage::open(&dir).unwrap();
+        let store = storage.store("test");
+
+        assert_eq!(None, store.get::<String>(ServerId(1), "key"));
+
+        store.set(ServerId(1), "key", &"value");
+        store.set(ServerId(2), "key", &5);
+        assert_eq!(Some("value".to_owned()), store.get(ServerId(1), "key"));
+        assert_eq!(Some(5), store.get(ServerId(2), "key"));
+
+        // Another namespace doesn't see it.
+        assert_eq!(None, storage.store("other").get::<String>(ServerId(1), "key"));
+
+        let mut servers = store.servers();
+        servers.sort();
+        assert_eq!(vec![ServerId(1), ServerId(2)], servers);
+
+        store.remove(ServerId(1), "key");
+        assert_eq!(None, store.get::<String>(ServerId(1), "key"));
+        assert_eq!(vec![ServerId(2)], store.servers());
+    }
+
+    #[test]
+    fn update() {
+        let dir = temp_dir();
+        let storage = Arc::new(Storage::open(&dir).unwrap());
+
+        let threads: Vec<_> = (0..8).map(|_| {
+                                             let storage = storage.clone();
+                                             thread::spawn(move || {
+                                                 let store = storage.store("test");
+                                                 for i in 0..100 {
+                                                     store.update(ServerId(1),
+                                                                  "key",
+                                                                  |x: &mut Vec<u32>| x.push(i));
+                                                 }
+                                             })
+                                         })
+                                    .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let store = storage.store("test");
+        assert_eq!(Some(800), store.get::<Vec<u32>>(ServerId(1), "key").map(|x| x.len()));
+
+        assert_eq!(Some(800),
+                   store.update(ServerId(1), "key", |x: &mut Vec<u32>| x.drain(..).count()));
+        assert_eq!(None, store.get::<Vec<u32>>(ServerId(1), "key"));
+        assert!(!dir.join("storage/test/1.json").exists());
+    }
+
+    #[test]
+    fn persists() {
+        let dir = temp_dir();
+
+        Storage::open(&dir).unwrap()
+                           .store("test")
+                           .set(ServerId(1), "key", &vec![1, 2, 3]);
+
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(Some(vec![1, 2, 3]), storage.store("test").get(ServerId(1), "key"));
+        assert!(!dir.join("storage/test/1.json.tmp").exists());
+    }
+
+    #[test]
+    fn imports_legacy_memory() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(LEGACY_MEMORY_FILENAME),
+                  r#"{"admin_roles":{"10":[3,1,3],"11":[]},"prefixes":{"10":"?"}}"#).unwrap();
+
+        let storage = Storage::open(&dir).unwrap();
+        let store = storage.store("admin");
+        assert_eq!(Some(vec![1u64, 3]), store.get(ServerId(10), "admin_roles"));
+        assert_eq!(Some("?".to_owned()), store.get(ServerId(10), "prefix"));
+        assert_eq!(vec![ServerId(10)], store.servers());
+
+        // Only the first time.
+        store.clear(ServerId(10));
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(None, storage.store("admin").get::<String>(ServerId(10), "prefix"));
+    }
+
+    #[test]
+    fn newer_version() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.join(STORAGE_DIRNAME)).unwrap();
+        fs::write(dir.join(STORAGE_DIRNAME).join(VERSION_FILENAME), "100").unwrap();
+
+        assert!(Storage::open(&dir).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use discord::{builders::EmbedBuilder, model::*, Discord, GetMessages, Result};
+use embed::Embed;
+use std::io::Read;
+
+// The Discord REST operations the bot uses, so that they can be swapped out for testing.
+pub trait Transport: Send + Sync {
+    fn send_message(&self, channel: ChannelId, text: &str) -> Result<Message>;
+
+    fn edit_message(&self, channel: ChannelId, message: MessageId, text: &str) -> Result<Message>;
+
+    fn send_embed(&self, channel: ChannelId, text: &str, embed: &Embed) -> Result<Message>;
+
+    fn edit_embed(&self, channel: ChannelId, message: MessageId, embed: &Embed) -> Result<Message>;
+
+    fn send_file(&self,
+                 channel: ChannelId,
+                 text: &str,
+                 file: &mut Read,
+                 filename: &str)
+                 -> Result<Message>;
+
+    fn get_message(&self, channel: ChannelId, message: MessageId) -> Result<Message>;
+
+    fn get_messages(&self,
+                    channel: ChannelId,
+                    what: GetMessages,
+                    limit: Option<u64>)
+                    -> Result<Vec<Message>>;
+
+    // Bulk deletes, which only works for 2 to 100 messages younger than two weeks.
+    fn delete_messages(&self, channel: ChannelId, messages: &[MessageId]) -> Result<()>;
+
+    fn delete_message(&self, channel: ChannelId, message: MessageId) -> Result<()>;
+
+    fn get_member(&self, server: ServerId, user: UserId) -> Result<Member>;
+
+    fn kick_member(&self, server: ServerId, user: UserId) -> Result<()>;
+
+    // Bans without deleting any of their messages.
+    fn add_ban(&self, server: ServerId, user: UserId) -> Result<()>;
+
+    fn remove_ban(&self, server: ServerId, user: UserId) -> Result<()>;
+
+    // Replaces all of the member's roles.
+    fn edit_member_roles(&self, server: ServerId, user: UserId, roles: &[RoleId]) -> Result<()>;
+
+    fn create_channel(&self, server: ServerId, name: &str, kind: ChannelType) -> Result<Channel>;
+
+    fn create_permission(&self, channel: ChannelId, target: PermissionOverwrite) -> Result<()>;
+
+    fn create_private_channel(&self, recipient: UserId) -> Result<PrivateChannel>;
+
+    fn broadcast_typing(&self, channel: ChannelId) -> Result<()>;
+
+    fn add_reaction(&self, channel: ChannelId, message: MessageId, emoji: ReactionEmoji)
+                    -> Result<()>;
+
+    // Removes our own reaction if the user is None.
+    fn delete_reaction(&self,
+                       channel: ChannelId,
+                       message: MessageId,
+                       user: Option<UserId>,
+                       emoji: ReactionEmoji)
+                       -> Result<()>;
+}
+
+impl Transport for Discord {
+    fn send_message(&self, channel: ChannelId, text: &str) -> Result<Message> {
+        Discord::send_message(self, channel, text, "", false)
+    }
+
+    fn edit_message(&self, channel: ChannelId, message: MessageId, text: &str) -> Result<Message> {
+        Discord::edit_message(self, channel, message, text)
+    }
+
+    fn send_embed(&self, channel: ChannelId, text: &str, embed: &Embed) -> Result<Message> {
+        Discord::send_embed(self, channel, text, |builder| build_embed(embed, builder))
+    }
+
+    fn edit_embed(&self, channel: ChannelId, message: MessageId, embed: &Embed) -> Result<Message> {
+        Discord::edit_embed(self, channel, message, |builder| build_embed(embed, builder))
+    }
+
+    fn send_file(&self,
+                 channel: ChannelId,
+                 text: &str,
+                 file: &mut Read,
+                 filename: &str)
+                 -> Result<Message> {
+        Discord::send_file(self, channel, text, file, filename)
+    }
+
+    fn get_message(&self, channel: ChannelId, message: MessageId) -> Result<Message> {
+        Discord::get_message(self, channel, message)
+    }
+
+    fn get_messages(&self,
+                    channel: ChannelId,
+                    what: GetMessages,
+                    limit: Option<u64>)
+                    -> Result<Vec<Message>> {
+        Discord::get_messages(self, channel, what, limit)
+    }
+
+    fn delete_messages(&self, channel: ChannelId, messages: &[MessageId]) -> Result<()> {
+        Discord::delete_messages(self, channel, messages)
+    }
+
+    fn delete_message(&self, channel: ChannelId, message: MessageId) -> Result<()> {
+        Discord::delete_message(self, channel, message)
+    }
+
+    fn get_member(&self, server: ServerId, user: UserId) -> Result<Member> {
+        Discord::get_member(self, server, user)
+    }
+
+    fn kick_member(&self, server: ServerId, user: UserId) -> Result<()> {
+        Discord::kick_member(self, server, user)
+    }
+
+    fn add_ban(&self, server: ServerId, user: UserId) -> Result<()> {
+        Discord::add_ban(self, server, user, 0)
+    }
+
+    fn remove_ban(&self, server: ServerId, user: UserId) -> Result<()> {
+        Discord::remove_ban(self, server, user)
+    }
+
+    fn edit_member_roles(&self, server: ServerId, user: UserId, roles: &[RoleId]) -> Result<()> {
+        Discord::edit_member(self, server, user, |x| x.roles(roles))
+    }
+
+    fn create_channel(&self, server: ServerId, name: &str, kind: ChannelType) -> Result<Channel> {
+        Discord::create_channel(self, server, name, kind)
+    }
+
+    fn create_permission(&self, channel: ChannelId, target: PermissionOverwrite) -> Result<()> {
+        Discord::create_permission(self, channel, target)
+    }
+
+    fn create_private_channel(&self, recipient: UserId) -> Result<PrivateChannel> {
+        Discord::create_private_channel(self, recipient)
+    }
+
+    fn broadcast_typing(&self, channel: ChannelId) -> Result<()> {
+        Discord::broadcast_typing(self, channel)
+    }
+
+    fn add_reaction(&self, channel: ChannelId, message: MessageId, emoji: ReactionEmoji)
+                    -> Result<()> {
+        Discord::add_reaction(self, channel, message, emoji)
+    }
+
+    fn delete_reaction(&self,
+                       channel: ChannelId,
+                       message: MessageId,
+                       user: Option<UserId>,
+                       emoji: ReactionEmoji)
+                       -> Result<()> {
+        Discord::delete_reaction(self, channel, message, user, emoji)
+    }
+}
+
+fn build_embed(embed: &Embed, mut builder: EmbedBuilder) -> EmbedBuilder {
+    if let Some(ref title) = embed.title {
+        builder = builder.title(title);
+    }
+    if let Some(ref url) = embed.url {
+        builder = builder.url(url);
+    }
+    if let Some(ref description) = embed.description {
+        builder = builder.description(description);
+    }
+    if let Some(color) = embed.color {
+        builder = builder.color(color);
+    }
+    if let Some(ref thumbnail) = embed.thumbnail {
+        builder = builder.thumbnail(thumbnail);
+    }
+    if let Some(ref image) = embed.image {
+        builder = builder.image(image);
+    }
+    if !embed.fields.is_empty() {
+        builder = builder.fields(|mut fields| {
+                                     for field in &embed.fields {
+                                         fields = fields.field(&field.name,
+                                                               &field.value,
+                                                               field.inline);
+                                     }
+                                     fields
+                                 });
+    }
+    if let Some(ref footer) = embed.footer {
+        builder = builder.footer(|x| x.text(footer));
+    }
+
+    builder
+}